@@ -0,0 +1,227 @@
+// Dynamic calls into deployed project canisters.
+//
+// The project's stored Candid interface is parsed at call time, textual
+// arguments are encoded against the method's parameter types, the call is
+// made with raw bytes and the reply is decoded back into Candid text.
+
+use crate::error::ApiError;
+use candid::types::{Function, Type, TypeInner};
+use candid::types::value::{IDLArgs, IDLValue};
+use candid::{Principal, TypeEnv};
+use candid_parser::utils::{instantiate_candid, CandidSource};
+use ic_cdk::call::{Call, CallFailed};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallError {
+    /// The stored interface could not be parsed.
+    InvalidInterface(String),
+    UnknownMethod(String),
+    ArgumentMismatch(String),
+    Rejected { code: u32, message: String },
+    /// The reply did not match the method's declared return types.
+    InvalidReply(String),
+}
+
+impl CallError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CallError::InvalidInterface(_) => "invalid_interface",
+            CallError::UnknownMethod(_) => "unknown_method",
+            CallError::ArgumentMismatch(_) => "argument_mismatch",
+            CallError::Rejected { .. } => "canister_reject",
+            CallError::InvalidReply(_) => "invalid_reply",
+        }
+    }
+}
+
+impl From<CallError> for ApiError {
+    fn from(e: CallError) -> Self {
+        match e {
+            CallError::Rejected { .. } | CallError::InvalidReply(_) => ApiError::External(e.to_string()),
+            CallError::InvalidInterface(_) | CallError::UnknownMethod(_) | CallError::ArgumentMismatch(_) => {
+                ApiError::InvalidInput(e.to_string())
+            }
+        }
+    }
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::InvalidInterface(e) => write!(f, "Invalid Candid interface: {}", e),
+            CallError::UnknownMethod(m) => write!(f, "Method '{}' not found in the canister interface", m),
+            CallError::ArgumentMismatch(e) => write!(f, "Argument type mismatch: {}", e),
+            CallError::Rejected { code, message } => write!(f, "Canister rejected the call (code {}): {}", code, message),
+            CallError::InvalidReply(e) => write!(f, "Could not decode reply: {}", e),
+        }
+    }
+}
+
+/// A method resolved against a parsed service definition.
+pub struct Method {
+    pub name: String,
+    pub env: TypeEnv,
+    pub func: Function,
+}
+
+fn load_service(did: &str) -> Result<(TypeEnv, Type), CallError> {
+    let (_, (env, service)) = instantiate_candid(CandidSource::Text(did))
+        .map_err(|e| CallError::InvalidInterface(e.to_string()))?;
+    Ok((env, service))
+}
+
+/// Every method of the service with its signature, e.g. `("greet", "(text) -> (text) query")`.
+pub fn methods(did: &str) -> Result<Vec<(String, String)>, CallError> {
+    let (env, service) = load_service(did)?;
+    let methods = env.as_service(&service).map_err(|e| CallError::InvalidInterface(e.to_string()))?;
+    Ok(methods
+        .iter()
+        .map(|(name, ty)| {
+            let signature = match env.as_func(ty) {
+                Ok(func) => func.to_string(),
+                Err(_) => ty.to_string(),
+            };
+            (name.clone(), signature)
+        })
+        .collect())
+}
+
+pub fn resolve_method(did: &str, name: &str) -> Result<Method, CallError> {
+    let (env, service) = load_service(did)?;
+    let func = env
+        .get_method(&service, name)
+        .map_err(|_| CallError::UnknownMethod(name.to_string()))?
+        .clone();
+    Ok(Method { name: name.to_string(), env, func })
+}
+
+/// Parse user supplied arguments. A single argument wrapped in parentheses is
+/// taken as a full Candid argument list; otherwise every entry is one value,
+/// and for `text` parameters anything but a quoted string is the text itself,
+/// so `42` or `true` stay words.
+pub fn parse_args(method: &Method, args: &[String]) -> Result<IDLArgs, CallError> {
+    if let [single] = args {
+        if single.trim_start().starts_with('(') {
+            return candid_parser::parse_idl_args(single)
+                .map_err(|e| CallError::ArgumentMismatch(e.to_string()));
+        }
+    }
+
+    let params = &method.func.args;
+    if args.len() > params.len() {
+        return Err(CallError::ArgumentMismatch(format!(
+            "{} expects {} argument(s) but {} were given",
+            method.name,
+            params.len(),
+            args.len()
+        )));
+    }
+
+    let mut values = Vec::with_capacity(params.len());
+    for (i, ty) in params.iter().enumerate() {
+        let ty = method.env.trace_type(ty).map_err(|e| CallError::InvalidInterface(e.to_string()))?;
+        let value = match args.get(i) {
+            Some(arg) => match (ty.as_ref(), candid_parser::parse_idl_value(arg)) {
+                (TypeInner::Text, Ok(IDLValue::Text(text))) => IDLValue::Text(text),
+                (TypeInner::Text, _) => IDLValue::Text(arg.clone()),
+                (_, Ok(value)) => value,
+                (_, Err(e)) => {
+                    return Err(CallError::ArgumentMismatch(format!("argument {}: {}", i + 1, e)));
+                }
+            },
+            // Trailing optional parameters may be left out.
+            None if matches!(ty.as_ref(), TypeInner::Opt(_) | TypeInner::Null | TypeInner::Reserved) => IDLValue::None,
+            None => {
+                return Err(CallError::ArgumentMismatch(format!(
+                    "{} expects {} argument(s) but {} were given",
+                    method.name,
+                    params.len(),
+                    args.len()
+                )));
+            }
+        };
+        values.push(value);
+    }
+    Ok(IDLArgs::new(&values))
+}
+
+pub fn encode_args(method: &Method, args: &IDLArgs) -> Result<Vec<u8>, CallError> {
+    args.to_bytes_with_types(&method.env, &method.func.args)
+        .map_err(|e| CallError::ArgumentMismatch(e.to_string()))
+}
+
+pub fn decode_reply(method: &Method, bytes: &[u8]) -> Result<IDLArgs, CallError> {
+    IDLArgs::from_bytes_with_types(bytes, &method.env, &method.func.rets).map_err(|e| CallError::InvalidReply(e.to_string()))
+}
+
+/// Call `method` on `canister_id` with textual arguments and return the reply as Candid text.
+pub async fn call(canister_id: Principal, did: &str, method: &str, args: &[String]) -> Result<String, CallError> {
+    let method = resolve_method(did, method)?;
+    call_method(canister_id, &method, args).await.map(|reply| reply.to_string())
+}
+
+/// Call a resolved method and decode its reply.
+pub async fn call_method(canister_id: Principal, method: &Method, args: &[String]) -> Result<IDLArgs, CallError> {
+    let args = parse_args(method, args)?;
+    let bytes = encode_args(method, &args)?;
+
+    let reply = Call::unbounded_wait(canister_id, &method.name)
+        .take_raw_args(bytes)
+        .await
+        .map_err(|e| match e {
+            CallFailed::CallRejected(rejected) => CallError::Rejected {
+                code: rejected.raw_reject_code(),
+                message: rejected.reject_message().to_string(),
+            },
+            other => CallError::Rejected { code: 0, message: other.to_string() },
+        })?;
+    decode_reply(method, &reply.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DID: &str = r#"
+        type Profile = record { name : text; age : nat8 };
+        service : {
+            greet : (text) -> (text) query;
+            set_profile : (Profile, opt text) -> (bool);
+        }
+    "#;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn encodes_and_decodes_round_trip() {
+        let method = resolve_method(DID, "greet").unwrap();
+        let args = parse_args(&method, &strings(&["World"])).unwrap();
+        let bytes = encode_args(&method, &args).unwrap();
+        assert_eq!(candid::decode_one::<String>(&bytes).unwrap(), "World");
+
+        // Words that look like other Candid values are still text for a `text` parameter.
+        for (arg, text) in [("42", "42"), ("true", "true"), ("\"a b\"", "a b")] {
+            let bytes = encode_args(&method, &parse_args(&method, &strings(&[arg])).unwrap()).unwrap();
+            assert_eq!(candid::decode_one::<String>(&bytes).unwrap(), text);
+        }
+
+        let reply = candid::encode_one("Hello, World!").unwrap();
+        assert_eq!(decode_reply(&method, &reply).unwrap().to_string(), "(\"Hello, World!\")");
+    }
+
+    #[test]
+    fn distinguishes_errors() {
+        assert_eq!(resolve_method(DID, "missing").err().unwrap().kind(), "unknown_method");
+
+        let method = resolve_method(DID, "set_profile").unwrap();
+        let args = parse_args(&method, &strings(&["record { name = \"a\"; age = 300 }"])).unwrap();
+        assert_eq!(encode_args(&method, &args).err().unwrap().kind(), "argument_mismatch");
+        assert!(parse_args(&method, &strings(&["1", "2", "3"])).is_err());
+
+        let ok = parse_args(&method, &strings(&["record { name = \"a\"; age = 30 }"])).unwrap();
+        assert!(encode_args(&method, &ok).is_ok());
+    }
+}
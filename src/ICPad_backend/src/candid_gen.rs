@@ -0,0 +1,464 @@
+// Candid interface generation for Rust projects.
+//
+// A real build would get the interface from `export_candid!()`, but the
+// canister cannot compile Rust. Instead the sources are walked with syn: every
+// `#[query]`, `#[update]` and `#[init]` function becomes a method of the
+// service, and the structs, enums and aliases their signatures mention are
+// translated the way `#[derive(CandidType)]` would translate them. Types that
+// cannot be translated are reported and emitted as `reserved`.
+
+use crate::diagnostics::{Diagnostic, Position, Range, Severity};
+use crate::vfs::ProjectFile;
+use candid_parser::utils::CandidSource;
+use std::collections::{BTreeMap, BTreeSet};
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+
+const SOURCE: &str = "rust";
+
+const KEYWORDS: &[&str] = &[
+    "blob", "bool", "composite_query", "empty", "float32", "float64", "func", "import", "int", "int8", "int16",
+    "int32", "int64", "nat", "nat8", "nat16", "nat32", "nat64", "null", "oneway", "opt", "principal", "query",
+    "record", "reserved", "service", "text", "type", "variant", "vec",
+];
+
+enum Definition {
+    Struct(syn::ItemStruct),
+    Enum(syn::ItemEnum),
+    Alias(syn::ItemType),
+}
+
+enum Kind {
+    Init,
+    Update,
+    Query,
+    CompositeQuery,
+}
+
+struct Method {
+    name: String,
+    kind: Kind,
+    file: String,
+    sig: syn::Signature,
+}
+
+/// Type definitions and exported functions of all files, in source order.
+#[derive(Default)]
+struct Collector {
+    file: String,
+    definitions: BTreeMap<String, (String, Definition)>,
+    methods: Vec<Method>,
+}
+
+/// `name = "..."` and `composite = true` from an export attribute.
+fn export_options(attr: &syn::Attribute) -> (Option<String>, bool) {
+    let (mut name, mut composite) = (None, false);
+    if matches!(attr.meta, syn::Meta::List(_)) {
+        let _ = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<syn::LitStr>()?.value());
+            } else if meta.path.is_ident("composite") {
+                composite = meta.value()?.parse::<syn::LitBool>()?.value;
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<syn::Expr>()?;
+            }
+            Ok(())
+        });
+    }
+    (name, composite)
+}
+
+/// The name serde (and so candid) uses for a field or variant.
+fn serde_name(attrs: &[syn::Attribute], ident: &syn::Ident) -> String {
+    let mut name = ident.to_string();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        let _ = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") && meta.input.peek(syn::Token![=]) {
+                name = meta.value()?.parse::<syn::LitStr>()?.value();
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<syn::Expr>()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                meta.parse_nested_meta(|nested| {
+                    nested.value()?.parse::<syn::Expr>()?;
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        });
+    }
+    name
+}
+
+impl<'ast> Visit<'ast> for Collector {
+    fn visit_item_fn(&mut self, item: &'ast syn::ItemFn) {
+        for attr in &item.attrs {
+            let Some(last) = attr.path().segments.last() else { continue };
+            let (name, composite) = export_options(attr);
+            let kind = match last.ident.to_string().as_str() {
+                "init" => Kind::Init,
+                "update" => Kind::Update,
+                "query" if composite => Kind::CompositeQuery,
+                "query" => Kind::Query,
+                _ => continue,
+            };
+            self.methods.push(Method {
+                name: name.unwrap_or_else(|| item.sig.ident.to_string()),
+                kind,
+                file: self.file.clone(),
+                sig: item.sig.clone(),
+            });
+        }
+        visit::visit_item_fn(self, item);
+    }
+
+    fn visit_item_struct(&mut self, item: &'ast syn::ItemStruct) {
+        self.definitions.insert(item.ident.to_string(), (self.file.clone(), Definition::Struct(item.clone())));
+    }
+
+    fn visit_item_enum(&mut self, item: &'ast syn::ItemEnum) {
+        self.definitions.insert(item.ident.to_string(), (self.file.clone(), Definition::Enum(item.clone())));
+    }
+
+    fn visit_item_type(&mut self, item: &'ast syn::ItemType) {
+        self.definitions.insert(item.ident.to_string(), (self.file.clone(), Definition::Alias(item.clone())));
+    }
+}
+
+pub fn candid_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid && !KEYWORDS.contains(&name) {
+        name.to_string()
+    } else {
+        format!("{:?}", name)
+    }
+}
+
+fn range_of(node: &impl Spanned) -> Range {
+    let span = node.span();
+    let at = |lc: proc_macro2::LineColumn| Position::new(lc.line.saturating_sub(1) as u32, lc.column as u32);
+    Range::new(at(span.start()), at(span.end()))
+}
+
+fn type_args(segment: &syn::PathSegment) -> Vec<&syn::Type> {
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                syn::GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn is_u8(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(p) if p.path.is_ident("u8"))
+}
+
+fn is_unit(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Tuple(t) if t.elems.is_empty())
+}
+
+struct Generator<'a> {
+    definitions: &'a BTreeMap<String, (String, Definition)>,
+    /// User types referenced so far, in the order they were first seen.
+    used: Vec<String>,
+    seen: BTreeSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Generator<'_> {
+    fn unsupported(&mut self, file: &str, ty: &syn::Type, why: &str) -> String {
+        self.diagnostics.push(
+            Diagnostic::new(SOURCE, file, range_of(ty), Severity::Warning, format!("{}, emitted as `reserved` in the Candid interface", why))
+                .with_code("unsupported-candid-type"),
+        );
+        "reserved".to_string()
+    }
+
+    fn ty(&mut self, file: &str, ty: &syn::Type) -> String {
+        match ty {
+            syn::Type::Reference(r) => self.ty(file, &r.elem),
+            syn::Type::Paren(p) => self.ty(file, &p.elem),
+            syn::Type::Group(g) => self.ty(file, &g.elem),
+            syn::Type::Tuple(t) if t.elems.is_empty() => "null".to_string(),
+            syn::Type::Tuple(t) => {
+                let fields: Vec<_> = t.elems.iter().map(|e| self.ty(file, e)).collect();
+                format!("record {{ {} }}", fields.join("; "))
+            }
+            syn::Type::Array(a) if is_u8(&a.elem) => "blob".to_string(),
+            syn::Type::Slice(s) if is_u8(&s.elem) => "blob".to_string(),
+            syn::Type::Array(a) => format!("vec {}", self.ty(file, &a.elem)),
+            syn::Type::Slice(s) => format!("vec {}", self.ty(file, &s.elem)),
+            syn::Type::Path(p) if p.qself.is_none() => {
+                let Some(segment) = p.path.segments.last() else {
+                    return self.unsupported(file, ty, "empty type path");
+                };
+                let args = type_args(segment);
+                let name = segment.ident.to_string();
+                let primitive = match name.as_str() {
+                    "bool" => "bool",
+                    "String" | "str" => "text",
+                    "u8" => "nat8",
+                    "u16" => "nat16",
+                    "u32" => "nat32",
+                    "u64" | "usize" => "nat64",
+                    "i8" => "int8",
+                    "i16" => "int16",
+                    "i32" => "int32",
+                    "i64" | "isize" => "int64",
+                    "u128" | "Nat" => "nat",
+                    "i128" | "Int" => "int",
+                    "f32" => "float32",
+                    "f64" => "float64",
+                    "Principal" => "principal",
+                    "Reserved" => "reserved",
+                    "Empty" => "empty",
+                    "ByteBuf" => "blob",
+                    _ => "",
+                };
+                if !primitive.is_empty() {
+                    return primitive.to_string();
+                }
+                match (name.as_str(), args.as_slice()) {
+                    ("Vec", [inner]) if is_u8(inner) => "blob".to_string(),
+                    ("Vec" | "VecDeque" | "HashSet" | "BTreeSet", [inner]) => format!("vec {}", self.ty(file, inner)),
+                    ("Option", [inner]) => format!("opt {}", self.ty(file, inner)),
+                    ("Box" | "Rc" | "Arc" | "Cow", [inner]) => self.ty(file, inner),
+                    ("HashMap" | "BTreeMap", [key, value]) => {
+                        format!("vec record {{ {}; {} }}", self.ty(file, key), self.ty(file, value))
+                    }
+                    ("Result", [ok, err]) => {
+                        let ok = if is_unit(ok) { "Ok".to_string() } else { format!("Ok : {}", self.ty(file, ok)) };
+                        format!("variant {{ {}; Err : {} }}", ok, self.ty(file, err))
+                    }
+                    (_, []) if self.definitions.contains_key(&name) => {
+                        if self.seen.insert(name.clone()) {
+                            self.used.push(name.clone());
+                        }
+                        candid_name(&name)
+                    }
+                    (_, [_, ..]) if self.definitions.contains_key(&name) => {
+                        self.unsupported(file, ty, &format!("generic type `{}` is not supported", name))
+                    }
+                    _ => self.unsupported(file, ty, &format!("unknown type `{}`", name)),
+                }
+            }
+            _ => self.unsupported(file, ty, "unsupported type"),
+        }
+    }
+
+    fn fields(&mut self, file: &str, fields: &syn::Fields) -> String {
+        match fields {
+            syn::Fields::Named(named) => {
+                let fields: Vec<_> = named
+                    .named
+                    .iter()
+                    .filter_map(|f| f.ident.as_ref().map(|ident| (f, ident)))
+                    .map(|(f, ident)| format!("{} : {}", candid_name(&serde_name(&f.attrs, ident)), self.ty(file, &f.ty)))
+                    .collect();
+                format!("record {{ {} }}", fields.join("; "))
+            }
+            syn::Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => self.ty(file, &unnamed.unnamed[0].ty),
+            syn::Fields::Unnamed(unnamed) => {
+                let fields: Vec<_> = unnamed.unnamed.iter().map(|f| self.ty(file, &f.ty)).collect();
+                format!("record {{ {} }}", fields.join("; "))
+            }
+            syn::Fields::Unit => "null".to_string(),
+        }
+    }
+
+    fn definition(&mut self, name: &str) -> String {
+        let (file, definition) = &self.definitions[name];
+        let body = match definition {
+            Definition::Struct(item) => self.fields(file, &item.fields),
+            Definition::Alias(item) => self.ty(file, &item.ty),
+            Definition::Enum(item) => {
+                let variants: Vec<_> = item
+                    .variants
+                    .iter()
+                    .map(|v| {
+                        let name = candid_name(&serde_name(&v.attrs, &v.ident));
+                        match &v.fields {
+                            syn::Fields::Unit => name,
+                            fields => format!("{} : {}", name, self.fields(file, fields)),
+                        }
+                    })
+                    .collect();
+                format!("variant {{ {} }}", variants.join("; "))
+            }
+        };
+        format!("type {} = {};", candid_name(name), body)
+    }
+
+    fn arguments(&mut self, method: &Method) -> String {
+        let args: Vec<_> = method
+            .sig
+            .inputs
+            .iter()
+            .filter_map(|arg| match arg {
+                syn::FnArg::Typed(arg) => Some(self.ty(&method.file, &arg.ty)),
+                syn::FnArg::Receiver(_) => None,
+            })
+            .collect();
+        format!("({})", args.join(", "))
+    }
+}
+
+/// Generate the service definition for the project's Rust sources. Returns
+/// `None` when the sources do not parse or export no methods.
+pub fn generate(sources: &[&ProjectFile]) -> (Option<String>, Vec<Diagnostic>) {
+    let mut collector = Collector::default();
+    for file in sources {
+        let Ok(ast) = syn::parse_file(&file.content) else {
+            return (None, vec![]);
+        };
+        collector.file = file.path.clone();
+        collector.visit_file(&ast);
+    }
+
+    let mut generator = Generator {
+        definitions: &collector.definitions,
+        used: vec![],
+        seen: BTreeSet::new(),
+        diagnostics: vec![],
+    };
+    let mut init = None;
+    let mut methods = Vec::new();
+    for method in &collector.methods {
+        let args = generator.arguments(method);
+        let mode = match method.kind {
+            Kind::Init => {
+                if init.is_some() {
+                    generator.diagnostics.push(
+                        Diagnostic::new(SOURCE, &method.file, range_of(&method.sig.ident), Severity::Error, "more than one #[init] function")
+                            .with_code("duplicate-init"),
+                    );
+                }
+                init = Some(args);
+                continue;
+            }
+            Kind::Update => "",
+            Kind::Query => " query",
+            Kind::CompositeQuery => " composite_query",
+        };
+        let result = match &method.sig.output {
+            syn::ReturnType::Type(_, ty) if !is_unit(ty) => format!("({})", generator.ty(&method.file, ty)),
+            _ => "()".to_string(),
+        };
+        methods.push(format!("  {} : {} -> {}{};", candid_name(&method.name), args, result, mode));
+    }
+    if methods.is_empty() {
+        return (None, generator.diagnostics);
+    }
+
+    // Definitions can mention further types, which are appended to `used`.
+    let mut types = Vec::new();
+    let mut next = 0;
+    while next < generator.used.len() {
+        let name = generator.used[next].clone();
+        types.push(generator.definition(&name));
+        next += 1;
+    }
+
+    let mut did = String::new();
+    for ty in &types {
+        did.push_str(ty);
+        did.push('\n');
+    }
+    match &init {
+        Some(args) => did.push_str(&format!("service : {} -> {{\n", args)),
+        None => did.push_str("service : {\n"),
+    }
+    for method in &methods {
+        did.push_str(method);
+        did.push('\n');
+    }
+    did.push_str("}\n");
+
+    let mut diagnostics = generator.diagnostics;
+    if let Err(e) = CandidSource::Text(&did).load() {
+        let file = collector.methods.first().map_or("src/lib.rs", |m| m.file.as_str());
+        diagnostics.push(
+            Diagnostic::new(SOURCE, file, Range::default(), Severity::Error, format!("generated Candid interface is invalid: {}", e))
+                .with_code("invalid-candid"),
+        );
+        return (None, diagnostics);
+    }
+    (Some(did), diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs;
+
+    fn generate_for(source: &str) -> (Option<String>, Vec<Diagnostic>) {
+        let tree = vfs::single_file_tree("rust", source.to_string(), 0);
+        let sources: Vec<_> = vfs::files_with_language(&tree, "rust").collect();
+        generate(&sources)
+    }
+
+    #[test]
+    fn generates_service_from_exports() {
+        let source = r#"
+use candid::{CandidType, Principal};
+use ic_cdk::{init, query, update};
+
+#[derive(CandidType, Deserialize)]
+struct Config { admin: Principal, #[serde(rename = "type")] kind: Kind }
+
+#[derive(CandidType, Deserialize)]
+enum Kind { Open, Limited(u32), Custom { users: Vec<Principal> } }
+
+#[derive(CandidType)]
+struct Note(String);
+
+type Notes = Vec<Note>;
+
+#[init]
+fn init(config: Option<Config>) {}
+
+#[query]
+fn greet(name: String) -> String { format!("Hello, {}!", name) }
+
+#[update(name = "addNote")]
+async fn add_note(note: &str, tags: Vec<u8>) -> Result<u64, String> { Ok(0) }
+
+#[query(composite = true)]
+fn notes() -> Notes { vec![] }
+
+#[update]
+fn reset() {}
+
+ic_cdk::export_candid!();
+"#;
+        let (did, diagnostics) = generate_for(source);
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(
+            did.unwrap(),
+            "type Config = record { admin : principal; \"type\" : Kind };\n\
+             type Notes = vec Note;\n\
+             type Kind = variant { Open; Limited : nat32; Custom : record { users : vec principal } };\n\
+             type Note = text;\n\
+             service : (opt Config) -> {\n\
+             \x20 greet : (text) -> (text) query;\n\
+             \x20 addNote : (text, blob) -> (variant { Ok : nat64; Err : text });\n\
+             \x20 notes : () -> (Notes) composite_query;\n\
+             \x20 reset : () -> ();\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn reports_unknown_types() {
+        let (did, diagnostics) = generate_for("#[ic_cdk::query]\nfn now() -> std::time::Instant { todo!() }\n");
+        assert_eq!(did.unwrap(), "service : {\n  now : () -> (reserved) query;\n}\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, Position::new(1, 12));
+    }
+}
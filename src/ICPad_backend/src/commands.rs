@@ -0,0 +1,522 @@
+// Terminal command registry.
+//
+// One table describes every terminal command: what it does, its usage, its
+// flags, its subcommands and what its operands are. `help`, `COMMAND --help`
+// and tab completion are all generated from it, and the shell only accepts
+// the flags listed here.
+
+use crate::candid_call;
+use crate::shell;
+use crate::vfs::{self, FileKind, FileTree};
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// Implemented by the shell over the project's files.
+    Shell,
+    /// Acts on the project itself: compiling, deploying, calling it.
+    Project,
+}
+
+/// What a command's operands complete to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    None,
+    Text,
+    Path,
+    Directory,
+    Command,
+    Variable,
+    /// A method of the deployed canister, then its arguments.
+    Method,
+    /// The project's canister.
+    Canister,
+    /// The project's canister, then one of its methods and its arguments.
+    CanisterMethod,
+}
+
+pub struct Flag {
+    pub name: &'static str,
+    pub help: &'static str,
+}
+
+const fn flag(name: &'static str, help: &'static str) -> Flag {
+    Flag { name, help }
+}
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub summary: &'static str,
+    /// Arguments after the name, e.g. `[-al] [PATH]...`.
+    pub usage: &'static str,
+    pub operands: Operand,
+    pub flags: &'static [Flag],
+    pub subcommands: &'static [CommandSpec],
+}
+
+const fn command(kind: Kind, name: &'static str, summary: &'static str, usage: &'static str, operands: Operand) -> CommandSpec {
+    CommandSpec { name, kind, summary, usage, operands, flags: &[], subcommands: &[] }
+}
+
+const fn subcommand(name: &'static str, summary: &'static str) -> CommandSpec {
+    command(Kind::Project, name, summary, "", Operand::None)
+}
+
+impl CommandSpec {
+    const fn with_flags(mut self, flags: &'static [Flag]) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    const fn with_subcommands(mut self, subcommands: &'static [CommandSpec]) -> Self {
+        self.subcommands = subcommands;
+        self
+    }
+
+    pub fn subcommand(&self, name: &str) -> Option<&'static CommandSpec> {
+        self.subcommands.iter().find(|s| s.name == name)
+    }
+
+    /// Whether `-c` is one of the command's single letter flags.
+    pub fn accepts_flag(&self, c: char) -> bool {
+        self.flags.iter().any(|f| f.name == format!("-{}", c))
+    }
+}
+
+use Kind::{Project, Shell};
+
+pub static COMMANDS: &[CommandSpec] = &[
+    command(Shell, "help", "Show the commands, or help for one command", "[COMMAND]", Operand::Command),
+    command(Shell, "ls", "List directory contents", "[-al1] [PATH]...", Operand::Path).with_flags(&[
+        flag("-a", "Include entries starting with `.`"),
+        flag("-l", "Show the type and size of each entry"),
+        flag("-1", "One entry per line (the default)"),
+    ]),
+    command(Shell, "cd", "Change the working directory", "[DIR]", Operand::Directory),
+    command(Shell, "pwd", "Print the working directory", "", Operand::None),
+    command(Shell, "cat", "Print files, or standard input", "[FILE]...", Operand::Path),
+    command(Shell, "echo", "Print the arguments", "[-n] [TEXT]...", Operand::Text)
+        .with_flags(&[flag("-n", "Do not print the trailing newline")]),
+    command(Shell, "mkdir", "Create directories", "[-p] DIR...", Operand::Directory)
+        .with_flags(&[flag("-p", "Create missing parents, no error if the directory exists")]),
+    command(Shell, "rm", "Remove files and directories", "[-rf] PATH...", Operand::Path).with_flags(&[
+        flag("-r", "Remove directories and their contents"),
+        flag("-R", "Same as -r"),
+        flag("-f", "Ignore missing files"),
+    ]),
+    command(Shell, "mv", "Move or rename files and directories", "SOURCE... DEST", Operand::Path)
+        .with_flags(&[flag("-f", "Overwrite without asking (the default)")]),
+    command(Shell, "cp", "Copy files and directories", "[-r] SOURCE... DEST", Operand::Path).with_flags(&[
+        flag("-r", "Copy directories and their contents"),
+        flag("-R", "Same as -r"),
+        flag("-f", "Overwrite without asking (the default)"),
+    ]),
+    command(Shell, "grep", "Print lines containing a fixed string", "[-cilnrv] PATTERN [FILE]...", Operand::Path).with_flags(&[
+        flag("-c", "Print the number of matching lines per file"),
+        flag("-i", "Ignore case"),
+        flag("-l", "Print only the names of matching files"),
+        flag("-n", "Prefix lines with their line number"),
+        flag("-r", "Search directories, the working directory by default"),
+        flag("-R", "Same as -r"),
+        flag("-v", "Print the lines that do not match"),
+    ]),
+    command(Shell, "head", "Print the first lines of files", "[-n COUNT] [FILE]...", Operand::Path)
+        .with_flags(&[flag("-n", "Number of lines, 10 by default")]),
+    command(Shell, "tail", "Print the last lines of files", "[-n COUNT] [FILE]...", Operand::Path)
+        .with_flags(&[flag("-n", "Number of lines, 10 by default")]),
+    command(Shell, "wc", "Count lines, words and bytes", "[-clw] [FILE]...", Operand::Path).with_flags(&[
+        flag("-c", "Count bytes"),
+        flag("-l", "Count lines"),
+        flag("-w", "Count words"),
+    ]),
+    command(Shell, "env", "List the environment variables", "", Operand::None),
+    command(Shell, "export", "Set environment variables", "NAME=VALUE...", Operand::Variable),
+    command(Shell, "unset", "Remove environment variables", "NAME...", Operand::Variable),
+    command(Project, "compile", "Check the project and generate its Candid interface", "", Operand::None),
+    command(Project, "deploy", "Deploy the project to its canister", "", Operand::None),
+    command(Project, "test", "Run the project's tests against its canister", "[NAME]...", Operand::Text),
+    command(Project, "call", "Call a method of the deployed canister", "METHOD [ARG]...", Operand::Method),
+    command(Project, "rust", "Rust project commands", "SUBCOMMAND", Operand::None).with_subcommands(&[
+        subcommand("build", "Check the project and generate its Candid interface"),
+        subcommand("check", "Same as build"),
+        subcommand("test", "Run the project's tests"),
+    ]),
+    command(Project, "motoko", "Motoko project commands", "SUBCOMMAND", Operand::None).with_subcommands(&[
+        subcommand("check", "Check the project's sources"),
+        subcommand("compile", "Same as check; the IDE builds the WASM"),
+    ]),
+    command(Project, "dfx", "dfx style project commands", "SUBCOMMAND", Operand::None).with_subcommands(&[
+        subcommand("start", "No-op, the replica is always running"),
+        subcommand("deploy", "Deploy the project to its canister"),
+        subcommand("generate", "Generate JavaScript and TypeScript bindings from the Candid interface"),
+        subcommand("canister", "Manage the project's canister").with_subcommands(&[
+            command(Project, "call", "Call a method of the canister", "CANISTER METHOD [ARG]...", Operand::CanisterMethod),
+            command(Project, "delete", "Stop the canister and remove its code", "[-y] CANISTER", Operand::Canister)
+                .with_flags(&[flag("-y", "Confirm the deletion"), flag("--yes", "Same as -y")]),
+            command(Project, "id", "Print the canister id", "CANISTER", Operand::Canister),
+            command(Project, "info", "Print the controllers and module hash", "CANISTER", Operand::Canister),
+            command(Project, "start", "Start the canister", "CANISTER", Operand::Canister),
+            command(Project, "status", "Print the canister's status and settings", "CANISTER", Operand::Canister),
+            command(Project, "stop", "Stop the canister", "CANISTER", Operand::Canister),
+            command(Project, "update-settings", "Change the canister's settings", "CANISTER OPTION...", Operand::Canister)
+                .with_flags(&[
+                    flag("--add-controller", "Add a controller"),
+                    flag("--remove-controller", "Remove a controller"),
+                    flag("--set-controller", "Replace the controllers, repeat for several"),
+                    flag("--freezing-threshold", "Seconds of idle cycles to keep in reserve"),
+                    flag("--wasm-memory-limit", "Upper limit of Wasm heap memory, in bytes"),
+                    flag("--wasm-memory-threshold", "Low memory threshold, in bytes"),
+                    flag("--log-visibility", "Who can read the logs: controllers or public"),
+                ]),
+        ]),
+    ]),
+    command(Project, "exec", "Run a command in a Docker session", "SESSION COMMAND...", Operand::Text),
+];
+
+pub fn find(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+pub fn is_shell_command(name: &str) -> bool {
+    find(name).is_some_and(|c| c.kind == Kind::Shell)
+}
+
+pub fn is_project_command(name: &str) -> bool {
+    find(name).is_some_and(|c| c.kind == Kind::Project)
+}
+
+fn table<'a>(rows: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    let rows: Vec<_> = rows.into_iter().collect();
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    rows.iter().map(|(name, text)| format!("  {:width$}  {}\n", name, text, width = width)).collect()
+}
+
+/// `COMMAND --help`: `path` is the command and any subcommands, e.g. `["rust", "build"]`.
+pub fn help(path: &[&str]) -> Option<String> {
+    let (first, rest) = path.split_first()?;
+    let mut spec = find(first)?;
+    for name in rest {
+        spec = spec.subcommand(name)?;
+    }
+    let name = path.join(" ");
+    let mut text = format!("{} - {}\n\nUsage: {}", name, spec.summary, name);
+    if !spec.usage.is_empty() {
+        text.push(' ');
+        text.push_str(spec.usage);
+    }
+    text.push('\n');
+    if !spec.flags.is_empty() {
+        text.push_str("\nOptions:\n");
+        text.push_str(&table(spec.flags.iter().map(|f| (f.name, f.help))));
+    }
+    if !spec.subcommands.is_empty() {
+        text.push_str("\nSubcommands:\n");
+        text.push_str(&table(spec.subcommands.iter().map(|s| (s.name, s.summary))));
+    }
+    Some(text)
+}
+
+/// `help` without arguments.
+pub fn overview() -> String {
+    let group = |kind| table(COMMANDS.iter().filter(|c| c.kind == kind).map(|c| (c.name, c.summary)));
+    format!(
+        "Shell commands:\n{}\nProject commands:\n{}\nRun `help COMMAND` or `COMMAND --help` for details.\n",
+        group(Kind::Shell),
+        group(Kind::Project)
+    )
+}
+
+// ---------------------------------------------------------------------------
+// Completion
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum CandidateKind {
+    Command,
+    Subcommand,
+    Flag,
+    File,
+    Directory,
+    Variable,
+    Method,
+    Canister,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Candidate {
+    /// Replacement text, escaped for the shell.
+    pub value: String,
+    pub kind: CandidateKind,
+    /// Summary of a command or flag, or a method's signature.
+    pub detail: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Completion {
+    /// Characters `start..end` of the line are replaced by a candidate.
+    pub start: u32,
+    pub end: u32,
+    pub candidates: Vec<Candidate>,
+}
+
+/// What completion can look at besides the line.
+pub struct Context<'a> {
+    pub files: Option<&'a FileTree>,
+    pub cwd: &'a str,
+    pub env: &'a BTreeMap<String, String>,
+    /// The project's service definition.
+    pub candid: Option<&'a str>,
+    /// The name of the project's canister.
+    pub canister: Option<&'a str>,
+}
+
+/// The command being typed at the cursor: its finished words and the
+/// partial word under the cursor.
+#[derive(Debug, Default, PartialEq)]
+struct Scan {
+    words: Vec<String>,
+    current: String,
+    /// Character offset where `current` starts.
+    start: usize,
+    /// The cursor is on the target of `>`.
+    redirect: bool,
+}
+
+fn scan(prefix: &str) -> Scan {
+    let mut scan = Scan::default();
+    let mut word: Option<(usize, String)> = None;
+    let mut quote: Option<char> = None;
+    let mut redirect = false;
+    let mut chars = prefix.chars().enumerate();
+
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                let escaped = chars.next().map(|(_, c)| c);
+                word.get_or_insert((i, String::new())).1.extend(escaped);
+            }
+            (Some(_), c) => word.get_or_insert((i, String::new())).1.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert((i, String::new()));
+            }
+            (None, c) if c.is_whitespace() || matches!(c, ';' | '|' | '&' | '>') => {
+                if let Some((_, text)) = word.take() {
+                    if redirect {
+                        redirect = false;
+                    } else {
+                        scan.words.push(text);
+                    }
+                }
+                match c {
+                    ';' | '|' | '&' => {
+                        scan.words.clear();
+                        redirect = false;
+                    }
+                    '>' => redirect = true,
+                    _ => {}
+                }
+            }
+            (None, c) => word.get_or_insert((i, String::new())).1.push(c),
+        }
+    }
+    let chars = prefix.chars().count();
+    (scan.start, scan.current) = word.unwrap_or((chars, String::new()));
+    scan.redirect = redirect;
+    scan
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if c.is_whitespace() || "'\"\\$&|;<>".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn candidate(value: impl Into<String>, kind: CandidateKind, detail: Option<&str>) -> Candidate {
+    Candidate { value: value.into(), kind, detail: detail.map(str::to_string) }
+}
+
+fn complete_paths(context: &Context, current: &str, directories_only: bool) -> Vec<Candidate> {
+    let Some(files) = context.files else {
+        return vec![];
+    };
+    let (dir_part, name) = match current.rfind('/') {
+        Some(slash) => current.split_at(slash + 1),
+        None => ("", current),
+    };
+    let dir = shell::resolve(context.cwd, if dir_part.is_empty() { "." } else { dir_part });
+    let Ok(entries) = vfs::list_directory(files, &dir) else {
+        return vec![];
+    };
+    entries
+        .into_iter()
+        .filter(|entry| !directories_only || entry.kind == FileKind::Directory)
+        .map(|entry| (entry, vfs::file_name(&entry.path)))
+        .filter(|(_, entry_name)| entry_name.starts_with(name) && (name.starts_with('.') || !entry_name.starts_with('.')))
+        .map(|(entry, entry_name)| match entry.kind {
+            FileKind::Directory => candidate(format!("{}{}/", escape(dir_part), escape(entry_name)), CandidateKind::Directory, None),
+            FileKind::File => candidate(format!("{}{}", escape(dir_part), escape(entry_name)), CandidateKind::File, None),
+        })
+        .collect()
+}
+
+fn complete_methods(context: &Context, current: &str) -> Vec<Candidate> {
+    let Some(methods) = context.candid.and_then(|did| candid_call::methods(did).ok()) else {
+        return vec![];
+    };
+    methods
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(current))
+        .map(|(name, signature)| candidate(escape(&name), CandidateKind::Method, Some(&signature)))
+        .collect()
+}
+
+fn complete_canister(context: &Context, current: &str) -> Vec<Candidate> {
+    context
+        .canister
+        .filter(|name| name.starts_with(current))
+        .map(|name| candidate(escape(name), CandidateKind::Canister, None))
+        .into_iter()
+        .collect()
+}
+
+fn complete_variables(context: &Context, current: &str, sigil: &str) -> Vec<Candidate> {
+    let current = current.strip_prefix(sigil).unwrap_or(current);
+    context
+        .env
+        .keys()
+        .filter(|name| name.starts_with(current))
+        .map(|name| candidate(format!("{}{}", sigil, name), CandidateKind::Variable, None))
+        .collect()
+}
+
+fn complete_commands<'a>(specs: impl IntoIterator<Item = &'a CommandSpec>, current: &str, kind: CandidateKind) -> Vec<Candidate> {
+    specs
+        .into_iter()
+        .filter(|spec| spec.name.starts_with(current))
+        .map(|spec| candidate(spec.name, kind, Some(spec.summary)))
+        .collect()
+}
+
+/// Candidates for the word at `cursor` (a character offset) in `line`.
+pub fn complete(line: &str, cursor: usize, context: &Context) -> Completion {
+    let prefix: String = line.chars().take(cursor).collect();
+    let scan = scan(&prefix);
+    let current = scan.current.as_str();
+    let mut candidates = if current.starts_with('$') {
+        complete_variables(context, current, "$")
+    } else if scan.redirect {
+        complete_paths(context, current, false)
+    } else if let Some((name, args)) = scan.words.split_first() {
+        // Walk down the subcommands already typed.
+        let mut spec = find(name);
+        let mut args = args;
+        while let (Some(parent), Some((first, rest))) = (spec, args.split_first()) {
+            match parent.subcommand(first) {
+                Some(sub) => {
+                    spec = Some(sub);
+                    args = rest;
+                }
+                None => break,
+            }
+        }
+        match spec {
+            None => complete_paths(context, current, false),
+            Some(spec) if current.starts_with('-') => spec
+                .flags
+                .iter()
+                .chain(std::iter::once(&flag("--help", "Show help")))
+                .filter(|f| f.name.starts_with(current))
+                .map(|f| candidate(f.name, CandidateKind::Flag, Some(f.help)))
+                .collect(),
+            Some(spec) if !spec.subcommands.is_empty() => {
+                if args.is_empty() {
+                    complete_commands(spec.subcommands, current, CandidateKind::Subcommand)
+                } else {
+                    vec![]
+                }
+            }
+            Some(spec) => {
+                let operands = args.iter().filter(|a| !a.starts_with('-')).count();
+                match spec.operands {
+                    Operand::Path => complete_paths(context, current, false),
+                    Operand::Directory => complete_paths(context, current, true),
+                    Operand::Command => complete_commands(COMMANDS, current, CandidateKind::Command),
+                    Operand::Variable => complete_variables(context, current, ""),
+                    Operand::Method if operands == 0 => complete_methods(context, current),
+                    Operand::Canister | Operand::CanisterMethod if operands == 0 => complete_canister(context, current),
+                    Operand::CanisterMethod if operands == 1 => complete_methods(context, current),
+                    Operand::Method | Operand::Canister | Operand::CanisterMethod | Operand::Text | Operand::None => vec![],
+                }
+            }
+        }
+    } else {
+        complete_commands(COMMANDS, current, CandidateKind::Command)
+    };
+    candidates.sort_by(|a, b| a.value.cmp(&b.value));
+    Completion { start: scan.start as u32, end: cursor as u32, candidates }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(completion: &Completion) -> Vec<&str> {
+        completion.candidates.iter().map(|c| c.value.as_str()).collect()
+    }
+
+    #[test]
+    fn help_is_generated_from_the_registry() {
+        assert_eq!(
+            help(&["wc"]).unwrap(),
+            "wc - Count lines, words and bytes\n\nUsage: wc [-clw] [FILE]...\n\n\
+             Options:\n  -c  Count bytes\n  -l  Count lines\n  -w  Count words\n"
+        );
+        assert!(help(&["rust"]).unwrap().contains("Subcommands:\n  build  Check the project"));
+        assert_eq!(help(&["rust", "test"]).unwrap(), "rust test - Run the project's tests\n\nUsage: rust test\n");
+        assert!(help(&["rust", "nope"]).is_none());
+        assert!(overview().contains("  grep    Print lines containing a fixed string\n"));
+        assert!(find("rm").unwrap().accepts_flag('R') && !find("rm").unwrap().accepts_flag('x'));
+    }
+
+    #[test]
+    fn completes_commands_flags_paths_and_methods() {
+        let mut files = vfs::single_file_tree("rust", String::new(), 0);
+        vfs::create_file(&mut files, "src/my file.rs", String::new(), 0).unwrap();
+        vfs::create_directory(&mut files, "scripts", 0).unwrap();
+        let env = BTreeMap::from([("HOME".to_string(), "/".to_string())]);
+        let did = "service : { greet : (text) -> (text) query; get : () -> (nat) }";
+        let context = Context { files: Some(&files), cwd: "", env: &env, candid: Some(did), canister: Some("my_app") };
+        let at_end = |line: &str| complete(line, line.chars().count(), &context);
+
+        assert_eq!(values(&at_end("c")), vec!["call", "cat", "cd", "compile", "cp"]);
+        assert_eq!(values(&at_end("ls -")), vec!["--help", "-1", "-a", "-l"]);
+        assert_eq!(values(&at_end("rust b")), vec!["build"]);
+        assert_eq!(values(&at_end("rust build ")), Vec::<&str>::new());
+        assert_eq!(values(&at_end("cat s")), vec!["scripts/", "src/"]);
+        assert_eq!(values(&at_end("cd s")), vec!["scripts/", "src/"]);
+        assert_eq!(values(&at_end("echo hi > src/")), vec!["src/lib.rs", "src/my\\ file.rs"]);
+        assert_eq!(values(&at_end("call g")), vec!["get", "greet"]);
+        assert_eq!(at_end("call g").candidates[1].detail.as_deref(), Some("(text) -> (text) query"));
+        assert_eq!(values(&at_end("call greet ")), Vec::<&str>::new());
+        assert_eq!(values(&at_end("echo $H")), vec!["$HOME"]);
+        assert_eq!(values(&at_end("dfx canister st")), vec!["start", "status", "stop"]);
+        assert_eq!(values(&at_end("dfx canister status ")), vec!["my_app"]);
+        assert_eq!(values(&at_end("dfx canister call my_app g")), vec!["get", "greet"]);
+        assert_eq!(values(&at_end("dfx canister delete -")), vec!["--help", "--yes", "-y"]);
+
+        let completion = at_end("pwd && cat \"src/my");
+        assert_eq!((completion.start, completion.end), (11, 18));
+        assert_eq!(values(&completion), vec!["src/my\\ file.rs"]);
+        // The cursor, not the end of the line, decides what is completed.
+        assert_eq!(values(&complete("ls src/ && pw", 2, &context)), vec!["ls"]);
+    }
+}
@@ -0,0 +1,249 @@
+// Admin-managed backend configuration.
+//
+// Holds the off-chain Docker controller settings and the shared secret used
+// to sign every request sent to it. Canister controllers are always admins;
+// extra admins can be granted at install time or by a controller later.
+
+use crate::error::{ApiError, ApiResult};
+use crate::profiles::{SessionLimits, SessionProfile};
+use candid::{CandidType, Principal};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::cell::RefCell;
+
+/// IC HTTP outcalls cannot return more than 2MB.
+pub const MAX_RESPONSE_BYTES_LIMIT: u64 = 2_000_000;
+const MIN_SECRET_LEN: usize = 32;
+
+/// Id of the endpoint implied by `ControllerConfig::base_url`.
+pub const DEFAULT_ENDPOINT: &str = "default";
+
+/// Boundary node domain project canisters are served from unless an admin
+/// configures another gateway, e.g. `http://localhost:4943` for a local replica.
+pub const MAINNET_GATEWAY: &str = "https://icp0.io";
+
+pub const TIMESTAMP_HEADER: &str = "X-ICPad-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-ICPad-Signature";
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ControllerConfig {
+    /// Base URL of the controller, e.g. `https://controller.example.com`.
+    pub base_url: String,
+    /// Response size limit passed to every HTTP outcall.
+    pub max_response_bytes: u64,
+    /// Sessions are stopped automatically after this many seconds.
+    pub session_timeout_secs: u64,
+}
+
+/// One host of the controller pool. All hosts share the settings and the
+/// secret of `ControllerConfig`.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ControllerEndpoint {
+    pub id: String,
+    pub base_url: String,
+    /// Share of new sessions relative to the other endpoints.
+    pub weight: u32,
+    /// Draining endpoints keep their sessions but get no new ones.
+    pub draining: bool,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct InitArgs {
+    pub controller: Option<ControllerConfig>,
+    pub controller_secret: Option<Vec<u8>>,
+    pub admins: Option<Vec<Principal>>,
+    pub gateway_url: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct BackendConfig {
+    #[serde(default)]
+    pub controller: Option<ControllerConfig>,
+    #[serde(default)]
+    pub controller_secret: Option<Vec<u8>>,
+    #[serde(default)]
+    pub admins: Vec<Principal>,
+    #[serde(default)]
+    pub profiles: Vec<SessionProfile>,
+    #[serde(default)]
+    pub limits: SessionLimits,
+    #[serde(default)]
+    pub endpoints: Vec<ControllerEndpoint>,
+    #[serde(default)]
+    pub gateway_url: Option<String>,
+}
+
+thread_local! {
+    pub static CONFIG: RefCell<BackendConfig> = RefCell::new(BackendConfig::default());
+}
+
+fn validate_url(url: &str) -> ApiResult<()> {
+    let url = url.trim();
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        return Err(ApiError::InvalidInput("base_url must start with https:// or http://".to_string()));
+    }
+    Ok(())
+}
+
+pub fn validate_controller_config(config: &ControllerConfig) -> ApiResult<()> {
+    validate_url(&config.base_url)?;
+    if config.max_response_bytes == 0 || config.max_response_bytes > MAX_RESPONSE_BYTES_LIMIT {
+        return Err(ApiError::InvalidInput(format!(
+            "max_response_bytes must be between 1 and {}",
+            MAX_RESPONSE_BYTES_LIMIT
+        )));
+    }
+    if config.session_timeout_secs == 0 {
+        return Err(ApiError::InvalidInput("session_timeout_secs must be positive".to_string()));
+    }
+    Ok(())
+}
+
+pub fn validate_endpoint(endpoint: &ControllerEndpoint) -> ApiResult<()> {
+    if endpoint.id.trim().is_empty() {
+        return Err(ApiError::InvalidInput("Controller endpoint id cannot be empty".to_string()));
+    }
+    if endpoint.weight == 0 {
+        return Err(ApiError::InvalidInput("Controller endpoint weight must be positive".to_string()));
+    }
+    validate_url(&endpoint.base_url)
+}
+
+pub fn validate_gateway_url(url: &str) -> ApiResult<()> {
+    validate_url(url)?;
+    let host = url.trim().split_once("://").map_or("", |(_, host)| host.trim_end_matches('/'));
+    if host.is_empty() || host.contains(['/', '?', '#']) {
+        return Err(ApiError::InvalidInput("gateway_url must be a scheme and host, e.g. https://icp0.io".to_string()));
+    }
+    Ok(())
+}
+
+pub fn validate_secret(secret: &[u8]) -> ApiResult<()> {
+    if secret.len() < MIN_SECRET_LEN {
+        return Err(ApiError::InvalidInput(format!(
+            "Controller secret must be at least {} bytes",
+            MIN_SECRET_LEN
+        )));
+    }
+    Ok(())
+}
+
+pub fn apply_init_args(args: InitArgs) -> ApiResult<()> {
+    if let Some(controller) = &args.controller {
+        validate_controller_config(controller)?;
+    }
+    if let Some(secret) = &args.controller_secret {
+        validate_secret(secret)?;
+    }
+    if let Some(url) = &args.gateway_url {
+        validate_gateway_url(url)?;
+    }
+    CONFIG.with(|c| {
+        let mut config = c.borrow_mut();
+        config.controller = args.controller;
+        config.controller_secret = args.controller_secret;
+        config.admins = args.admins.unwrap_or_default();
+        config.gateway_url = args.gateway_url;
+    });
+    Ok(())
+}
+
+pub fn is_admin(principal: &Principal) -> bool {
+    ic_cdk::api::is_controller(principal) || CONFIG.with(|c| c.borrow().admins.contains(principal))
+}
+
+pub fn require_admin() -> ApiResult<Principal> {
+    let caller = ic_cdk::api::msg_caller();
+    if !is_admin(&caller) {
+        return Err(ApiError::Unauthorized("only admins can change the backend configuration".to_string()));
+    }
+    Ok(caller)
+}
+
+/// The controller settings and secret, or an error telling admins what is missing.
+pub fn controller() -> ApiResult<(ControllerConfig, Vec<u8>)> {
+    CONFIG.with(|c| {
+        let config = c.borrow();
+        let controller = config.controller.clone().ok_or_else(|| {
+            ApiError::Internal("Docker controller is not configured, an admin must call set_controller_config".to_string())
+        })?;
+        let secret = config.controller_secret.clone().ok_or_else(|| {
+            ApiError::Internal("Docker controller secret is not configured, an admin must call set_controller_secret".to_string())
+        })?;
+        Ok((controller, secret))
+    })
+}
+
+/// Where project canisters are served from, `MAINNET_GATEWAY` by default.
+pub fn gateway_url() -> String {
+    CONFIG.with(|c| c.borrow().gateway_url.clone()).unwrap_or_else(|| MAINNET_GATEWAY.to_string())
+}
+
+/// The controller pool: the configured endpoints, or a single default
+/// endpoint at `ControllerConfig::base_url` when none are configured.
+pub fn endpoints() -> Vec<ControllerEndpoint> {
+    CONFIG.with(|c| {
+        let config = c.borrow();
+        if !config.endpoints.is_empty() {
+            return config.endpoints.clone();
+        }
+        config
+            .controller
+            .iter()
+            .map(|controller| default_endpoint(&controller.base_url))
+            .collect()
+    })
+}
+
+/// Sessions started before the pool existed belong to the default endpoint,
+/// which stays reachable at `base_url` even when it is not in the pool.
+pub fn endpoint(id: &str) -> ApiResult<ControllerEndpoint> {
+    if let Some(endpoint) = endpoints().into_iter().find(|e| e.id == id) {
+        return Ok(endpoint);
+    }
+    if id == DEFAULT_ENDPOINT {
+        let (controller, _) = controller()?;
+        return Ok(default_endpoint(&controller.base_url));
+    }
+    Err(ApiError::NotFound(format!("Controller endpoint not found: {}", id)))
+}
+
+fn default_endpoint(base_url: &str) -> ControllerEndpoint {
+    ControllerEndpoint {
+        id: DEFAULT_ENDPOINT.to_string(),
+        base_url: base_url.to_string(),
+        weight: 1,
+        draining: false,
+    }
+}
+
+/// Hex HMAC-SHA256 of `"{timestamp}.{method}.{path}.{body}"`. The controller
+/// recomputes it and rejects requests with a bad signature or a stale
+/// timestamp. Covering the method and path keeps a signed body from being
+/// replayed against another route.
+pub fn sign(secret: &[u8], timestamp_secs: u64, method: &str, path: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}.{}.", timestamp_secs, method, path).as_bytes());
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_matches_reference_hmac() {
+        // echo -n '1700000000.POST./stop.{"a":1}' | openssl dgst -sha256 -hmac 'secret'
+        assert_eq!(
+            sign(b"secret", 1_700_000_000, "POST", "/stop", br#"{"a":1}"#),
+            "f48791731af4cd3824dd2d763759895bce06e7cf45105a9089d7a7454ff213b3"
+        );
+        // The same body signed for another route does not verify there.
+        assert_ne!(
+            sign(b"secret", 1_700_000_000, "POST", "/exec", br#"{"a":1}"#),
+            sign(b"secret", 1_700_000_000, "POST", "/stop", br#"{"a":1}"#)
+        );
+    }
+}
@@ -0,0 +1,544 @@
+// Client for the pool of off-chain Docker controllers.
+//
+// Requests are signed (see `config`), carry an idempotency key when repeating
+// them must not repeat their effect, and go through a per-endpoint circuit
+// breaker that fails fast while that controller is down. A periodic health
+// check probes every endpoint so breakers also recover without user traffic.
+// New sessions are placed on the least loaded healthy endpoint relative to
+// its weight; later calls for a session go to the endpoint that hosts it.
+//
+// Requests that never reached the controller are retried once inline, after
+// checking the breaker again; callers that cannot leave work unfinished
+// (starting and stopping containers) hand the rest off to
+// `retry_in_background`, which backs off exponentially using timers. An
+// update call cannot await a timer, so the long retries always run after the
+// call has replied.
+
+use crate::config::{self, ControllerEndpoint};
+use crate::error::{ApiError, ApiResult};
+use crate::transform;
+use candid::CandidType;
+use ic_cdk::management_canister::{http_request, HttpHeader, HttpMethod, HttpRequestArgs};
+use num_traits::cast::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Duration;
+
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+pub const HEALTH_PATH: &str = "/health";
+pub const START_PATH: &str = "/start";
+pub const STOP_PATH: &str = "/stop";
+
+const HEALTH_CHECK_INTERVAL_SECS: u64 = 60;
+
+/// Attempts made while the caller waits, for transport errors only.
+const INLINE_ATTEMPTS: u32 = 2;
+/// Further attempts made from timers by `retry_in_background`.
+const BACKGROUND_ATTEMPTS: u32 = 5;
+const BASE_BACKOFF_SECS: u64 = 2;
+const MAX_BACKOFF_SECS: u64 = 60;
+
+/// Consecutive transient failures that open the circuit.
+const FAILURE_THRESHOLD: u32 = 5;
+/// How long an open circuit rejects calls before letting a trial through.
+const OPEN_COOLDOWN_SECS: u64 = 30;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// A failed controller call. Transient failures (transport errors, 429 and
+/// 5xx responses, an open circuit) are worth retrying; anything else is
+/// returned as is. `transport` marks requests that got no response at all.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub error: ApiError,
+    pub retryable: bool,
+    pub transport: bool,
+}
+
+impl From<Failure> for ApiError {
+    fn from(f: Failure) -> Self {
+        f.error
+    }
+}
+
+impl From<Failure> for String {
+    fn from(f: Failure) -> Self {
+        f.error.to_string()
+    }
+}
+
+impl Failure {
+    fn transient(message: String) -> Self {
+        Failure { error: ApiError::External(message), retryable: true, transport: false }
+    }
+
+    fn transport(message: String) -> Self {
+        Failure { transport: true, ..Failure::transient(message) }
+    }
+
+    fn fatal(error: ApiError) -> Self {
+        Failure { error, retryable: false, transport: false }
+    }
+}
+
+pub fn classify_status(status: u64) -> Result<(), Failure> {
+    match status {
+        200..=299 => Ok(()),
+        429 | 500..=599 => Err(Failure::transient(format!("Controller returned status {}", status))),
+        _ => Err(Failure::fatal(ApiError::External(format!("Non-200 status code: {}", status)))),
+    }
+}
+
+/// Delay before background attempt `attempt` (0 based): 2s, 4s, 8s, ... capped at a minute.
+pub fn backoff(attempt: u32) -> Duration {
+    let secs = BASE_BACKOFF_SECS.saturating_mul(1u64 << attempt.min(16));
+    Duration::from_secs(secs.min(MAX_BACKOFF_SECS))
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ControllerHealth {
+    pub id: String,
+    pub base_url: String,
+    pub weight: u32,
+    pub draining: bool,
+    /// Sessions that may still hold a container on this endpoint.
+    pub active_sessions: u32,
+    pub circuit: CircuitState,
+    pub consecutive_failures: u32,
+    pub last_failure: Option<String>,
+    pub last_success_at: Option<u64>,
+    /// Seconds until an open circuit lets a trial call through.
+    pub retry_after_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: u64,
+    /// When the half-open trial call was let through, while it is outstanding.
+    probe_started_at: Option<u64>,
+    last_failure: Option<String>,
+    last_success_at: Option<u64>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            opened_at: 0,
+            probe_started_at: None,
+            last_failure: None,
+            last_success_at: None,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    fn reopens_at(&self) -> u64 {
+        self.opened_at.saturating_add(OPEN_COOLDOWN_SECS * NANOS_PER_SEC)
+    }
+
+    /// Whether a half-open trial call is still outstanding. A trial that never
+    /// reported back, e.g. across an upgrade, stops counting after a cooldown.
+    fn probing(&self, now: u64) -> bool {
+        self.probe_started_at
+            .is_some_and(|started| now < started.saturating_add(OPEN_COOLDOWN_SECS * NANOS_PER_SEC))
+    }
+
+    /// Whether placement should consider the endpoint: closed, or ready to
+    /// let a trial call through.
+    pub fn available(&self, now: u64) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => now >= self.reopens_at(),
+            CircuitState::HalfOpen => !self.probing(now),
+        }
+    }
+
+    /// Fail fast while open; after the cooldown a single trial call is let
+    /// through and the rest wait for its outcome.
+    pub fn allow(&mut self, now: u64) -> Result<(), Failure> {
+        match self.state {
+            CircuitState::Closed => return Ok(()),
+            CircuitState::Open if now < self.reopens_at() => {
+                return Err(Failure::transient(format!(
+                    "Docker controller is unavailable, retry in {}s",
+                    (self.reopens_at() - now).div_ceil(NANOS_PER_SEC)
+                )));
+            }
+            CircuitState::HalfOpen if self.probing(now) => {
+                return Err(Failure::transient("Docker controller is recovering, retry shortly".to_string()));
+            }
+            CircuitState::Open | CircuitState::HalfOpen => {}
+        }
+        self.state = CircuitState::HalfOpen;
+        self.probe_started_at = Some(now);
+        Ok(())
+    }
+
+    pub fn on_success(&mut self, now: u64) {
+        self.probe_started_at = None;
+        self.state = CircuitState::Closed;
+        self.consecutive_failures = 0;
+        self.last_success_at = Some(now);
+    }
+
+    /// Only transient failures count; a 4xx means the controller is up.
+    pub fn on_failure(&mut self, now: u64, failure: &Failure) {
+        self.probe_started_at = None;
+        if !failure.retryable {
+            return;
+        }
+        self.consecutive_failures += 1;
+        self.last_failure = Some(failure.error.to_string());
+        if self.state == CircuitState::HalfOpen || self.consecutive_failures >= FAILURE_THRESHOLD {
+            self.state = CircuitState::Open;
+            self.opened_at = now;
+        }
+    }
+
+    pub fn health(&self, endpoint: &ControllerEndpoint, active_sessions: u32, now: u64) -> ControllerHealth {
+        ControllerHealth {
+            id: endpoint.id.clone(),
+            base_url: endpoint.base_url.clone(),
+            weight: endpoint.weight,
+            draining: endpoint.draining,
+            active_sessions,
+            circuit: self.state,
+            consecutive_failures: self.consecutive_failures,
+            last_failure: self.last_failure.clone(),
+            last_success_at: self.last_success_at,
+            retry_after_secs: (self.state == CircuitState::Open)
+                .then(|| self.reopens_at().saturating_sub(now).div_ceil(NANOS_PER_SEC)),
+        }
+    }
+}
+
+thread_local! {
+    static BREAKERS: RefCell<BTreeMap<String, CircuitBreaker>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+fn with_breaker<T>(endpoint_id: &str, f: impl FnOnce(&mut CircuitBreaker) -> T) -> T {
+    BREAKERS.with(|b| f(b.borrow_mut().entry(endpoint_id.to_string()).or_default()))
+}
+
+/// Health of every endpoint in the pool, given the session count per endpoint.
+pub fn health(load: &BTreeMap<String, u32>) -> Vec<ControllerHealth> {
+    let now = crate::env::time();
+    config::endpoints()
+        .iter()
+        .map(|endpoint| {
+            let active = load.get(&endpoint.id).copied().unwrap_or(0);
+            with_breaker(&endpoint.id, |breaker| breaker.health(endpoint, active, now))
+        })
+        .collect()
+}
+
+/// Pick the endpoint for a new session: among endpoints that are not
+/// draining and whose circuit is not open, the one with the fewest sessions
+/// per unit of weight. Ties go to the heavier endpoint, then to the lower id,
+/// so every replica makes the same choice.
+pub fn pick<'a>(
+    endpoints: &'a [ControllerEndpoint],
+    available: impl Fn(&ControllerEndpoint) -> bool,
+    load: &BTreeMap<String, u32>,
+) -> ApiResult<&'a ControllerEndpoint> {
+    endpoints
+        .iter()
+        .filter(|e| !e.draining && e.weight > 0 && available(e))
+        .min_by(|a, b| {
+            let load_a = load.get(&a.id).copied().unwrap_or(0) as u64;
+            let load_b = load.get(&b.id).copied().unwrap_or(0) as u64;
+            // Compare load_a / weight_a with load_b / weight_b without division.
+            (load_a * b.weight as u64)
+                .cmp(&(load_b * a.weight as u64))
+                .then(b.weight.cmp(&a.weight))
+                .then(a.id.cmp(&b.id))
+        })
+        .ok_or_else(|| ApiError::External("No healthy Docker controller is available for new sessions".to_string()))
+}
+
+/// The endpoint a new session should be started on.
+pub fn place(load: &BTreeMap<String, u32>) -> ApiResult<ControllerEndpoint> {
+    let endpoints = config::endpoints();
+    if endpoints.is_empty() {
+        config::controller()?;
+    }
+    let now = crate::env::time();
+    pick(&endpoints, |e| with_breaker(&e.id, |b| b.available(now)), load).cloned()
+}
+
+/// Build a signed POST to a controller endpoint.
+fn request(endpoint: &ControllerEndpoint, path: &str, payload: &str, idempotency_key: Option<&str>) -> ApiResult<HttpRequestArgs> {
+    let (controller, secret) = config::controller()?;
+    let timestamp = crate::env::time() / NANOS_PER_SEC;
+    let body = payload.as_bytes().to_vec();
+    let signature = config::sign(&secret, timestamp, "POST", path, &body);
+
+    let mut headers = vec![
+        HttpHeader {
+            name: "Content-Type".to_string(),
+            value: "application/json".to_string(),
+        },
+        HttpHeader {
+            name: config::TIMESTAMP_HEADER.to_string(),
+            value: timestamp.to_string(),
+        },
+        HttpHeader {
+            name: config::SIGNATURE_HEADER.to_string(),
+            value: format!("v2={}", signature),
+        },
+    ];
+    if let Some(key) = idempotency_key {
+        headers.push(HttpHeader {
+            name: IDEMPOTENCY_HEADER.to_string(),
+            value: key.to_string(),
+        });
+    }
+
+    Ok(HttpRequestArgs {
+        url: format!("{}{}", endpoint.base_url.trim_end_matches('/'), path),
+        max_response_bytes: Some(controller.max_response_bytes),
+        method: HttpMethod::POST,
+        headers,
+        body: Some(body),
+        transform: Some(transform::context(path)),
+    })
+}
+
+/// Send one request and feed the outcome to the endpoint's breaker.
+async fn send(endpoint_id: &str, path: &str, payload: &str, idempotency_key: Option<&str>) -> Result<Vec<u8>, Failure> {
+    let endpoint = config::endpoint(endpoint_id).map_err(Failure::fatal)?;
+    let req = request(&endpoint, path, payload, idempotency_key).map_err(Failure::fatal)?;
+    let result = match http_request(&req).await {
+        Ok(response) => classify_status(response.status.0.to_u64().unwrap_or(0)).map(|_| response.body),
+        Err(err) => Err(Failure::transport(format!("HTTP call failed: {:?}", err))),
+    };
+    with_breaker(endpoint_id, |breaker| {
+        let now = crate::env::time();
+        match &result {
+            Ok(_) => breaker.on_success(now),
+            Err(failure) => breaker.on_failure(now, failure),
+        }
+    });
+    result
+}
+
+async fn attempt(endpoint_id: &str, path: &str, payload: &str, idempotency_key: Option<&str>) -> Result<Vec<u8>, Failure> {
+    with_breaker(endpoint_id, |b| b.allow(crate::env::time()))?;
+    send(endpoint_id, path, payload, idempotency_key).await
+}
+
+/// POST `payload` to `path` on an endpoint. Transport errors are retried
+/// while the caller waits; everything else is returned to the caller.
+pub async fn call(endpoint_id: &str, path: &str, payload: &str, idempotency_key: Option<&str>) -> Result<Vec<u8>, Failure> {
+    retry_inline(|| attempt(endpoint_id, path, payload, idempotency_key)).await
+}
+
+/// Run `attempt` up to `INLINE_ATTEMPTS` times. Each attempt checks the
+/// breaker first, so a circuit opened by the previous one stops the retries.
+/// A response, even a 429 or 5xx, means the controller got the request and
+/// is left to `retry_in_background`.
+async fn retry_inline<F: Future<Output = Result<Vec<u8>, Failure>>>(mut attempt: impl FnMut() -> F) -> Result<Vec<u8>, Failure> {
+    let mut attempts = 1;
+    loop {
+        match attempt().await {
+            Err(failure) if failure.transport && attempts < INLINE_ATTEMPTS => attempts += 1,
+            result => return result,
+        }
+    }
+}
+
+/// Keep retrying a call from timers with exponential backoff, then pass the
+/// final outcome to `on_done`. Requests retried this way should carry an
+/// idempotency key so the controller applies them at most once.
+pub fn retry_in_background(
+    endpoint_id: String,
+    path: String,
+    payload: String,
+    idempotency_key: Option<String>,
+    on_done: Box<dyn FnOnce(Result<Vec<u8>, Failure>)>,
+) {
+    schedule_retry(Retry { endpoint_id, path, payload, idempotency_key }, 0, on_done);
+}
+
+struct Retry {
+    endpoint_id: String,
+    path: String,
+    payload: String,
+    idempotency_key: Option<String>,
+}
+
+fn schedule_retry(retry: Retry, attempt_no: u32, on_done: Box<dyn FnOnce(Result<Vec<u8>, Failure>)>) {
+    crate::env::set_timer(backoff(attempt_no), move || {
+        ic_cdk::futures::spawn_017_compat(async move {
+            let result = attempt(&retry.endpoint_id, &retry.path, &retry.payload, retry.idempotency_key.as_deref()).await;
+            match result {
+                Err(failure) if failure.retryable && attempt_no + 1 < BACKGROUND_ATTEMPTS => {
+                    schedule_retry(retry, attempt_no + 1, on_done);
+                }
+                result => on_done(result),
+            }
+        });
+    });
+}
+
+/// Probe every endpoint on a timer. Probes skip the breaker check so an
+/// open circuit closes as soon as its controller answers again.
+pub fn start_health_checks() {
+    crate::env::set_timer_interval(Duration::from_secs(HEALTH_CHECK_INTERVAL_SECS), || {
+        ic_cdk::futures::spawn_017_compat(async {
+            for endpoint in config::endpoints() {
+                let _ = send(&endpoint.id, HEALTH_PATH, "{}", None).await;
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: u64 = NANOS_PER_SEC;
+
+    #[test]
+    fn classifies_statuses() {
+        assert!(classify_status(200).is_ok());
+        assert!(classify_status(503).unwrap_err().retryable);
+        assert!(classify_status(429).unwrap_err().retryable);
+        assert!(!classify_status(404).unwrap_err().retryable);
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match std::pin::pin!(future).as_mut().poll(&mut context) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("future did not complete"),
+        }
+    }
+
+    /// Attempts made by `retry_inline` when every one fails with `failure`.
+    fn attempts_on(failure: Failure) -> u32 {
+        let count = std::cell::Cell::new(0);
+        let result = block_on(retry_inline(|| {
+            count.set(count.get() + 1);
+            std::future::ready(Err(failure.clone()))
+        }));
+        assert!(result.is_err());
+        count.get()
+    }
+
+    #[test]
+    fn only_transport_errors_retry_inline() {
+        assert_eq!(attempts_on(Failure::transport("connection reset".to_string())), INLINE_ATTEMPTS);
+        assert_eq!(attempts_on(classify_status(429).unwrap_err()), 1);
+        assert_eq!(attempts_on(classify_status(503).unwrap_err()), 1);
+        assert_eq!(attempts_on(classify_status(404).unwrap_err()), 1);
+        let mut open = CircuitBreaker { state: CircuitState::Open, opened_at: 1, ..Default::default() };
+        assert_eq!(attempts_on(open.allow(1).unwrap_err()), 1);
+
+        let count = std::cell::Cell::new(0);
+        let result = block_on(retry_inline(|| {
+            count.set(count.get() + 1);
+            std::future::ready(match count.get() {
+                1 => Err(Failure::transport("connection reset".to_string())),
+                _ => Ok(b"ok".to_vec()),
+            })
+        }));
+        assert_eq!(result.unwrap(), b"ok");
+        assert_eq!(count.get(), 2);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_cap() {
+        let delays: Vec<u64> = (0..7).map(|a| backoff(a).as_secs()).collect();
+        assert_eq!(delays, vec![2, 4, 8, 16, 32, 60, 60]);
+    }
+
+    fn endpoint(id: &str, weight: u32, draining: bool) -> ControllerEndpoint {
+        ControllerEndpoint {
+            id: id.to_string(),
+            base_url: format!("https://{}.example.com", id),
+            weight,
+            draining,
+        }
+    }
+
+    #[test]
+    fn placement_balances_by_weight() {
+        let endpoints = vec![endpoint("a", 1, false), endpoint("b", 3, false), endpoint("c", 5, true)];
+        let mut load = BTreeMap::new();
+        let mut placed = Vec::new();
+        for _ in 0..8 {
+            let chosen = pick(&endpoints, |_| true, &load).unwrap().id.clone();
+            *load.entry(chosen.clone()).or_insert(0) += 1;
+            placed.push(chosen);
+        }
+        // The draining endpoint gets nothing; b takes three sessions for each of a's.
+        assert_eq!(load.get("a"), Some(&2));
+        assert_eq!(load.get("b"), Some(&6));
+        assert_eq!(load.get("c"), None);
+        assert_eq!(placed[0], "b");
+
+        assert_eq!(pick(&endpoints, |e| e.id != "b", &load).unwrap().id, "a");
+        assert!(pick(&endpoints, |_| false, &load).is_err());
+    }
+
+    fn health(breaker: &CircuitBreaker, now: u64) -> ControllerHealth {
+        breaker.health(&endpoint("a", 1, false), 0, now)
+    }
+
+    #[test]
+    fn circuit_opens_and_recovers() {
+        let mut breaker = CircuitBreaker::default();
+        let down = Failure::transient("connection refused".to_string());
+        for i in 0..FAILURE_THRESHOLD {
+            assert!(breaker.allow(i as u64 * SEC).is_ok());
+            breaker.on_failure(i as u64 * SEC, &down);
+        }
+        let opened = (FAILURE_THRESHOLD - 1) as u64 * SEC;
+        assert_eq!(health(&breaker, opened).circuit, CircuitState::Open);
+        assert_eq!(health(&breaker, opened).retry_after_secs, Some(OPEN_COOLDOWN_SECS));
+        assert!(breaker.allow(opened + SEC).is_err());
+        assert!(!breaker.available(opened + SEC));
+
+        // After the cooldown one trial goes through and the others wait for
+        // it; failing it reopens the circuit.
+        let trial = opened + OPEN_COOLDOWN_SECS * SEC;
+        assert!(breaker.allow(trial).is_ok());
+        assert_eq!(health(&breaker, trial).circuit, CircuitState::HalfOpen);
+        let waiting = breaker.allow(trial).unwrap_err();
+        assert!(waiting.retryable);
+        assert!(!breaker.available(trial));
+        breaker.on_failure(trial, &down);
+        assert!(breaker.allow(trial + SEC).is_err());
+
+        // A trial that never reports back stops blocking after a cooldown.
+        let mut stuck = breaker.clone();
+        let reopened = trial + OPEN_COOLDOWN_SECS * SEC;
+        assert!(stuck.allow(reopened).is_ok());
+        assert!(stuck.allow(reopened + SEC).is_err());
+        assert!(stuck.allow(reopened + OPEN_COOLDOWN_SECS * SEC).is_ok());
+
+        let retry = trial + OPEN_COOLDOWN_SECS * SEC;
+        assert!(breaker.allow(retry).is_ok());
+        breaker.on_success(retry);
+        assert_eq!(health(&breaker, retry).circuit, CircuitState::Closed);
+        assert_eq!(health(&breaker, retry).consecutive_failures, 0);
+
+        // Client errors do not count against the controller.
+        breaker.on_failure(retry, &classify_status(400).unwrap_err());
+        assert_eq!(health(&breaker, retry).consecutive_failures, 0);
+    }
+}
@@ -0,0 +1,160 @@
+// Canister creation and code installation through the management canister.
+//
+// The backend creates one canister per project and stays one of its
+// controllers (together with the project owner) so later deploys can
+// upgrade it in place.
+
+use crate::error::{ApiError, ApiResult};
+use candid::Principal;
+use ic_cdk::management_canister::{
+    create_canister_with_extra_cycles, install_code, CanisterInstallMode, CanisterSettings,
+    CreateCanisterArgs, InstallCodeArgs,
+};
+
+/// Cycles given to every newly created project canister.
+pub const PROJECT_CANISTER_CYCLES: u128 = 500_000_000_000;
+
+/// Project canisters a single owner may hold, counting ones being created.
+pub const MAX_CANISTERS_PER_OWNER: usize = 3;
+
+/// Project canisters the backend holds across all owners, counting ones
+/// being created. Owners are cheap to mint, so this is the real spending cap.
+pub const MAX_TOTAL_CANISTERS: usize = 100;
+
+/// Minimum time between two canister creations by the same owner.
+pub const CANISTER_CREATION_INTERVAL_NANOS: u64 = 10 * 60 * 1_000_000_000;
+
+/// Cycles the backend keeps for itself and never spends on project canisters.
+const BACKEND_CYCLES_RESERVE: u128 = 200_000_000_000;
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+pub fn validate_wasm(wasm: &[u8]) -> ApiResult<()> {
+    if wasm.is_empty() {
+        return Err(ApiError::InvalidInput("WASM module is empty".to_string()));
+    }
+    if !wasm.starts_with(WASM_MAGIC) && !wasm.starts_with(GZIP_MAGIC) {
+        return Err(ApiError::InvalidInput("Not a WASM module: expected a .wasm or .wasm.gz file".to_string()));
+    }
+    Ok(())
+}
+
+pub fn check_canister_quota(owned: usize) -> ApiResult<()> {
+    if owned >= MAX_CANISTERS_PER_OWNER {
+        return Err(ApiError::Conflict(format!(
+            "You already have {} project canister(s), the limit is {}; deploy to one of your existing projects instead",
+            owned, MAX_CANISTERS_PER_OWNER
+        )));
+    }
+    Ok(())
+}
+
+pub fn check_canister_budget(total: usize) -> ApiResult<()> {
+    if total >= MAX_TOTAL_CANISTERS {
+        return Err(ApiError::Conflict(format!(
+            "The IDE has reached its limit of {} project canisters, try again later",
+            MAX_TOTAL_CANISTERS
+        )));
+    }
+    Ok(())
+}
+
+/// Rejects a creation less than `CANISTER_CREATION_INTERVAL_NANOS` after the
+/// owner's previous one.
+pub fn check_creation_rate(last_created: Option<u64>, now: u64) -> ApiResult<()> {
+    match last_created {
+        Some(last) if now.saturating_sub(last) < CANISTER_CREATION_INTERVAL_NANOS => {
+            let wait = (CANISTER_CREATION_INTERVAL_NANOS - now.saturating_sub(last)).div_ceil(1_000_000_000);
+            Err(ApiError::Conflict(format!("A canister was created for you recently, try again in {} seconds", wait)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Create an empty canister controlled by the backend and `owner`.
+pub async fn create_project_canister(owner: Principal) -> ApiResult<Principal> {
+    let needed = PROJECT_CANISTER_CYCLES + ic_cdk::api::cost_create_canister() + BACKEND_CYCLES_RESERVE;
+    let balance = ic_cdk::api::canister_cycle_balance();
+    if balance < needed {
+        return Err(ApiError::External(format!(
+            "Backend has insufficient cycles to create a canister: {} available, {} needed",
+            balance, needed
+        )));
+    }
+
+    let settings = CanisterSettings {
+        controllers: Some(vec![ic_cdk::api::canister_self(), owner]),
+        ..Default::default()
+    };
+    let result = create_canister_with_extra_cycles(
+        &CreateCanisterArgs { settings: Some(settings) },
+        PROJECT_CANISTER_CYCLES,
+    )
+    .await
+    .map_err(|e| ApiError::External(format!("create_canister failed: {}", e)))?;
+    Ok(result.canister_id)
+}
+
+/// Install `wasm`, upgrading when the canister already runs project code.
+pub async fn install_wasm(
+    canister_id: Principal,
+    wasm: Vec<u8>,
+    init_args: Vec<u8>,
+    upgrade: bool,
+) -> ApiResult<CanisterInstallMode> {
+    let mode = if upgrade {
+        CanisterInstallMode::Upgrade(None)
+    } else {
+        CanisterInstallMode::Install
+    };
+    install_code(&InstallCodeArgs {
+        mode,
+        canister_id,
+        wasm_module: wasm,
+        arg: init_args,
+    })
+    .await
+    .map_err(|e| ApiError::External(format!("install_code failed: {}", e)))?;
+    Ok(mode)
+}
+
+/// Encoded empty argument list, used when no init args are supplied.
+pub fn empty_args() -> Vec<u8> {
+    candid::encode_args(()).unwrap_or_default()
+}
+
+/// The canister's address behind `gateway`, e.g. `https://<id>.icp0.io` on
+/// mainnet or `http://<id>.localhost:4943` on a local replica.
+pub fn canister_url(canister_id: &Principal, gateway: &str) -> String {
+    let gateway = gateway.trim().trim_end_matches('/');
+    let (scheme, host) = gateway.split_once("://").unwrap_or(("https", gateway));
+    format!("{}://{}.{}", scheme, canister_id, host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canister_creation_is_rate_limited() {
+        let now = 1_000 * CANISTER_CREATION_INTERVAL_NANOS;
+        assert!(check_creation_rate(None, now).is_ok());
+        assert!(check_creation_rate(Some(now - CANISTER_CREATION_INTERVAL_NANOS), now).is_ok());
+        let err = check_creation_rate(Some(now - 1_000_000_000), now).unwrap_err();
+        assert!(matches!(err, ApiError::Conflict(ref m) if m.contains("599 seconds")), "{:?}", err);
+    }
+
+    #[test]
+    fn canister_budget_is_global() {
+        assert!(check_canister_budget(MAX_TOTAL_CANISTERS - 1).is_ok());
+        assert!(check_canister_budget(MAX_TOTAL_CANISTERS).is_err());
+    }
+
+    #[test]
+    fn canister_url_follows_the_gateway() {
+        let id = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        assert_eq!(canister_url(&id, crate::config::MAINNET_GATEWAY), "https://rrkah-fqaaa-aaaaa-aaaaq-cai.icp0.io");
+        assert_eq!(canister_url(&id, "http://localhost:4943/"), "http://rrkah-fqaaa-aaaaa-aaaaq-cai.localhost:4943");
+    }
+}
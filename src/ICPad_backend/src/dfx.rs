@@ -0,0 +1,443 @@
+// dfx style commands for the project's canister.
+//
+// `dfx canister ...` reaches the project's canister through the management
+// canister: the backend is one of its controllers, so it can inspect, stop
+// or reconfigure it on the owner's behalf. Only the project's own canister
+// is ever accepted as a target. Output follows dfx's wording so steps
+// written for dfx read the same in the IDE terminal.
+
+use crate::candid_call;
+use crate::commands;
+use crate::error::{ApiError, ApiResult};
+use crate::shell::Output;
+use crate::vfs;
+use crate::Project;
+use candid::{Nat, Principal};
+use candid_parser::utils::CandidSource;
+use candid_parser::{bindings, syntax::IDLMergedProg, IDLProg};
+use ic_cdk::management_canister::{
+    self as management, CanisterInfoArgs, CanisterInfoResult, CanisterSettings, CanisterStatusArgs,
+    CanisterStatusResult, CanisterStatusType, LogVisibility, StartCanisterArgs, StopCanisterArgs,
+    UninstallCodeArgs, UpdateSettingsArgs,
+};
+
+/// The name the project's canister goes by, e.g. `my_app` for "My App".
+pub fn canister_name(project: &Project) -> String {
+    let name: String = project
+        .name
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    if name.is_empty() {
+        project.id.clone()
+    } else {
+        name
+    }
+}
+
+/// The project's canister, if `name` refers to it by canister name, project
+/// name, project id or canister id.
+fn target(project: &Project, name: &str) -> ApiResult<Principal> {
+    let canister = canister_name(project);
+    let by_id = project.canister_id.is_some_and(|id| id.to_text() == name);
+    if name != canister && name != project.name && name != project.id && !by_id {
+        return Err(ApiError::InvalidInput(format!(
+            "Cannot find canister '{}'. This project's canister is '{}'.",
+            name, canister
+        )));
+    }
+    project.canister_id.ok_or_else(|| {
+        ApiError::InvalidInput(format!("Cannot find canister id for '{}'. Please issue 'dfx deploy' first.", canister))
+    })
+}
+
+fn failed(call: &str, e: impl std::fmt::Display) -> ApiError {
+    ApiError::External(format!("{} failed: {}", call, e))
+}
+
+fn usage(subcommand: &str) -> Output {
+    let usage = commands::find("dfx")
+        .and_then(|dfx| dfx.subcommand("canister"))
+        .and_then(|canister| canister.subcommand(subcommand))
+        .map(|spec| spec.usage)
+        .unwrap_or_default();
+    Output::error(2, format!("Usage: dfx canister {} {}", subcommand, usage))
+}
+
+/// `dfx canister SUBCOMMAND ...`.
+pub async fn canister(project: &Project, args: &[&str]) -> ApiResult<Output> {
+    let name = canister_name(project);
+    Ok(match args {
+        ["id", canister] => Output::ok(target(project, canister)?.to_text()),
+        ["status", canister] => {
+            let canister_id = target(project, canister)?;
+            let status = management::canister_status(&CanisterStatusArgs { canister_id })
+                .await
+                .map_err(|e| failed("canister_status", e))?;
+            Output::ok(status_report(&name, &status))
+        }
+        ["info", canister] => {
+            let canister_id = target(project, canister)?;
+            let info = management::canister_info(&CanisterInfoArgs { canister_id, num_requested_changes: None })
+                .await
+                .map_err(|e| failed("canister_info", e))?;
+            Output::ok(info_report(&info))
+        }
+        ["start", canister] => {
+            let canister_id = target(project, canister)?;
+            management::start_canister(&StartCanisterArgs { canister_id })
+                .await
+                .map_err(|e| failed("start_canister", e))?;
+            Output::ok(format!("Starting code for canister {}, with canister_id {}", name, canister_id))
+        }
+        ["stop", canister] => {
+            let canister_id = target(project, canister)?;
+            management::stop_canister(&StopCanisterArgs { canister_id })
+                .await
+                .map_err(|e| failed("stop_canister", e))?;
+            Output::ok(format!("Stopping code for canister {}, with canister_id {}", name, canister_id))
+        }
+        ["delete", rest @ ..] => {
+            let (flags, operands): (Vec<&str>, Vec<&str>) = rest.iter().partition(|a| a.starts_with('-'));
+            let [canister] = operands.as_slice() else {
+                return Ok(usage("delete"));
+            };
+            let canister_id = target(project, canister)?;
+            if !flags.iter().any(|f| matches!(*f, "-y" | "--yes")) {
+                return Ok(Output::error(
+                    1,
+                    format!("Deleting canister {} removes its code and state. Run again with --yes to confirm.", name),
+                ));
+            }
+            // The canister is emptied rather than deleted: deleting burns its
+            // cycles, which the backend paid for, and would free a slot in the
+            // owner's canister quota. The next deploy reinstalls into it.
+            management::stop_canister(&StopCanisterArgs { canister_id })
+                .await
+                .map_err(|e| failed("stop_canister", e))?;
+            management::uninstall_code(&UninstallCodeArgs { canister_id })
+                .await
+                .map_err(|e| failed("uninstall_code", e))?;
+            management::start_canister(&StartCanisterArgs { canister_id })
+                .await
+                .map_err(|e| failed("start_canister", e))?;
+            crate::PROJECTS.with(|projects| {
+                if let Some(project) = projects.borrow_mut().get_mut(&project.id) {
+                    project.deployed = false;
+                }
+            });
+            Output::ok(format!(
+                "Stopping code for canister {}, with canister_id {}\nUninstalling code for canister {}, with canister_id {}\n\
+                 The canister id is kept for the project's next deploy.",
+                name, canister_id, name, canister_id
+            ))
+        }
+        ["call", canister, method, call_args @ ..] => {
+            let canister_id = target(project, canister)?;
+            let candid = project.interface().ok_or_else(|| {
+                ApiError::InvalidInput("No Candid interface stored for this project, compile it or redeploy it with its .did".to_string())
+            })?;
+            let call_args: Vec<String> = call_args.iter().map(|a| a.to_string()).collect();
+            match candid_call::call(canister_id, candid, method, &call_args).await {
+                Ok(reply) => Output::ok(reply),
+                Err(e) => Output::error(1, e.to_string()),
+            }
+        }
+        ["update-settings", canister, options @ ..] => {
+            let canister_id = target(project, canister)?;
+            let controllers = if options.iter().any(|o| o.ends_with("-controller") || o.contains("-controller=")) {
+                management::canister_status(&CanisterStatusArgs { canister_id })
+                    .await
+                    .map_err(|e| failed("canister_status", e))?
+                    .settings
+                    .controllers
+            } else {
+                Vec::new()
+            };
+            let settings = match settings_update(options, &controllers, ic_cdk::api::canister_self()) {
+                Ok(settings) => settings,
+                Err(e) => return Ok(Output::error(2, e)),
+            };
+            management::update_settings(&UpdateSettingsArgs { canister_id, settings })
+                .await
+                .map_err(|e| failed("update_settings", e))?;
+            Output::ok(format!("Updated settings for canister {}, with canister_id {}", name, canister_id))
+        }
+        [subcommand, ..] if is_subcommand(subcommand) => usage(subcommand),
+        [] => Output::ok(commands::help(&["dfx", "canister"]).unwrap_or_default()),
+        [subcommand, ..] => Output::error(2, format!("Unknown dfx canister command: {}", subcommand)),
+    })
+}
+
+fn is_subcommand(name: &str) -> bool {
+    commands::find("dfx")
+        .and_then(|dfx| dfx.subcommand("canister"))
+        .is_some_and(|canister| canister.subcommand(name).is_some())
+}
+
+fn module_hash(hash: &Option<Vec<u8>>) -> String {
+    match hash {
+        Some(hash) => format!("0x{}", hash.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+        None => "None".to_string(),
+    }
+}
+
+fn principals(list: &[Principal]) -> String {
+    list.iter().map(Principal::to_text).collect::<Vec<_>>().join(" ")
+}
+
+fn log_visibility(visibility: &LogVisibility) -> String {
+    match visibility {
+        LogVisibility::Controllers => "controllers".to_string(),
+        LogVisibility::Public => "public".to_string(),
+        LogVisibility::AllowedViewers(viewers) => format!("allowed viewers: {}", principals(viewers)),
+    }
+}
+
+/// `dfx canister status`, in dfx's layout.
+pub fn status_report(name: &str, status: &CanisterStatusResult) -> String {
+    let settings = &status.settings;
+    let state = match status.status {
+        CanisterStatusType::Running => "Running",
+        CanisterStatusType::Stopping => "Stopping",
+        CanisterStatusType::Stopped => "Stopped",
+    };
+    let stats = &status.query_stats;
+    [
+        format!("Canister status call result for {}.", name),
+        format!("Status: {}", state),
+        format!("Controllers: {}", principals(&settings.controllers)),
+        format!("Memory allocation: {} Bytes", settings.memory_allocation),
+        format!("Compute allocation: {} %", settings.compute_allocation),
+        format!("Freezing threshold: {} Seconds", settings.freezing_threshold),
+        format!("Idle cycles burned per day: {} Cycles", status.idle_cycles_burned_per_day),
+        format!("Memory Size: {} Bytes", status.memory_size),
+        format!("Balance: {} Cycles", status.cycles),
+        format!("Reserved: {} Cycles", status.reserved_cycles),
+        format!("Reserved cycles limit: {} Cycles", settings.reserved_cycles_limit),
+        format!("Wasm memory limit: {} Bytes", settings.wasm_memory_limit),
+        format!("Wasm memory threshold: {} Bytes", settings.wasm_memory_threshold),
+        format!("Module hash: {}", module_hash(&status.module_hash)),
+        format!("Number of queries: {}", stats.num_calls_total),
+        format!("Instructions spent in queries: {}", stats.num_instructions_total),
+        format!("Total query request payload size: {} Bytes", stats.request_payload_bytes_total),
+        format!("Total query response payload size: {} Bytes", stats.response_payload_bytes_total),
+        format!("Log visibility: {}", log_visibility(&settings.log_visibility)),
+    ]
+    .join("\n")
+}
+
+/// `dfx canister info`.
+pub fn info_report(info: &CanisterInfoResult) -> String {
+    format!("Controllers: {}\nModule hash: {}", principals(&info.controllers), module_hash(&info.module_hash))
+}
+
+/// Settings for `dfx canister update-settings OPTION...`. `controllers` are
+/// the canister's current controllers and `backend` must stay one of them,
+/// otherwise the IDE loses control of the project's canister. Allocations
+/// and the reserved cycles limit are refused since the backend pays for them.
+pub fn settings_update(options: &[&str], controllers: &[Principal], backend: Principal) -> Result<CanisterSettings, String> {
+    let mut settings = CanisterSettings::default();
+    let (mut set, mut add, mut remove) = (Vec::new(), Vec::new(), Vec::new());
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        let (name, value) = match option.split_once('=') {
+            Some((name, value)) => (name, value),
+            None => (*option, *options.next().ok_or_else(|| format!("{} requires a value", option))?),
+        };
+        let number = || {
+            value
+                .replace('_', "")
+                .parse::<u128>()
+                .ok()
+                .map(Nat::from)
+                .ok_or_else(|| format!("Invalid value for {}: '{}'", name, value))
+        };
+        let principal = || Principal::from_text(value).map_err(|_| format!("Invalid principal for {}: '{}'", name, value));
+        match name {
+            "--set-controller" => set.push(principal()?),
+            "--add-controller" => add.push(principal()?),
+            "--remove-controller" => remove.push(principal()?),
+            "--compute-allocation" | "--memory-allocation" | "--reserved-cycles-limit" => {
+                return Err(format!("{} is not available: the reservation would be paid for by the IDE's cycles", name))
+            }
+            "--freezing-threshold" => settings.freezing_threshold = Some(number()?),
+            "--wasm-memory-limit" => settings.wasm_memory_limit = Some(number()?),
+            "--wasm-memory-threshold" => settings.wasm_memory_threshold = Some(number()?),
+            "--log-visibility" => {
+                settings.log_visibility = Some(match value {
+                    "controllers" => LogVisibility::Controllers,
+                    "public" => LogVisibility::Public,
+                    _ => return Err(format!("Invalid value for {}: '{}', expected controllers or public", name, value)),
+                })
+            }
+            _ => return Err(format!("Unknown option: {}", name)),
+        }
+    }
+
+    if !(set.is_empty() && add.is_empty() && remove.is_empty()) {
+        let mut list = if set.is_empty() { controllers.to_vec() } else { set };
+        for principal in add {
+            if !list.contains(&principal) {
+                list.push(principal);
+            }
+        }
+        list.retain(|p| !remove.contains(p));
+        if !list.contains(&backend) {
+            return Err(format!("The IDE backend ({}) must remain a controller of the canister", backend));
+        }
+        settings.controllers = Some(list);
+    }
+    if settings == CanisterSettings::default() {
+        return Err("Nothing to update, pass at least one option".to_string());
+    }
+    Ok(settings)
+}
+
+/// `dfx generate`: client bindings for the canister `name` from its Candid
+/// interface, as `(path, contents)` under `src/declarations/<name>/`.
+pub fn bindings(name: &str, did: &str, canister_id: Option<Principal>) -> ApiResult<Vec<(String, String)>> {
+    let invalid = |e: candid_parser::Error| ApiError::InvalidInput(format!("Invalid Candid interface: {}", e));
+    let (env, actor) = CandidSource::Text(did).load().map_err(invalid)?;
+    let prog = IDLMergedProg::new(did.parse::<IDLProg>().map_err(invalid)?);
+    let dir = format!("src/declarations/{}", name);
+    let env_var = format!("CANISTER_ID_{}", name.to_uppercase());
+    let default_id = canister_id.map(|id| format!(" ?? \"{}\"", id)).unwrap_or_default();
+    let index = format!(
+        r#"import {{ Actor, HttpAgent }} from "@icp-sdk/core/agent";
+
+// Imports and re-exports the Candid interface
+import {{ idlFactory }} from "./{name}.did.js";
+export {{ idlFactory }} from "./{name}.did.js";
+
+export const canisterId = process.env.{env_var}{default_id};
+
+export const createActor = (canisterId, options = {{}}) => {{
+  const agent = options.agent || new HttpAgent({{ ...options.agentOptions }});
+
+  if (options.agent && options.agentOptions) {{
+    console.warn(
+      "Detected both agent and agentOptions passed to createActor. Ignoring agentOptions and proceeding with the provided agent."
+    );
+  }}
+
+  // Fetch the root key for certificate validation during development
+  if (process.env.DFX_NETWORK !== "ic") {{
+    agent.fetchRootKey().catch((err) => {{
+      console.warn("Unable to fetch root key. Check to ensure that your local replica is running");
+      console.error(err);
+    }});
+  }}
+
+  return Actor.createActor(idlFactory, {{
+    agent,
+    canisterId,
+    ...options.actorOptions,
+  }});
+}};
+
+export const {name} = canisterId ? createActor(canisterId) : undefined;
+"#
+    );
+    Ok(vec![
+        (format!("{}/{}.did", dir, name), did.to_string()),
+        (format!("{}/{}.did.js", dir, name), bindings::javascript::compile(&env, &actor)),
+        (format!("{}/{}.did.d.ts", dir, name), bindings::typescript::compile(&env, &actor, &prog)),
+        (format!("{}/index.js", dir), index),
+    ])
+}
+
+/// Write the bindings for the project's canister into its files.
+pub fn generate(project_id: &str) -> ApiResult<Output> {
+    let project = crate::get_owned_project(project_id)?;
+    let did = project.interface().ok_or_else(|| {
+        ApiError::InvalidInput("No Candid interface stored for this project, compile it or redeploy it with its .did".to_string())
+    })?;
+    let name = canister_name(&project);
+    let files = bindings(&name, did, project.canister_id)?;
+    crate::with_owned_project_mut(project_id, "dfx generate", |project, now| {
+        for (path, contents) in &files {
+            if project.files.contains_key(path) {
+                vfs::write_file(&mut project.files, path, contents.clone(), now)?;
+            } else {
+                vfs::create_file(&mut project.files, path, contents.clone(), now)?;
+            }
+        }
+        Ok(())
+    })?;
+    let mut report = format!("Generating type declarations for canister {}:\n", name);
+    for (path, _) in &files {
+        report.push_str(&format!("  {}\n", path));
+    }
+    Ok(Output::ok(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKEND: &str = "rrkah-fqaaa-aaaaa-aaaaq-cai";
+    const OWNER: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+
+    fn principal(text: &str) -> Principal {
+        Principal::from_text(text).unwrap()
+    }
+
+    #[test]
+    fn update_settings_options() {
+        let backend = principal(BACKEND);
+        let current = [backend, principal(OWNER)];
+        let settings = settings_update(
+            &["--freezing-threshold", "2_592_000", "--wasm-memory-limit=1_000_000", "--log-visibility", "public"],
+            &current,
+            backend,
+        )
+        .unwrap();
+        assert_eq!(settings.freezing_threshold, Some(Nat::from(2_592_000u32)));
+        assert_eq!(settings.wasm_memory_limit, Some(Nat::from(1_000_000u32)));
+        assert_eq!(settings.log_visibility, Some(LogVisibility::Public));
+        assert_eq!(settings.controllers, None);
+
+        let other = "2vxsx-fae";
+        let settings = settings_update(&["--add-controller", other, "--remove-controller", OWNER], &current, backend).unwrap();
+        assert_eq!(settings.controllers, Some(vec![backend, principal(other)]));
+
+        assert!(settings_update(&["--set-controller", OWNER], &current, backend).unwrap_err().contains("must remain"));
+        for option in ["--compute-allocation=1", "--memory-allocation=4096", "--reserved-cycles-limit=0"] {
+            assert!(settings_update(&[option], &current, backend).unwrap_err().contains("not available"));
+        }
+        assert!(settings_update(&["--freezing-threshold"], &current, backend).is_err());
+        assert!(settings_update(&["--cycles", "1"], &current, backend).is_err());
+        assert!(settings_update(&[], &current, backend).is_err());
+    }
+
+    #[test]
+    fn generates_bindings_and_reports() {
+        let did = "service : { greet : (text) -> (text) query }";
+        let files = bindings("my_app", did, Some(principal(OWNER))).unwrap();
+        let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "src/declarations/my_app/my_app.did",
+                "src/declarations/my_app/my_app.did.js",
+                "src/declarations/my_app/my_app.did.d.ts",
+                "src/declarations/my_app/index.js",
+            ]
+        );
+        assert!(files[1].1.contains("'greet' : IDL.Func([IDL.Text], [IDL.Text], ['query'])"));
+        assert!(files[2].1.contains("'greet' : ActorMethod<[string], string>"));
+        assert!(files[3].1.contains(&format!("process.env.CANISTER_ID_MY_APP ?? \"{}\"", OWNER)));
+        assert!(bindings("my_app", "service : {", None).is_err());
+
+        let info = CanisterInfoResult {
+            total_num_changes: 1,
+            recent_changes: vec![],
+            module_hash: Some(vec![0xab, 0x01]),
+            controllers: vec![principal(BACKEND), principal(OWNER)],
+        };
+        assert_eq!(info_report(&info), format!("Controllers: {} {}\nModule hash: 0xab01", BACKEND, OWNER));
+    }
+}
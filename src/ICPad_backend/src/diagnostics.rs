@@ -0,0 +1,303 @@
+// Editor diagnostics shared by every language check.
+//
+// The shape follows the Language Server Protocol so the IDE can underline
+// ranges and offer fixes directly: positions are zero based lines and
+// characters, and a diagnostic may point at related locations and carry
+// quick fixes made of text edits.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: u32,
+    /// Counted in characters, not bytes.
+    pub character: u32,
+}
+
+impl Position {
+    pub fn new(line: u32, character: u32) -> Self {
+        Position { line, character }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Range {
+    pub start: Position,
+    /// Exclusive.
+    pub end: Position,
+}
+
+impl Range {
+    pub fn new(start: Position, end: Position) -> Self {
+        Range { start, end }
+    }
+
+    pub fn at(position: Position) -> Self {
+        Range { start: position, end: position }
+    }
+
+    /// `len` characters from `start`, on one line.
+    pub fn span(start: Position, len: u32) -> Self {
+        Range { start, end: Position::new(start.line, start.character + len) }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub range: Range,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RelatedInformation {
+    pub location: Location,
+    pub message: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    pub range: Range,
+    pub new_text: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct QuickFix {
+    pub title: String,
+    pub file: String,
+    pub edits: Vec<TextEdit>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub range: Range,
+    pub severity: Severity,
+    /// Stable identifier of the check, e.g. `missing-export-candid`.
+    pub code: Option<String>,
+    /// The language check that produced it: `rust`, `motoko` or `javascript`.
+    pub source: String,
+    pub message: String,
+    pub related: Vec<RelatedInformation>,
+    pub fixes: Vec<QuickFix>,
+}
+
+impl Diagnostic {
+    pub fn new(source: &str, file: &str, range: Range, severity: Severity, message: impl Into<String>) -> Self {
+        Diagnostic {
+            file: file.to_string(),
+            range,
+            severity,
+            code: None,
+            source: source.to_string(),
+            message: message.into(),
+            related: vec![],
+            fixes: vec![],
+        }
+    }
+
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.code = Some(code.to_string());
+        self
+    }
+
+    pub fn with_related(mut self, file: &str, range: Range, message: impl Into<String>) -> Self {
+        self.related.push(RelatedInformation {
+            location: Location { file: file.to_string(), range },
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_fix(mut self, title: &str, edits: Vec<TextEdit>) -> Self {
+        self.fixes.push(QuickFix { title: title.to_string(), file: self.file.clone(), edits });
+        self
+    }
+}
+
+/// Compiler style, with one based line and column: `src/lib.rs:3:13: error[syntax]: ...`.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Information => "info",
+            Severity::Hint => "hint",
+        };
+        write!(f, "{}:{}:{}: {}", self.file, self.range.start.line + 1, self.range.start.character + 1, severity)?;
+        if let Some(code) = &self.code {
+            write!(f, "[{}]", code)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+pub fn error_count(diagnostics: &[Diagnostic]) -> usize {
+    diagnostics.iter().filter(|d| d.severity == Severity::Error).count()
+}
+
+/// Terminal rendering: one diagnostic per line, related locations indented below.
+pub fn render(diagnostics: &[Diagnostic]) -> String {
+    let mut lines = Vec::new();
+    for diagnostic in diagnostics {
+        lines.push(diagnostic.to_string());
+        for related in &diagnostic.related {
+            let start = related.location.range.start;
+            lines.push(format!(
+                "  --> {}:{}:{}: {}",
+                related.location.file,
+                start.line + 1,
+                start.character + 1,
+                related.message
+            ));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Position just past the last character of `content`.
+pub fn end_of(content: &str) -> Position {
+    let line = content.matches('\n').count() as u32;
+    let last_line = content.rsplit('\n').next().unwrap_or("");
+    Position::new(line, last_line.chars().count() as u32)
+}
+
+/// Report unbalanced `()`, `[]` and `{}` in C-like sources, skipping string
+/// literals and comments. Used by the Motoko and JavaScript checks.
+pub fn check_delimiters(source: &str, file: &str, content: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut open: Vec<(char, Position)> = Vec::new();
+    let mut chars = content.chars().peekable();
+    let (mut line, mut character) = (0u32, 0u32);
+    // Advance past one character, keeping track of the position.
+    let step = |c: char, line: &mut u32, character: &mut u32| {
+        if c == '\n' {
+            *line += 1;
+            *character = 0;
+        } else {
+            *character += 1;
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        let here = Position::new(line, character);
+        step(c, &mut line, &mut character);
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    chars.next();
+                    step(next, &mut line, &mut character);
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                step('*', &mut line, &mut character);
+                let mut previous = ' ';
+                let mut closed = false;
+                for next in chars.by_ref() {
+                    step(next, &mut line, &mut character);
+                    if previous == '*' && next == '/' {
+                        closed = true;
+                        break;
+                    }
+                    previous = next;
+                }
+                if !closed {
+                    diagnostics.push(
+                        Diagnostic::new(source, file, Range::span(here, 2), Severity::Error, "unterminated block comment")
+                            .with_code("unterminated-comment"),
+                    );
+                }
+            }
+            '"' | '\'' | '`' => {
+                let mut closed = false;
+                while let Some(next) = chars.next() {
+                    step(next, &mut line, &mut character);
+                    if next == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            step(escaped, &mut line, &mut character);
+                        }
+                    } else if next == c {
+                        closed = true;
+                        break;
+                    } else if next == '\n' && c != '`' {
+                        break;
+                    }
+                }
+                if !closed {
+                    diagnostics.push(
+                        Diagnostic::new(source, file, Range::span(here, 1), Severity::Error, "unterminated string literal")
+                            .with_code("unterminated-string"),
+                    );
+                }
+            }
+            '(' | '[' | '{' => open.push((c, here)),
+            ')' | ']' | '}' => {
+                let range = Range::span(here, 1);
+                let expected = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                match open.pop() {
+                    Some((opener, _)) if opener == expected => {}
+                    Some((opener, at)) => diagnostics.push(
+                        Diagnostic::new(source, file, range, Severity::Error, format!("mismatched closing delimiter `{}`", c))
+                            .with_code("mismatched-delimiter")
+                            .with_related(file, Range::span(at, 1), format!("`{}` opened here", opener)),
+                    ),
+                    None => diagnostics.push(
+                        Diagnostic::new(source, file, range, Severity::Error, format!("unexpected closing delimiter `{}`", c))
+                            .with_code("unexpected-delimiter"),
+                    ),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (opener, at) in open {
+        diagnostics.push(
+            Diagnostic::new(source, file, Range::span(at, 1), Severity::Error, format!("unclosed delimiter `{}`", opener))
+                .with_code("unclosed-delimiter"),
+        );
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_unbalanced_delimiters() {
+        let source = "actor {\n  // }\n  public func f() : async Text { \"}\" ];\n";
+        let diagnostics = check_delimiters("motoko", "main.mo", source);
+        assert_eq!(
+            render(&diagnostics),
+            "main.mo:3:38: error[mismatched-delimiter]: mismatched closing delimiter `]`\n\
+             \x20 --> main.mo:3:32: `{` opened here\n\
+             main.mo:1:7: error[unclosed-delimiter]: unclosed delimiter `{`"
+        );
+        assert!(check_delimiters("javascript", "a.js", "export const f = () => { return `}`; /* ) */ };").is_empty());
+    }
+
+    #[test]
+    fn end_position() {
+        assert_eq!(end_of(""), Position::new(0, 0));
+        assert_eq!(end_of("ab\ncdé"), Position::new(1, 3));
+        assert_eq!(end_of("ab\n"), Position::new(1, 0));
+    }
+}
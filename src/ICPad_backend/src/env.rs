@@ -0,0 +1,88 @@
+// The parts of the canister system API used by the lifecycle hooks and the
+// project endpoints.
+//
+// The real calls trap outside a replica, so under `cargo test` the caller,
+// the clock, stable memory and timers are in-memory stand-ins. That lets
+// tests drive `pre_upgrade`/`post_upgrade` and the endpoints themselves.
+
+use candid::Principal;
+use std::time::Duration;
+
+#[cfg(not(test))]
+pub fn caller() -> Principal {
+    ic_cdk::api::msg_caller()
+}
+
+#[cfg(not(test))]
+pub fn time() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(not(test))]
+pub fn stable_save(version: u32, bytes: Vec<u8>) -> Result<(), String> {
+    ic_cdk::storage::stable_save((version, bytes)).map_err(|e| e.to_string())
+}
+
+/// `None` for canisters that have never written stable memory.
+#[cfg(not(test))]
+pub fn stable_restore() -> Result<Option<(u32, Vec<u8>)>, String> {
+    if ic_cdk::stable::stable_size() == 0 {
+        return Ok(None);
+    }
+    ic_cdk::storage::stable_restore().map(Some)
+}
+
+#[cfg(not(test))]
+pub fn set_timer(delay: Duration, func: impl FnOnce() + 'static) {
+    ic_cdk_timers::set_timer(delay, func);
+}
+
+#[cfg(not(test))]
+pub fn set_timer_interval(interval: Duration, func: impl FnMut() + 'static) {
+    ic_cdk_timers::set_timer_interval(interval, func);
+}
+
+#[cfg(test)]
+pub use fake::*;
+
+#[cfg(test)]
+mod fake {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+
+    thread_local! {
+        static CALLER: Cell<Principal> = const { Cell::new(Principal::anonymous()) };
+        static TIME: Cell<u64> = const { Cell::new(0) };
+        static STABLE: RefCell<Option<(u32, Vec<u8>)>> = const { RefCell::new(None) };
+    }
+
+    pub fn caller() -> Principal {
+        CALLER.with(|c| c.get())
+    }
+
+    pub fn set_caller(principal: Principal) {
+        CALLER.with(|c| c.set(principal));
+    }
+
+    pub fn time() -> u64 {
+        TIME.with(|t| t.get())
+    }
+
+    pub fn set_time(nanos: u64) {
+        TIME.with(|t| t.set(nanos));
+    }
+
+    pub fn stable_save(version: u32, bytes: Vec<u8>) -> Result<(), String> {
+        STABLE.with(|s| *s.borrow_mut() = Some((version, bytes)));
+        Ok(())
+    }
+
+    pub fn stable_restore() -> Result<Option<(u32, Vec<u8>)>, String> {
+        Ok(STABLE.with(|s| s.borrow().clone()))
+    }
+
+    // Timers never fire in tests; their callbacks are tested directly.
+    pub fn set_timer(_delay: Duration, _func: impl FnOnce() + 'static) {}
+
+    pub fn set_timer_interval(_interval: Duration, _func: impl FnMut() + 'static) {}
+}
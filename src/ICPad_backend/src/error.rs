@@ -0,0 +1,41 @@
+// Structured errors returned by the typed backend API.
+//
+// Legacy endpoints still return `Result<String, String>`; `ApiError` converts
+// into the same message text they always produced so `?` works in both.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum ApiError {
+    NotFound(String),
+    Unauthorized(String),
+    InvalidInput(String),
+    /// The request clashes with existing state, e.g. a file that already exists.
+    Conflict(String),
+    /// A call to another canister or an off-chain service failed.
+    External(String),
+    Internal(String),
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            ApiError::NotFound(msg)
+            | ApiError::InvalidInput(msg)
+            | ApiError::Conflict(msg)
+            | ApiError::External(msg)
+            | ApiError::Internal(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<ApiError> for String {
+    fn from(e: ApiError) -> Self {
+        e.to_string()
+    }
+}
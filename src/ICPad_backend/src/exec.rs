@@ -0,0 +1,255 @@
+// Running commands inside Docker session containers.
+//
+// Short commands go through `/exec` and return their full output. Long
+// running ones are started as jobs whose output is read back in chunks by
+// byte offset, so no single outcall response has to hold all of it.
+//
+// Every replica of the subnet sends its own copy of each outcall, so requests
+// carry a `request_id` the controller uses to run a command only once. Reads
+// of a running job are keyed the same way: the controller answers every copy
+// of a read with the reply it gave the first one, so all replicas see the same
+// chunk even while the job keeps writing.
+
+use crate::error::{ApiError, ApiResult};
+use candid::CandidType;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+pub const EXEC_PATH: &str = "/exec";
+pub const JOB_START_PATH: &str = "/jobs/start";
+pub const JOB_OUTPUT_PATH: &str = "/jobs/output";
+pub const JOB_CANCEL_PATH: &str = "/jobs/cancel";
+
+const MAX_COMMAND_LEN: usize = 4096;
+
+/// Room left in each response for the JSON envelope around an output chunk.
+const CHUNK_OVERHEAD_BYTES: u64 = 1024;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ExecResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct JobOutput {
+    pub job_id: String,
+    /// Output starting at the requested offset.
+    pub chunk: String,
+    /// Offset to pass to the next read.
+    pub next_offset: u64,
+    pub finished: bool,
+    /// Set once the job has finished.
+    pub exit_code: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct JobStarted {
+    job_id: String,
+}
+
+pub fn validate_command(command: &str) -> ApiResult<()> {
+    if command.trim().is_empty() {
+        return Err(ApiError::InvalidInput("Command cannot be empty".to_string()));
+    }
+    if command.len() > MAX_COMMAND_LEN {
+        return Err(ApiError::InvalidInput(format!(
+            "Command is longer than {} bytes",
+            MAX_COMMAND_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Body for both `/exec` and `/jobs/start`.
+pub fn command_payload(container_id: &str, request_id: &str, command: &str) -> String {
+    json!({ "container_id": container_id, "request_id": request_id, "command": command }).to_string()
+}
+
+/// Request id of the read of `job_id` at `offset`, the same on every replica.
+pub fn output_request_id(job_id: &str, offset: u64) -> String {
+    format!("output:{}:{}", job_id, offset)
+}
+
+/// Request id of the cancellation of `job_id`.
+pub fn cancel_request_id(job_id: &str) -> String {
+    format!("cancel:{}", job_id)
+}
+
+/// Ask for output from `offset`, capped so the reply fits in `max_response_bytes`.
+pub fn output_payload(container_id: &str, job_id: &str, offset: u64, max_response_bytes: u64) -> String {
+    let max_bytes = max_response_bytes.saturating_sub(CHUNK_OVERHEAD_BYTES).max(1);
+    json!({
+        "container_id": container_id,
+        "job_id": job_id,
+        "request_id": output_request_id(job_id, offset),
+        "offset": offset,
+        "max_bytes": max_bytes,
+    })
+    .to_string()
+}
+
+pub fn cancel_payload(container_id: &str, job_id: &str) -> String {
+    json!({ "container_id": container_id, "job_id": job_id, "request_id": cancel_request_id(job_id) }).to_string()
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> ApiResult<T> {
+    serde_json::from_slice(body).map_err(|e| ApiError::External(format!("Unexpected controller response: {}", e)))
+}
+
+pub fn parse_exec(body: &[u8]) -> ApiResult<ExecResult> {
+    parse(body)
+}
+
+pub fn parse_job_started(body: &[u8]) -> ApiResult<String> {
+    parse::<JobStarted>(body).map(|started| started.job_id)
+}
+
+pub fn parse_job_output(body: &[u8]) -> ApiResult<JobOutput> {
+    parse(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform;
+    use ic_cdk::management_canister::{HttpRequestResult, TransformArgs};
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    /// In-memory stand-in for the controller's exec and job API.
+    #[derive(Default)]
+    struct MockController {
+        executed: Vec<String>,
+        replies: HashMap<String, Vec<u8>>,
+        jobs: HashMap<String, (String, bool)>,
+    }
+
+    impl MockController {
+        fn handle(&mut self, path: &str, payload: &str) -> Vec<u8> {
+            let req: Value = serde_json::from_str(payload).unwrap();
+            let reply = match path {
+                EXEC_PATH | JOB_START_PATH => {
+                    let request_id = req["request_id"].as_str().unwrap().to_string();
+                    if let Some(reply) = self.replies.get(&request_id) {
+                        return reply.clone();
+                    }
+                    let command = req["command"].as_str().unwrap().to_string();
+                    self.executed.push(command.clone());
+                    let reply = if path == EXEC_PATH {
+                        match command.strip_prefix("echo ") {
+                            Some(text) => json!({ "stdout": format!("{}\n", text), "stderr": "", "exit_code": 0 }),
+                            None => json!({ "stdout": "", "stderr": "sh: not found\n", "exit_code": 127 }),
+                        }
+                    } else {
+                        let job_id = format!("job_{}", self.jobs.len() + 1);
+                        self.jobs.insert(job_id.clone(), ("line 1\nline 2\nline 3\n".to_string(), false));
+                        json!({ "job_id": job_id, "started_at": 1 })
+                    };
+                    let bytes = reply.to_string().into_bytes();
+                    self.replies.insert(request_id, bytes.clone());
+                    return bytes;
+                }
+                JOB_OUTPUT_PATH => {
+                    let request_id = req["request_id"].as_str().unwrap().to_string();
+                    if let Some(reply) = self.replies.get(&request_id) {
+                        return reply.clone();
+                    }
+                    let (output, cancelled) = &self.jobs[req["job_id"].as_str().unwrap()];
+                    let offset = req["offset"].as_u64().unwrap() as usize;
+                    let end = (offset + req["max_bytes"].as_u64().unwrap() as usize).min(output.len());
+                    let finished = *cancelled || end == output.len();
+                    let reply = json!({
+                        "job_id": req["job_id"],
+                        "chunk": output[offset..end],
+                        "next_offset": end,
+                        "finished": finished,
+                        "exit_code": if *cancelled { Some(130) } else if finished { Some(0) } else { None },
+                    })
+                    .to_string()
+                    .into_bytes();
+                    self.replies.insert(request_id, reply.clone());
+                    return reply;
+                }
+                JOB_CANCEL_PATH => {
+                    assert_eq!(req["request_id"], cancel_request_id(req["job_id"].as_str().unwrap()));
+                    self.jobs.get_mut(req["job_id"].as_str().unwrap()).unwrap().1 = true;
+                    json!({ "cancelled": true })
+                }
+                _ => json!({ "error": "not found" }),
+            };
+            reply.to_string().into_bytes()
+        }
+
+        /// What the backend sees: the reply after the consensus transform.
+        fn call(&mut self, path: &str, payload: &str) -> Vec<u8> {
+            let body = self.handle(path, payload);
+            transform::canonicalize(TransformArgs {
+                response: HttpRequestResult { status: 200u64.into(), headers: vec![], body },
+                context: path.as_bytes().to_vec(),
+            })
+            .body
+        }
+    }
+
+    #[test]
+    fn exec_returns_output_and_runs_once() {
+        let mut controller = MockController::default();
+        let payload = command_payload("c1", "session_1-1", "echo hello");
+        // Every replica sends the same request.
+        let first = parse_exec(&controller.call(EXEC_PATH, &payload)).unwrap();
+        let second = parse_exec(&controller.call(EXEC_PATH, &payload)).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(controller.executed, vec!["echo hello"]);
+        assert_eq!((first.stdout.as_str(), first.exit_code), ("hello\n", 0));
+
+        let failed = parse_exec(&controller.call(EXEC_PATH, &command_payload("c1", "session_1-2", "nope"))).unwrap();
+        assert_eq!((failed.stderr.as_str(), failed.exit_code), ("sh: not found\n", 127));
+    }
+
+    #[test]
+    fn job_output_is_read_in_chunks() {
+        let mut controller = MockController::default();
+        let job_id = parse_job_started(&controller.call(JOB_START_PATH, &command_payload("c1", "r1", "cargo build"))).unwrap();
+
+        let mut output = String::new();
+        let mut offset = 0;
+        loop {
+            // A 1031 byte response limit leaves room for 7 bytes of output.
+            let chunk = parse_job_output(&controller.call(JOB_OUTPUT_PATH, &output_payload("c1", &job_id, offset, 1031))).unwrap();
+            output.push_str(&chunk.chunk);
+            offset = chunk.next_offset;
+            if chunk.finished {
+                assert_eq!(chunk.exit_code, Some(0));
+                break;
+            }
+            assert_eq!(chunk.exit_code, None);
+        }
+        assert_eq!(output, "line 1\nline 2\nline 3\n");
+
+        // A replica reading after the job wrote more still gets the first reply.
+        let job_id = parse_job_started(&controller.call(JOB_START_PATH, &command_payload("c1", "r3", "tail -f log"))).unwrap();
+        let read = output_payload("c1", &job_id, 0, 2048);
+        let first = controller.call(JOB_OUTPUT_PATH, &read);
+        controller.jobs.get_mut(&job_id).unwrap().0.push_str("line 4\n");
+        assert_eq!(controller.call(JOB_OUTPUT_PATH, &read), first);
+        let next = parse_job_output(&first).unwrap().next_offset;
+        let rest = controller.call(JOB_OUTPUT_PATH, &output_payload("c1", &job_id, next, 2048));
+        assert_eq!(parse_job_output(&rest).unwrap().chunk, "line 4\n");
+
+        let job_id = parse_job_started(&controller.call(JOB_START_PATH, &command_payload("c1", "r2", "sleep 100"))).unwrap();
+        controller.call(JOB_CANCEL_PATH, &cancel_payload("c1", &job_id));
+        let chunk = parse_job_output(&controller.call(JOB_OUTPUT_PATH, &output_payload("c1", &job_id, 0, 1031))).unwrap();
+        assert!(chunk.finished);
+        assert_eq!(chunk.exit_code, Some(130));
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert!(validate_command("  ").is_err());
+        assert!(validate_command(&"x".repeat(MAX_COMMAND_LEN + 1)).is_err());
+        assert!(validate_command("ls -la").is_ok());
+    }
+}
@@ -0,0 +1,334 @@
+// Project version history.
+//
+// Every save records a revision holding a full copy of the project's file
+// tree; named snapshots are revisions with a name attached. Revisions are
+// pruned according to the project's retention policy after each save.
+
+use crate::error::{ApiError, ApiResult};
+use crate::vfs::{FileKind, FileTree};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::collections::BTreeSet;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// Most unnamed revisions a policy may keep, so history stays bounded.
+pub const MAX_REVISIONS_LIMIT: u32 = 200;
+/// Shortest age limit a policy may set.
+pub const MIN_AGE_DAYS: u32 = 1;
+/// Most named snapshots a policy may keep.
+pub const MAX_SNAPSHOTS_LIMIT: u32 = 20;
+/// Bytes of file content a project's history may hold across all revisions.
+pub const MAX_HISTORY_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RetentionPolicy {
+    /// Maximum number of unnamed revisions to keep.
+    pub max_revisions: u32,
+    /// Drop unnamed revisions older than this many days.
+    pub max_age_days: Option<u32>,
+    /// Named snapshots are never pruned when set.
+    pub keep_snapshots: bool,
+    /// Maximum number of named snapshots; new ones are refused past it.
+    #[serde(default = "default_max_snapshots")]
+    pub max_snapshots: u32,
+}
+
+fn default_max_snapshots() -> u32 {
+    10
+}
+
+impl RetentionPolicy {
+    /// Check the policy's limits before it is stored.
+    pub fn validate(&self) -> ApiResult<()> {
+        if self.max_revisions == 0 || self.max_revisions > MAX_REVISIONS_LIMIT {
+            return Err(ApiError::InvalidInput(format!("max_revisions must be between 1 and {}", MAX_REVISIONS_LIMIT)));
+        }
+        if self.max_age_days.is_some_and(|days| days < MIN_AGE_DAYS) {
+            return Err(ApiError::InvalidInput(format!("max_age_days must be at least {}", MIN_AGE_DAYS)));
+        }
+        if self.max_snapshots > MAX_SNAPSHOTS_LIMIT {
+            return Err(ApiError::InvalidInput(format!("max_snapshots must be at most {}", MAX_SNAPSHOTS_LIMIT)));
+        }
+        Ok(())
+    }
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            max_revisions: 50,
+            max_age_days: None,
+            keep_snapshots: true,
+            max_snapshots: default_max_snapshots(),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone)]
+pub struct Revision {
+    pub id: u64,
+    pub created_at: u64,
+    pub author: Principal,
+    pub message: String,
+    pub snapshot_name: Option<String>,
+    pub files: FileTree,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone)]
+pub struct RevisionSummary {
+    pub id: u64,
+    pub created_at: u64,
+    pub author: Principal,
+    pub message: String,
+    pub snapshot_name: Option<String>,
+    pub file_count: u64,
+    pub size_bytes: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct ProjectHistory {
+    pub next_id: u64,
+    pub revisions: Vec<Revision>,
+    #[serde(default)]
+    pub policy: RetentionPolicy,
+}
+
+impl Revision {
+    pub fn summary(&self) -> RevisionSummary {
+        RevisionSummary {
+            id: self.id,
+            created_at: self.created_at,
+            author: self.author,
+            message: self.message.clone(),
+            snapshot_name: self.snapshot_name.clone(),
+            file_count: self.files.values().filter(|f| f.kind == FileKind::File).count() as u64,
+            size_bytes: self.size_bytes(),
+        }
+    }
+
+    fn size_bytes(&self) -> u64 {
+        tree_bytes(&self.files)
+    }
+}
+
+fn tree_bytes(files: &FileTree) -> u64 {
+    files.values().map(|f| f.content.len() as u64).sum()
+}
+
+fn same_contents(a: &FileTree, b: &FileTree) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b.iter()).all(|((pa, fa), (pb, fb))| {
+            pa == pb && fa.kind == fb.kind && fa.content == fb.content
+        })
+}
+
+impl ProjectHistory {
+    /// Record an automatic revision, skipping saves that changed nothing.
+    pub fn record(&mut self, files: &FileTree, author: Principal, message: &str, now: u64) -> Option<u64> {
+        if self.revisions.last().is_some_and(|last| same_contents(&last.files, files)) {
+            return None;
+        }
+        let id = self.push(files, author, message.to_string(), None, now);
+        self.prune(now);
+        Some(id)
+    }
+
+    /// Record a named snapshot of the current files.
+    pub fn snapshot(&mut self, files: &FileTree, author: Principal, name: &str, now: u64) -> ApiResult<u64> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ApiError::InvalidInput("Snapshot name cannot be empty".to_string()));
+        }
+        if self.revisions.iter().any(|r| r.snapshot_name.as_deref() == Some(name)) {
+            return Err(ApiError::Conflict(format!("Snapshot already exists: {}", name)));
+        }
+        let snapshots: Vec<&Revision> = self.revisions.iter().filter(|r| r.snapshot_name.is_some()).collect();
+        if snapshots.len() >= self.policy.max_snapshots as usize {
+            return Err(ApiError::Conflict(format!("Snapshot limit of {} reached", self.policy.max_snapshots)));
+        }
+        // Snapshots may never be pruned, so they alone must fit the budget.
+        if snapshots.iter().map(|r| r.size_bytes()).sum::<u64>() + tree_bytes(files) > MAX_HISTORY_BYTES {
+            return Err(ApiError::Conflict(format!("Snapshots would exceed the history budget of {} bytes", MAX_HISTORY_BYTES)));
+        }
+        let id = self.push(files, author, format!("Snapshot {}", name), Some(name.to_string()), now);
+        self.prune(now);
+        Ok(id)
+    }
+
+    fn push(&mut self, files: &FileTree, author: Principal, message: String, snapshot_name: Option<String>, now: u64) -> u64 {
+        self.next_id += 1;
+        self.revisions.push(Revision {
+            id: self.next_id,
+            created_at: now,
+            author,
+            message,
+            snapshot_name,
+            files: files.clone(),
+        });
+        self.next_id
+    }
+
+    /// Apply the retention policy, then drop the oldest unprotected revisions
+    /// until the history fits `MAX_HISTORY_BYTES`. The newest revision is
+    /// always kept so the history never loses the current state.
+    pub fn prune(&mut self, now: u64) {
+        let policy = self.policy.clone();
+        let newest = self.revisions.last().map(|r| r.id);
+        let protected = |r: &Revision| Some(r.id) == newest || (policy.keep_snapshots && r.snapshot_name.is_some());
+
+        if let Some(days) = policy.max_age_days {
+            let cutoff = now.saturating_sub(days as u64 * NANOS_PER_DAY);
+            self.revisions.retain(|r| protected(r) || r.created_at >= cutoff);
+        }
+
+        let prunable: Vec<u64> = self.revisions.iter().filter(|r| !protected(r)).map(|r| r.id).collect();
+        // The newest revision counts towards the limit too.
+        let limit = (policy.max_revisions as usize).saturating_sub(1);
+        if prunable.len() > limit {
+            let dropped: BTreeSet<u64> = prunable[..prunable.len() - limit].iter().copied().collect();
+            self.revisions.retain(|r| !dropped.contains(&r.id));
+        }
+
+        let mut total: u64 = self.revisions.iter().map(|r| r.size_bytes()).sum();
+        let mut dropped = BTreeSet::new();
+        for revision in self.revisions.iter().filter(|r| !protected(r)) {
+            if total <= MAX_HISTORY_BYTES {
+                break;
+            }
+            total -= revision.size_bytes();
+            dropped.insert(revision.id);
+        }
+        self.revisions.retain(|r| !dropped.contains(&r.id));
+    }
+
+    pub fn get(&self, revision_id: u64) -> ApiResult<&Revision> {
+        self.revisions
+            .iter()
+            .find(|r| r.id == revision_id)
+            .ok_or_else(|| ApiError::NotFound(format!("Revision not found: {}", revision_id)))
+    }
+}
+
+/// Unified diff between two file trees, one section per changed file.
+pub fn unified_diff(from: &FileTree, from_label: &str, to: &FileTree, to_label: &str) -> String {
+    let paths: BTreeSet<&String> = from
+        .iter()
+        .chain(to.iter())
+        .filter(|(_, f)| f.kind == FileKind::File)
+        .map(|(path, _)| path)
+        .collect();
+
+    let mut out = String::new();
+    for path in paths {
+        let old = from.get(path).filter(|f| f.kind == FileKind::File);
+        let new = to.get(path).filter(|f| f.kind == FileKind::File);
+        let old_text = old.map(|f| f.content.as_str()).unwrap_or("");
+        let new_text = new.map(|f| f.content.as_str()).unwrap_or("");
+        if old.is_some() && new.is_some() && old_text == new_text {
+            continue;
+        }
+        let old_header = match old {
+            Some(_) => format!("a/{} ({})", path, from_label),
+            None => "/dev/null".to_string(),
+        };
+        let new_header = match new {
+            Some(_) => format!("b/{} ({})", path, to_label),
+            None => "/dev/null".to_string(),
+        };
+        out.push_str(&format!("diff --icpad a/{} b/{}\n", path, path));
+        out.push_str(
+            &TextDiff::from_lines(old_text, new_text)
+                .unified_diff()
+                .context_radius(3)
+                .header(&old_header, &new_header)
+                .to_string(),
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs;
+
+    fn tree(code: &str) -> FileTree {
+        vfs::single_file_tree("rust", code.to_string(), 0)
+    }
+
+    #[test]
+    fn prune_keeps_snapshots_and_newest() {
+        let author = Principal::anonymous();
+        let mut history = ProjectHistory {
+            policy: RetentionPolicy { max_revisions: 2, ..Default::default() },
+            ..Default::default()
+        };
+        history.record(&tree("a"), author, "save", 1);
+        history.snapshot(&tree("a"), author, "v1", 2).unwrap();
+        for (i, code) in ["b", "c", "d"].iter().enumerate() {
+            history.record(&tree(code), author, "save", 3 + i as u64);
+        }
+        // Saving identical contents is a no-op.
+        assert_eq!(history.record(&tree("d"), author, "save", 9), None);
+
+        let kept: Vec<u64> = history.revisions.iter().map(|r| r.id).collect();
+        assert_eq!(kept, vec![2, 4, 5]);
+    }
+
+    #[test]
+    fn policy_limits() {
+        assert_eq!(RetentionPolicy::default().validate(), Ok(()));
+        let policy = |max_revisions, max_age_days| RetentionPolicy { max_revisions, max_age_days, ..Default::default() };
+        assert_eq!(policy(MAX_REVISIONS_LIMIT, Some(MIN_AGE_DAYS)).validate(), Ok(()));
+        assert!(policy(0, None).validate().is_err());
+        assert!(policy(u32::MAX, None).validate().is_err());
+        assert!(policy(10, Some(0)).validate().is_err());
+        let snapshots = RetentionPolicy { max_snapshots: MAX_SNAPSHOTS_LIMIT + 1, ..Default::default() };
+        assert!(snapshots.validate().is_err());
+    }
+
+    #[test]
+    fn snapshots_and_bytes_are_bounded() {
+        let author = Principal::anonymous();
+        let mut history = ProjectHistory {
+            policy: RetentionPolicy { max_snapshots: 2, ..Default::default() },
+            ..Default::default()
+        };
+        history.snapshot(&tree("a"), author, "v1", 1).unwrap();
+        history.snapshot(&tree("b"), author, "v2", 2).unwrap();
+        assert!(matches!(history.snapshot(&tree("c"), author, "v3", 3), Err(ApiError::Conflict(_))));
+
+        // Unnamed revisions give way, oldest first, once the history is over budget.
+        // Built past the project budget to reach the history budget quickly.
+        let sized = |c: &str, len: usize| {
+            let mut files = tree("");
+            files.values_mut().filter(|f| f.kind == FileKind::File).for_each(|f| f.content = c.repeat(len));
+            files
+        };
+        let big = |c: &str| sized(c, MAX_HISTORY_BYTES as usize / 3);
+        let mut history = ProjectHistory::default();
+        for (i, code) in ["a", "b", "c", "d"].iter().enumerate() {
+            history.record(&big(code), author, "save", i as u64);
+        }
+        let kept: Vec<u64> = history.revisions.iter().map(|r| r.id).collect();
+        assert_eq!(kept, vec![2, 3, 4]);
+        assert!(history.revisions.iter().map(|r| r.size_bytes()).sum::<u64>() <= MAX_HISTORY_BYTES);
+        assert!(matches!(
+            history.snapshot(&sized("x", MAX_HISTORY_BYTES as usize + 1), author, "huge", 9),
+            Err(ApiError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn unified_diff_per_file() {
+        let from = tree("fn a() {}\nfn b() {}\n");
+        let mut to = tree("fn a() {}\nfn c() {}\n");
+        vfs::create_file(&mut to, "README.md", "hi\n".to_string(), 0).unwrap();
+
+        let diff = unified_diff(&from, "revision 1", &to, "revision 2");
+        assert!(diff.contains("--- a/src/lib.rs (revision 1)\n+++ b/src/lib.rs (revision 2)\n"));
+        assert!(diff.contains("-fn b() {}\n+fn c() {}\n"));
+        assert!(diff.contains("--- /dev/null\n+++ b/README.md (revision 2)\n"));
+    }
+}
@@ -0,0 +1,302 @@
+// Language backends.
+//
+// Everything the backend does differently per language sits behind
+// `LanguageBackend`: checking sources, building, generating Candid, starter
+// code and running tests. Adding a language (e.g. TypeScript with Azle or Python with
+// Kybra) means implementing the trait and adding it to `BACKENDS`; endpoints
+// only ever look backends up by their normalized id.
+
+use crate::diagnostics::{self, Diagnostic};
+use crate::error::{ApiError, ApiResult};
+use crate::test_suite::{self, SuiteResult, TestCase};
+use crate::vfs::{self, FileTree, ProjectFile};
+use crate::{candid_gen, lint, rust_check, CompileResult};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+
+/// The future `run_tests` returns; boxed so backends stay usable as trait objects.
+pub type TestRun<'a> = Pin<Box<dyn Future<Output = ApiResult<SuiteResult>> + 'a>>;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct LanguageInfo {
+    /// Normalized id stored on projects, e.g. `rust`.
+    pub id: String,
+    pub name: String,
+    /// Other spellings accepted for `id`, e.g. `rs`.
+    pub aliases: Vec<String>,
+    pub extensions: Vec<String>,
+    /// File single-file editors read and write.
+    pub entry_path: String,
+    pub generates_candid: bool,
+}
+
+pub trait LanguageBackend: Sync {
+    fn id(&self) -> &'static str;
+    fn name(&self) -> &'static str;
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+    fn extensions(&self) -> &'static [&'static str];
+    fn entry_path(&self) -> &'static str;
+
+    /// Starter code for a new project.
+    fn scaffold(&self) -> &'static str;
+
+    /// Check the project's sources in this language.
+    fn validate(&self, sources: &[&ProjectFile]) -> Vec<Diagnostic>;
+
+    fn generates_candid(&self) -> bool {
+        false
+    }
+
+    /// The service definition of sources that passed `validate`, if the
+    /// language can produce one without a build.
+    fn generate_candid(&self, _sources: &[&ProjectFile]) -> (Option<String>, Vec<Diagnostic>) {
+        (None, vec![])
+    }
+
+    fn compile(&self, files: &FileTree) -> CompileResult {
+        check(self, files)
+    }
+
+    /// Run `cases` against the project's deployed canister, whose interface is `did`.
+    fn run_tests<'a>(&'a self, _canister_id: Principal, _did: &'a str, _cases: &'a [&'a TestCase]) -> TestRun<'a> {
+        let name = self.name();
+        Box::pin(async move { Err(ApiError::InvalidInput(format!("{} projects have no test runner", name))) })
+    }
+
+    fn info(&self) -> LanguageInfo {
+        LanguageInfo {
+            id: self.id().to_string(),
+            name: self.name().to_string(),
+            aliases: self.aliases().iter().map(|a| a.to_string()).collect(),
+            extensions: self.extensions().iter().map(|e| e.to_string()).collect(),
+            entry_path: self.entry_path().to_string(),
+            generates_candid: self.generates_candid(),
+        }
+    }
+}
+
+/// Validate the sources and, when they have no errors, generate Candid.
+/// No WASM is produced in the canister.
+fn check<B: LanguageBackend + ?Sized>(backend: &B, files: &FileTree) -> CompileResult {
+    let name = backend.name();
+    let sources: Vec<&ProjectFile> = vfs::files_with_language(files, backend.id()).collect();
+    if sources.is_empty() {
+        return CompileResult {
+            success: false,
+            output: format!("{} check failed! No {} source files found in project", name, name),
+            diagnostics: vec![],
+            wasm: None,
+            candid: None,
+        };
+    }
+
+    let mut diagnostics = backend.validate(&sources);
+    let mut candid = None;
+    if diagnostics::error_count(&diagnostics) == 0 {
+        let (did, warnings) = backend.generate_candid(&sources);
+        diagnostics.extend(warnings);
+        candid = did;
+    }
+    let errors = diagnostics::error_count(&diagnostics);
+    let warnings = diagnostics.len() - errors;
+    CompileResult {
+        success: errors == 0,
+        output: if errors == 0 {
+            format!("{} check successful! Checked {} file(s), {} warning(s).", name, sources.len(), warnings)
+        } else {
+            format!("{} check failed! {} error(s), {} warning(s).", name, errors, warnings)
+        },
+        diagnostics,
+        wasm: None,
+        candid: candid.filter(|_| errors == 0),
+    }
+}
+
+pub struct Rust;
+
+impl LanguageBackend for Rust {
+    fn id(&self) -> &'static str {
+        "rust"
+    }
+
+    fn name(&self) -> &'static str {
+        "Rust"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["rs"]
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["rs"]
+    }
+
+    fn entry_path(&self) -> &'static str {
+        "src/lib.rs"
+    }
+
+    fn scaffold(&self) -> &'static str {
+        "use ic_cdk::query;\n\n\
+         #[query]\n\
+         fn greet(name: String) -> String {\n    format!(\"Hello, {}! Welcome to ICPad!\", name)\n}\n\n\
+         ic_cdk::export_candid!();\n"
+    }
+
+    fn validate(&self, sources: &[&ProjectFile]) -> Vec<Diagnostic> {
+        rust_check::check(sources)
+    }
+
+    fn generates_candid(&self) -> bool {
+        true
+    }
+
+    fn generate_candid(&self, sources: &[&ProjectFile]) -> (Option<String>, Vec<Diagnostic>) {
+        candid_gen::generate(sources)
+    }
+
+    fn run_tests<'a>(&'a self, canister_id: Principal, did: &'a str, cases: &'a [&'a TestCase]) -> TestRun<'a> {
+        Box::pin(async move { Ok(test_suite::run(canister_id, did, cases).await) })
+    }
+}
+
+pub struct Motoko;
+
+impl LanguageBackend for Motoko {
+    fn id(&self) -> &'static str {
+        "motoko"
+    }
+
+    fn name(&self) -> &'static str {
+        "Motoko"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["mo"]
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["mo"]
+    }
+
+    fn entry_path(&self) -> &'static str {
+        "main.mo"
+    }
+
+    fn scaffold(&self) -> &'static str {
+        "actor {\n  public query func greet(name : Text) : async Text {\n    \"Hello, \" # name # \"! Welcome to ICPad!\"\n  };\n};\n"
+    }
+
+    fn validate(&self, sources: &[&ProjectFile]) -> Vec<Diagnostic> {
+        lint::check_motoko(sources)
+    }
+
+    /// Motoko is built by the frontend compiler.
+    fn compile(&self, files: &FileTree) -> CompileResult {
+        let mut result = check(self, files);
+        if result.success {
+            result.output.push_str(" Use the Compile button in the IDE to build the WASM.");
+        }
+        result
+    }
+
+    fn run_tests<'a>(&'a self, canister_id: Principal, did: &'a str, cases: &'a [&'a TestCase]) -> TestRun<'a> {
+        Box::pin(async move { Ok(test_suite::run(canister_id, did, cases).await) })
+    }
+}
+
+pub struct JavaScript;
+
+impl LanguageBackend for JavaScript {
+    fn id(&self) -> &'static str {
+        "javascript"
+    }
+
+    fn name(&self) -> &'static str {
+        "JavaScript"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["js", "node"]
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["js", "mjs"]
+    }
+
+    fn entry_path(&self) -> &'static str {
+        "index.js"
+    }
+
+    fn scaffold(&self) -> &'static str {
+        "export function greet(name) {\n  return `Hello, ${name}! Welcome to ICPad!`;\n}\n"
+    }
+
+    fn validate(&self, sources: &[&ProjectFile]) -> Vec<Diagnostic> {
+        lint::check_javascript(sources)
+    }
+}
+
+static BACKENDS: &[&dyn LanguageBackend] = &[&Rust, &Motoko, &JavaScript];
+
+/// The normalized id for `language`, accepting any case and the aliases.
+pub fn normalize(language: &str) -> Option<&'static str> {
+    let language = language.trim().to_ascii_lowercase();
+    BACKENDS
+        .iter()
+        .find(|b| b.id() == language || b.aliases().contains(&language.as_str()))
+        .map(|b| b.id())
+}
+
+pub fn backend(language: &str) -> ApiResult<&'static dyn LanguageBackend> {
+    let id = normalize(language).ok_or_else(|| {
+        let supported: Vec<_> = BACKENDS.iter().map(|b| b.id()).collect();
+        ApiError::InvalidInput(format!("Unsupported language: {} (supported: {})", language, supported.join(", ")))
+    })?;
+    Ok(*BACKENDS.iter().find(|b| b.id() == id).expect("normalize returns registered ids"))
+}
+
+pub fn supported() -> Vec<LanguageInfo> {
+    BACKENDS.iter().map(|b| b.info()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_language_ids() {
+        assert_eq!(normalize(" Rust "), Some("rust"));
+        assert_eq!(normalize("js"), Some("javascript"));
+        assert_eq!(normalize("MO"), Some("motoko"));
+        assert_eq!(normalize("cobol"), None);
+        assert!(backend("python").is_err());
+    }
+
+    #[test]
+    fn scaffolds_pass_their_own_checks() {
+        for backend in BACKENDS {
+            let files = vfs::single_file_tree(backend.id(), backend.scaffold().to_string(), 0);
+            let result = backend.compile(&files);
+            assert!(result.success, "{}: {}", backend.id(), diagnostics::render(&result.diagnostics));
+            assert!(result.diagnostics.is_empty(), "{}", diagnostics::render(&result.diagnostics));
+            assert_eq!(result.candid.is_some(), backend.info().generates_candid);
+        }
+    }
+
+    #[test]
+    fn languages_without_a_runner_reject_tests() {
+        let mut run = JavaScript.run_tests(Principal::anonymous(), "service : {}", &[]);
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match run.as_mut().poll(&mut context) {
+            std::task::Poll::Ready(result) => assert_eq!(
+                result.unwrap_err(),
+                ApiError::InvalidInput("JavaScript projects have no test runner".to_string())
+            ),
+            std::task::Poll::Pending => panic!("test run did not complete"),
+        }
+    }
+}
@@ -1,29 +1,72 @@
-use candid::CandidType;
+#![allow(non_snake_case)]
+
+mod candid_call;
+mod candid_gen;
+mod commands;
+mod config;
+mod controller;
+mod deploy;
+mod dfx;
+mod diagnostics;
+mod env;
+mod error;
+mod exec;
+mod history;
+mod languages;
+mod lint;
+mod profiles;
+mod rust_check;
+mod sessions;
+mod shell;
+mod state;
+mod sync;
+mod test_suite;
+mod transform;
+mod vfs;
+
+use candid::{CandidType, Principal};
+use error::{ApiError, ApiResult};
 use ic_cdk::update;
 use ic_cdk::query;
-use ic_cdk::management_canister::http_request;
+use ic_cdk::{init, post_upgrade, pre_upgrade};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap};
 use std::time::Duration;
-use ic_cdk_timers::set_timer;
-use num_traits::cast::ToPrimitive;
 
 #[derive(CandidType, Deserialize, Serialize, Clone)]
 pub struct Project {
     pub id: String,
     pub name: String,
     pub language: String,
-    pub code: String,
+    pub files: vfs::FileTree,
     pub created_at: u64,
     pub updated_at: u64,
     pub deployed: bool,
-    pub canister_id: Option<String>,
+    pub canister_id: Option<Principal>,
+    // Candid interface supplied with the last successful deploy
+    #[serde(default)]
+    pub candid: Option<String>,
+    // Candid interface generated by the last successful check of a Rust project
+    #[serde(default)]
+    pub generated_candid: Option<String>,
+    // Test cases run against the deployed canister by `test`
+    #[serde(default)]
+    pub tests: Vec<test_suite::TestCase>,
+    // Projects saved before ownership existed have no owner and stay unreachable
+    #[serde(default = "Principal::anonymous")]
+    pub owner: Principal,
 }
 
-// ✅ Define your ngrok/off-chain controller URL once here
-const DOCKER_CONTROLLER_URL: &str = "https://fdbe92cd247b.ngrok-free.app";
+impl Project {
+    /// The project's Candid interface: the deployed `.did` if there is one,
+    /// otherwise the one generated from its sources.
+    pub fn interface(&self) -> Option<&str> {
+        self.candid.as_deref().or(self.generated_candid.as_deref())
+    }
+}
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Serialize)]
 struct SessionResponse {
     container_id: String,
     editor_url: String,
@@ -33,7 +76,7 @@ struct SessionResponse {
 pub struct CompileResult {
     pub success: bool,
     pub output: String,
-    pub errors: Vec<String>,
+    pub diagnostics: Vec<diagnostics::Diagnostic>,
     pub wasm: Option<Vec<u8>>,
     pub candid: Option<String>,
 }
@@ -41,7 +84,7 @@ pub struct CompileResult {
 #[derive(CandidType, Deserialize, Serialize)]
 pub struct DeployResult {
     pub success: bool,
-    pub canister_id: Option<String>,
+    pub canister_id: Option<Principal>,
     pub url: Option<String>,
     pub output: String,
 }
@@ -56,456 +99,1249 @@ pub struct TestResult {
 // Storage
 thread_local! {
     static PROJECTS: std::cell::RefCell<HashMap<String, Project>> = std::cell::RefCell::new(HashMap::new());
-    static SESSIONS: std::cell::RefCell<HashMap<String, String>> = std::cell::RefCell::new(HashMap::new());
-}
-
-#[update]
-async fn start_docker_session(user_id: String) -> Result<String, String> {
-    let payload = format!(
-        r#"{{"project_id":"test_project","user_id":"{}"}}"#,
-        user_id
-    );
-
-  let req = ic_cdk::management_canister::HttpRequestArgs {
-        url: "https://e2bd84efdf04.ngrok-free.app/start".to_string(),
-  max_response_bytes: Some(2000),
-        method: ic_cdk::management_canister::HttpMethod::POST,
-        headers: vec![ic_cdk::management_canister::HttpHeader {
-            name: "Content-Type".to_string(),
-            value: "application/json".to_string(),
-        }],
-        body: Some(payload.into_bytes()),
-        transform: None,
-    };
+    static SESSIONS: std::cell::RefCell<sessions::SessionRegistry> = std::cell::RefCell::new(sessions::SessionRegistry::default());
+    static HISTORY: std::cell::RefCell<HashMap<String, history::ProjectHistory>> = std::cell::RefCell::new(HashMap::new());
+    static SHELLS: std::cell::RefCell<HashMap<Principal, shell::ShellSession>> = std::cell::RefCell::new(HashMap::new());
+    // Projects whose canister is being created, with their owner
+    static PENDING_CANISTERS: std::cell::RefCell<HashMap<String, Principal>> = std::cell::RefCell::new(HashMap::new());
+    // When each owner last started creating a canister
+    static CANISTER_CREATED_AT: std::cell::RefCell<HashMap<Principal, u64>> = std::cell::RefCell::new(HashMap::new());
+    static NEXT_PROJECT_ID: std::cell::Cell<u64> = const { std::cell::Cell::new(1) };
+}
 
-    match http_request(&req).await {
-        Ok(ic_cdk::management_canister::HttpRequestResult { status, body, .. }) => {
-            if status.0.to_u64().unwrap_or(0) != 200 {
-                return Err(format!("Non-200 status code: {}", status));
-            }
+fn next_project_id() -> String {
+    let id = NEXT_PROJECT_ID.with(|next| next.replace(next.get() + 1));
+    format!("proj_{}", id)
+}
 
-            let body_str = String::from_utf8_lossy(&body);
-            let session: SessionResponse =
-                serde_json::from_str(&body_str).map_err(|e| format!("JSON parse error: {}", e))?;
+// Access control
+fn authenticated_caller() -> ApiResult<Principal> {
+    let caller = env::caller();
+    if caller == Principal::anonymous() {
+        return Err(ApiError::Unauthorized("anonymous callers cannot access projects, please sign in".to_string()));
+    }
+    Ok(caller)
+}
 
-            let container_id = session.container_id.clone();
+fn check_owner(project: &Project, caller: Principal) -> ApiResult<()> {
+    if project.owner != caller {
+        return Err(ApiError::Unauthorized(format!("project {} belongs to another user", project.id)));
+    }
+    Ok(())
+}
 
-            // Store session
-            SESSIONS.with(|sessions| {
-                sessions.borrow_mut().insert(user_id.clone(), container_id.clone());
-            });
+fn project_not_found() -> ApiError {
+    ApiError::NotFound("Project not found".to_string())
+}
 
-            // Schedule auto-stop after 5 minutes
-            set_timer(Duration::from_secs(5 * 60), move || {
-                ic_cdk::spawn(async move {
-                    let _ = stop_docker_session(container_id.clone()).await;
-                });
-            });
+/// Fetch a project, failing unless the caller owns it.
+fn get_owned_project(project_id: &str) -> ApiResult<Project> {
+    let caller = authenticated_caller()?;
+    let project = PROJECTS.with(|projects| projects.borrow().get(project_id).cloned())
+        .ok_or_else(project_not_found)?;
+    check_owner(&project, caller)?;
+    Ok(project)
+}
 
-            Ok(session.editor_url)
+/// Run `f` against a project the caller owns, bumping `updated_at` and
+/// recording a revision described by `message` on success.
+fn with_owned_project_mut<T>(
+    project_id: &str,
+    message: &str,
+    f: impl FnOnce(&mut Project, u64) -> ApiResult<T>,
+) -> ApiResult<T> {
+    let caller = authenticated_caller()?;
+    let now = env::time();
+    let (result, files) = PROJECTS.with(|projects| {
+        let mut projects = projects.borrow_mut();
+        let project = projects.get_mut(project_id).ok_or_else(project_not_found)?;
+        check_owner(project, caller)?;
+        let result = f(project, now)?;
+        project.updated_at = now;
+        Ok::<_, ApiError>((result, project.files.clone()))
+    })?;
+    record_revision(project_id, &files, caller, message, now);
+    Ok(result)
+}
+
+fn record_revision(project_id: &str, files: &vfs::FileTree, author: Principal, message: &str, now: u64) {
+    HISTORY.with(|history| {
+        history.borrow_mut()
+            .entry(project_id.to_string())
+            .or_default()
+            .record(files, author, message, now);
+    });
+}
+
+fn with_history<T>(project_id: &str, f: impl FnOnce(&mut history::ProjectHistory) -> ApiResult<T>) -> ApiResult<T> {
+    HISTORY.with(|history| f(history.borrow_mut().entry(project_id.to_string()).or_default()))
+}
+
+/// The project's canister, created on first use and recorded right away so a
+/// failed install never leaks it.
+async fn ensure_project_canister(project: &Project) -> ApiResult<Principal> {
+    // `project` may predate an earlier await, so look at the live record.
+    let (current, owned, total) = PROJECTS.with(|projects| {
+        let projects = projects.borrow();
+        let current = projects.get(&project.id).and_then(|p| p.canister_id);
+        let owned = projects.values().filter(|p| p.owner == project.owner && p.canister_id.is_some()).count();
+        let total = projects.values().filter(|p| p.canister_id.is_some()).count();
+        (current, owned, total)
+    });
+    if let Some(canister_id) = current {
+        return Ok(canister_id);
+    }
+    // Claim the creation before awaiting so a concurrent deploy of the same
+    // project fails instead of creating a second canister.
+    PENDING_CANISTERS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        if pending.contains_key(&project.id) {
+            return Err(ApiError::Conflict("A canister is already being created for this project, try again shortly".to_string()));
         }
-        Err(err) => Err(format!("HTTP call failed: {:?}", err)),
+        let creating = pending.values().filter(|owner| **owner == project.owner).count();
+        deploy::check_canister_quota(owned + creating)?;
+        deploy::check_canister_budget(total + pending.len())?;
+        let now = env::time();
+        CANISTER_CREATED_AT.with(|created| {
+            let mut created = created.borrow_mut();
+            deploy::check_creation_rate(created.get(&project.owner).copied(), now)?;
+            created.insert(project.owner, now);
+            Ok::<_, ApiError>(())
+        })?;
+        pending.insert(project.id.clone(), project.owner);
+        Ok(())
+    })?;
+    let created = deploy::create_project_canister(project.owner).await;
+    PENDING_CANISTERS.with(|pending| pending.borrow_mut().remove(&project.id));
+    let canister_id = created?;
+    PROJECTS.with(|projects| {
+        if let Some(p) = projects.borrow_mut().get_mut(&project.id) {
+            p.canister_id = Some(canister_id);
+        }
+    });
+    Ok(canister_id)
+}
+
+fn projects_owned_by(owner: Principal) -> Vec<Project> {
+    PROJECTS.with(|projects| {
+        projects.borrow().values()
+            .filter(|p| p.owner == owner)
+            .cloned()
+            .collect()
+    })
+}
+
+#[init]
+fn init(args: Option<config::InitArgs>) {
+    if let Some(args) = args {
+        config::apply_init_args(args).unwrap_or_else(|e| ic_cdk::trap(e.to_string()));
+    }
+    start_session_reaper();
+    controller::start_health_checks();
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    state::save_to_stable_memory();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    state::load_from_stable_memory();
+    let cleanups = SESSIONS.with(|s| s.borrow_mut().recover_after_upgrade());
+    for (controller_id, start_key) in cleanups {
+        cleanup_start(controller_id, &start_key);
     }
+    start_session_reaper();
+    controller::start_health_checks();
+}
+
+/// Strips headers and normalizes controller responses so all replicas agree.
+#[query(hidden = true)]
+fn transform_controller_response(args: ic_cdk::management_canister::TransformArgs) -> ic_cdk::management_canister::HttpRequestResult {
+    transform::canonicalize(args)
 }
 
+/// Start an editor container with a copy of one of the caller's projects and
+/// return its URL, using the default session profile.
+#[update]
+async fn start_docker_session(project_id: String) -> Result<String, String> {
+    let session = start_session(project_id, None).await?;
+    session.editor_url.ok_or_else(|| {
+        format!("Session {} is still starting, check list_my_sessions for its editor URL", session.id)
+    })
+}
+
+/// Start a session for one of the caller's projects with a named profile
+/// (or the default one), within the caller's session limits. If the
+/// controller is briefly unreachable the session is returned as `Starting`
+/// and the start is retried in the background.
+#[update]
+async fn start_session(project_id: String, profile: Option<String>) -> ApiResult<sessions::Session> {
+    let project = get_owned_project(&project_id)?;
+    let caller = project.owner;
+    let (profile, limits) = config::CONFIG.with(|c| {
+        let config = c.borrow();
+        profiles::resolve(&config.profiles, profile.as_deref()).map(|p| (p, config.limits.clone()))
+    })?;
+    let payload = sync::start_payload(&project.id, caller, &project.files, profile.as_ref())?;
+
+    let (controller, _) = config::controller()?;
+    let timeout = controller.session_timeout_secs;
+    let now = env::time();
+    // Quota check, placement and registration happen before the first await
+    // so concurrent starts cannot both pass the limit.
+    let (session_id, endpoint_id) = SESSIONS.with(|s| {
+        let mut registry = s.borrow_mut();
+        let remaining = registry.quota_for_new_session(caller, &limits, now)?;
+        let lifetime = profile.as_ref().map_or(remaining, |p| p.max_lifetime_secs.min(remaining));
+        let endpoint = controller::place(&registry.load_by_controller())?;
+        let session_id = registry.begin(caller, now, timeout);
+        if let Ok(session) = registry.get_mut(caller, &session_id) {
+            session.controller_id = Some(endpoint.id.clone());
+            session.project_id = Some(project.id.clone());
+            session.profile = profile.as_ref().map(|p| p.name.clone());
+            session.deadline = Some(sessions::expiry(now, lifetime));
+        }
+        registry.set_sync_base(&session_id, sync::hashes(&project.files));
+        Ok::<_, ApiError>((session_id, endpoint.id))
+    })?;
+
+    // Retries must not start a second container for the same session.
+    let key = sessions::start_key(&session_id, now);
+    match controller::call(&endpoint_id, controller::START_PATH, &payload, Some(&key)).await {
+        Err(failure) if failure.retryable => {
+            let session = SESSIONS.with(|s| {
+                let mut registry = s.borrow_mut();
+                let session = registry.get_mut(caller, &session_id)?;
+                session.error = Some(format!("{}, retrying", failure.error));
+                Ok::<_, ApiError>(session.clone())
+            })?;
+            let session_id = session_id.clone();
+            controller::retry_in_background(
+                endpoint_id,
+                controller::START_PATH.to_string(),
+                payload,
+                Some(key),
+                Box::new(move |result| {
+                    let _ = finish_start(caller, &session_id, timeout, result.map_err(ApiError::from));
+                }),
+            );
+            Ok(session)
+        }
+        result => finish_start(caller, &session_id, timeout, result.map_err(ApiError::from)),
+    }
+}
+
+/// Record the controller's answer to `/start` on the session.
+fn finish_start(owner: Principal, session_id: &str, timeout: u64, result: ApiResult<Vec<u8>>) -> ApiResult<sessions::Session> {
+    let started = result.and_then(|body| {
+        serde_json::from_slice::<SessionResponse>(&body)
+            .map_err(|e| ApiError::External(format!("JSON parse error: {}", e)))
+    });
+    SESSIONS.with(|s| {
+        let mut registry = s.borrow_mut();
+        let session = registry.get_mut(owner, session_id)?;
+        match started {
+            Ok(response) => {
+                let now = env::time();
+                session.container_id = Some(response.container_id);
+                session.editor_url = Some(response.editor_url);
+                session.state = sessions::SessionState::Running;
+                session.last_heartbeat = now;
+                session.expires_at = sessions::expiry(now, timeout).min(session.deadline.unwrap_or(u64::MAX));
+                session.error = None;
+                Ok(session.clone())
+            }
+            Err(e) => {
+                session.state = sessions::SessionState::Failed;
+                session.error = Some(e.to_string());
+                // The controller may have started the container anyway.
+                cleanup_start(session.controller(), &sessions::start_key(&session.id, session.created_at));
+                Err(e)
+            }
+        }
+    })
+}
+
+/// Stop whatever container the `/start` sent with `start_key` created, for
+/// starts whose outcome is unknown. A controller that never started one
+/// answers with an error, which is ignored.
+fn cleanup_start(controller_id: String, start_key: &str) {
+    let payload = serde_json::json!({ "start_key": start_key }).to_string();
+    let key = format!("stop:{}", start_key);
+    controller::retry_in_background(controller_id, controller::STOP_PATH.to_string(), payload, Some(key), Box::new(|_| {}));
+}
+
+/// Stop one of the caller's sessions, addressed by session or container id.
 #[update]
 async fn stop_docker_session(container_id: String) -> Result<String, String> {
-    let payload = format!(r#"{{"container_id":"{}"}}"#, container_id);
-
-    let req = ic_cdk::management_canister::HttpRequestArgs {
-        url: "https://e2bd84efdf04.ngrok-free.app/stop".to_string(),
-        max_response_bytes: Some(2000),
-        method: ic_cdk::management_canister::HttpMethod::POST,
-        headers: vec![ic_cdk::management_canister::HttpHeader {
-            name: "Content-Type".to_string(),
-            value: "application/json".to_string(),
-        }],
-        body: Some(payload.into_bytes()),
-        transform: None,
-    };
+    let caller = authenticated_caller()?;
+    Ok(stop_session(caller, &container_id).await?)
+}
+
+/// Ask the controller to stop a running session. Shared by
+/// `stop_docker_session` and the reaper. Transient failures keep the session
+/// `Stopping` while the stop is retried in the background; if that fails too
+/// the session goes back to `Running` and the reaper tries again later. A
+/// stop the controller refuses outright (e.g. an unknown container) is not
+/// retried: the session is marked `Failed`.
+async fn stop_session(owner: Principal, key: &str) -> ApiResult<String> {
+    let target = SESSIONS.with(|s| {
+        let mut registry = s.borrow_mut();
+        let target = registry.running_container(owner, key)?;
+        if let Ok(session) = registry.get_mut(owner, &target.session_id) {
+            session.state = sessions::SessionState::Stopping;
+        }
+        Ok::<_, ApiError>(target)
+    })?;
 
+    let sessions::Target { session_id, container_id, controller_id } = target;
+    let payload = serde_json::json!({ "container_id": container_id }).to_string();
+    let key = format!("stop:{}", container_id);
+    match controller::call(&controller_id, controller::STOP_PATH, &payload, Some(&key)).await {
+        Err(failure) if failure.retryable => {
+            controller::retry_in_background(
+                controller_id,
+                controller::STOP_PATH.to_string(),
+                payload,
+                Some(key),
+                Box::new(move |result| finish_stop(owner, &session_id, result.map(|_| ()))),
+            );
+            Ok(format!("{}; the stop will be retried", failure.error))
+        }
+        result => {
+            let result = result.map(|_| ());
+            finish_stop(owner, &session_id, result.clone());
+            result.map(|_| "Container stopped successfully".to_string()).map_err(ApiError::from)
+        }
+    }
+}
 
-    match http_request(&req).await {
-        Ok(ic_cdk::management_canister::HttpRequestResult { status, body: _, .. }) => {
-            if status.0.to_u64().unwrap_or(0) != 200 {
-                return Err(format!("Non-200 status code: {}", status));
+fn finish_stop(owner: Principal, session_id: &str, result: Result<(), controller::Failure>) {
+    SESSIONS.with(|s| {
+        let mut registry = s.borrow_mut();
+        let settled = match &result {
+            Ok(()) => Some(sessions::SessionState::Stopped),
+            Err(failure) if !failure.retryable => Some(sessions::SessionState::Failed),
+            Err(_) => None,
+        };
+        if settled.is_some() {
+            registry.forget_sync_base(session_id);
+        }
+        if let Ok(session) = registry.get_mut(owner, session_id) {
+            session.error = result.err().map(|failure| failure.error.to_string());
+            match settled {
+                Some(state) => {
+                    session.state = state;
+                    let started_at = session.created_at;
+                    registry.charge(owner, started_at, env::time());
+                }
+                None => session.state = sessions::SessionState::Running,
             }
-            Ok("Container stopped successfully".to_string())
         }
-        Err(err) => Err(format!("HTTP call failed: {:?}", err)),
+    });
+}
+
+/// Sweep right away, then every `REAPER_INTERVAL_SECS`. Timers do not survive
+/// upgrades, so this runs from both `init` and `post_upgrade`; the first sweep
+/// stops sessions whose deadline passed while the canister was upgrading.
+fn start_session_reaper() {
+    env::set_timer(Duration::ZERO, || {
+        ic_cdk::futures::spawn_017_compat(reap_expired_sessions());
+    });
+    env::set_timer_interval(Duration::from_secs(sessions::REAPER_INTERVAL_SECS), || {
+        ic_cdk::futures::spawn_017_compat(reap_expired_sessions());
+    });
+}
+
+async fn reap_expired_sessions() {
+    let expired = SESSIONS.with(|s| s.borrow().expired(env::time()));
+    for (owner, session_id) in expired {
+        let _ = stop_session(owner, &session_id).await;
+    }
+}
+
+/// Keep a session alive for another session timeout.
+#[update]
+fn heartbeat_session(session_id: String) -> ApiResult<sessions::Session> {
+    let caller = authenticated_caller()?;
+    let (controller, _) = config::controller()?;
+    SESSIONS.with(|s| {
+        s.borrow_mut()
+            .heartbeat(caller, &session_id, env::time(), controller.session_timeout_secs)
+    })
+}
+
+/// Circuit breaker state and session count of every Docker controller.
+#[query]
+fn get_controller_health() -> Vec<controller::ControllerHealth> {
+    let load = SESSIONS.with(|s| s.borrow().load_by_controller());
+    controller::health(&load)
+}
+
+#[query]
+fn list_session_profiles() -> Vec<profiles::SessionProfile> {
+    config::CONFIG.with(|c| c.borrow().profiles.clone())
+}
+
+/// The caller's active sessions and session-minutes left today.
+#[query]
+fn get_my_session_usage() -> ApiResult<profiles::UsageSummary> {
+    let caller = authenticated_caller()?;
+    let limits = config::CONFIG.with(|c| c.borrow().limits.clone());
+    let now = env::time();
+    Ok(SESSIONS.with(|s| {
+        let registry = s.borrow();
+        profiles::summary(&limits, registry.active_count(caller), registry.seconds_used_today(caller, now))
+    }))
+}
+
+#[query]
+fn list_my_sessions() -> ApiResult<Vec<sessions::Session>> {
+    let caller = authenticated_caller()?;
+    Ok(SESSIONS.with(|s| s.borrow().list(caller)))
+}
+
+/// The caller's running session container and a fresh request id for it.
+fn session_target(session_id: &str) -> ApiResult<(sessions::Target, String)> {
+    let caller = authenticated_caller()?;
+    SESSIONS.with(|s| {
+        let mut registry = s.borrow_mut();
+        let target = registry.running_container(caller, session_id)?;
+        let request_id = registry.request_id(&target.session_id);
+        Ok((target, request_id))
+    })
+}
+
+/// Run a command in a session container and wait for it to finish.
+#[update]
+async fn exec_in_session(session_id: String, command: String) -> ApiResult<exec::ExecResult> {
+    exec::validate_command(&command)?;
+    let (target, request_id) = session_target(&session_id)?;
+    let payload = exec::command_payload(&target.container_id, &request_id, &command);
+    let body = controller::call(&target.controller_id, exec::EXEC_PATH, &payload, Some(&request_id)).await?;
+    exec::parse_exec(&body)
+}
+
+/// Start a long running command and return its job id.
+#[update]
+async fn start_session_job(session_id: String, command: String) -> ApiResult<String> {
+    exec::validate_command(&command)?;
+    let (target, request_id) = session_target(&session_id)?;
+    let payload = exec::command_payload(&target.container_id, &request_id, &command);
+    let body = controller::call(&target.controller_id, exec::JOB_START_PATH, &payload, Some(&request_id)).await?;
+    exec::parse_job_started(&body)
+}
+
+/// Read a job's output from `offset`; pass back `next_offset` until `finished`.
+#[update]
+async fn read_session_job(session_id: String, job_id: String, offset: u64) -> ApiResult<exec::JobOutput> {
+    let (target, _) = session_target(&session_id)?;
+    let (controller, _) = config::controller()?;
+    let payload = exec::output_payload(&target.container_id, &job_id, offset, controller.max_response_bytes);
+    let request_id = exec::output_request_id(&job_id, offset);
+    let body = controller::call(&target.controller_id, exec::JOB_OUTPUT_PATH, &payload, Some(&request_id)).await?;
+    exec::parse_job_output(&body)
+}
+
+/// Copy files changed in the session container back into its project. Files
+/// that were also edited in the project since the last sync are reported as
+/// conflicts and left as they are.
+#[update]
+async fn pull_session_files(session_id: String) -> ApiResult<sync::SyncReport> {
+    let caller = authenticated_caller()?;
+    let (target, project_id, request_id, base) = SESSIONS.with(|s| {
+        let mut registry = s.borrow_mut();
+        let target = registry.running_container(caller, &session_id)?;
+        let project_id = registry
+            .get_mut(caller, &target.session_id)?
+            .project_id
+            .clone()
+            .ok_or_else(|| ApiError::Conflict(format!("Session {} has no project", target.session_id)))?;
+        let request_id = registry.request_id(&target.session_id);
+        let base = registry.sync_base(&target.session_id);
+        Ok::<_, ApiError>((target, project_id, request_id, base))
+    })?;
+
+    let payload = sync::pull_payload(&target.container_id, &request_id);
+    let body = controller::call(&target.controller_id, sync::PULL_PATH, &payload, Some(&request_id)).await?;
+    let remote = sync::parse_pull(&body)?;
+    let (report, next_base) = with_owned_project_mut(&project_id, "Pull files from session", |project, now| {
+        sync::merge(&mut project.files, &base, remote, now)
+    })?;
+    SESSIONS.with(|s| s.borrow_mut().set_sync_base(&target.session_id, next_base));
+    Ok(report)
+}
+
+#[update]
+async fn cancel_session_job(session_id: String, job_id: String) -> ApiResult<()> {
+    let (target, _) = session_target(&session_id)?;
+    let payload = exec::cancel_payload(&target.container_id, &job_id);
+    let request_id = exec::cancel_request_id(&job_id);
+    controller::call(&target.controller_id, exec::JOB_CANCEL_PATH, &payload, Some(&request_id)).await?;
+    Ok(())
+}
+
+// Backend configuration (admin only)
+#[query]
+fn get_controller_config() -> ApiResult<Option<config::ControllerConfig>> {
+    config::require_admin()?;
+    Ok(config::CONFIG.with(|c| c.borrow().controller.clone()))
+}
+
+#[update]
+fn set_controller_config(controller: config::ControllerConfig) -> ApiResult<()> {
+    config::require_admin()?;
+    config::validate_controller_config(&controller)?;
+    config::CONFIG.with(|c| c.borrow_mut().controller = Some(controller));
+    Ok(())
+}
+
+#[query]
+fn list_controller_endpoints() -> ApiResult<Vec<config::ControllerEndpoint>> {
+    config::require_admin()?;
+    Ok(config::CONFIG.with(|c| c.borrow().endpoints.clone()))
+}
+
+/// Add a controller to the pool, or replace the one with the same id.
+#[update]
+fn upsert_controller_endpoint(endpoint: config::ControllerEndpoint) -> ApiResult<()> {
+    config::require_admin()?;
+    config::validate_endpoint(&endpoint)?;
+    config::CONFIG.with(|c| {
+        let endpoints = &mut c.borrow_mut().endpoints;
+        match endpoints.iter_mut().find(|e| e.id == endpoint.id) {
+            Some(existing) => *existing = endpoint,
+            None => endpoints.push(endpoint),
+        }
+    });
+    Ok(())
+}
+
+/// Remove a controller from the pool. Drain it first: endpoints that still
+/// host sessions cannot be removed.
+#[update]
+fn remove_controller_endpoint(id: String) -> ApiResult<()> {
+    config::require_admin()?;
+    let active = SESSIONS.with(|s| s.borrow().load_by_controller().get(&id).copied().unwrap_or(0));
+    if active > 0 {
+        return Err(ApiError::Conflict(format!(
+            "Controller {} still hosts {} session(s), drain it first",
+            id, active
+        )));
     }
+    config::CONFIG.with(|c| {
+        let endpoints = &mut c.borrow_mut().endpoints;
+        let before = endpoints.len();
+        endpoints.retain(|e| e.id != id);
+        if endpoints.len() == before {
+            return Err(ApiError::NotFound(format!("Controller endpoint not found: {}", id)));
+        }
+        Ok(())
+    })
+}
+
+/// Stop (or resume) placing new sessions on a controller. Its running
+/// sessions are unaffected.
+#[update]
+fn set_controller_draining(id: String, draining: bool) -> ApiResult<()> {
+    config::require_admin()?;
+    config::CONFIG.with(|c| {
+        let mut config = c.borrow_mut();
+        let endpoint = config
+            .endpoints
+            .iter_mut()
+            .find(|e| e.id == id)
+            .ok_or_else(|| ApiError::NotFound(format!("Controller endpoint not found: {}", id)))?;
+        endpoint.draining = draining;
+        Ok(())
+    })
+}
+
+#[update]
+fn set_gateway_url(url: String) -> ApiResult<()> {
+    config::require_admin()?;
+    config::validate_gateway_url(&url)?;
+    config::CONFIG.with(|c| c.borrow_mut().gateway_url = Some(url.trim().to_string()));
+    Ok(())
+}
+
+#[update]
+fn set_controller_secret(secret: Vec<u8>) -> ApiResult<()> {
+    config::require_admin()?;
+    config::validate_secret(&secret)?;
+    config::CONFIG.with(|c| c.borrow_mut().controller_secret = Some(secret));
+    Ok(())
+}
+
+/// Replace the controller secret with 32 random bytes and return them once so
+/// they can be installed on the controller.
+#[update]
+async fn rotate_controller_secret() -> ApiResult<Vec<u8>> {
+    config::require_admin()?;
+    let secret = ic_cdk::management_canister::raw_rand()
+        .await
+        .map_err(|e| ApiError::External(format!("raw_rand failed: {}", e)))?;
+    config::CONFIG.with(|c| c.borrow_mut().controller_secret = Some(secret.clone()));
+    Ok(secret)
+}
+
+#[update]
+fn set_session_profiles(profiles: Vec<profiles::SessionProfile>) -> ApiResult<()> {
+    config::require_admin()?;
+    profiles::validate_profiles(&profiles)?;
+    config::CONFIG.with(|c| c.borrow_mut().profiles = profiles);
+    Ok(())
+}
+
+#[update]
+fn set_session_limits(limits: profiles::SessionLimits) -> ApiResult<()> {
+    config::require_admin()?;
+    config::CONFIG.with(|c| c.borrow_mut().limits = limits);
+    Ok(())
+}
+
+#[query]
+fn get_session_limits() -> profiles::SessionLimits {
+    config::CONFIG.with(|c| c.borrow().limits.clone())
+}
+
+#[update]
+fn set_admins(admins: Vec<Principal>) -> ApiResult<()> {
+    if !ic_cdk::api::is_controller(&ic_cdk::api::msg_caller()) {
+        return Err(ApiError::Unauthorized("only canister controllers can change admins".to_string()));
+    }
+    config::CONFIG.with(|c| c.borrow_mut().admins = admins);
+    Ok(())
 }
 
 // Project Management
 #[update]
 pub async fn create_project(name: String, language: String, initial_code: String) -> Result<String, String> {
-    let project_id = format!("proj_{}", ic_cdk::api::time());
-    let now = ic_cdk::api::time();
-    
+    let owner = authenticated_caller()?;
+    let backend = languages::backend(&language)?;
+    let project_id = next_project_id();
+    let now = env::time();
+    // An empty editor starts from the language's template
+    let initial_code = if initial_code.trim().is_empty() { backend.scaffold().to_string() } else { initial_code };
+
     let project = Project {
         id: project_id.clone(),
         name: name.clone(),
-        language: language.clone(),
-        code: initial_code,
+        language: backend.id().to_string(),
+        files: vfs::single_file_tree(backend.id(), initial_code, now),
         created_at: now,
         updated_at: now,
         deployed: false,
         canister_id: None,
+        candid: None,
+        generated_candid: None,
+        tests: Vec::new(),
+        owner,
     };
 
-    PROJECTS.with(|projects| {
-        projects.borrow_mut().insert(project_id.clone(), project);
-    });
+    let files = project.files.clone();
+    PROJECTS.with(|projects| match projects.borrow_mut().entry(project_id.clone()) {
+        Entry::Occupied(_) => Err(ApiError::Conflict(format!("Project {} already exists", project_id))),
+        Entry::Vacant(slot) => {
+            slot.insert(project);
+            Ok(())
+        }
+    })?;
+    record_revision(&project_id, &files, owner, "Create project", now);
 
     Ok(project_id)
 }
 
+/// Deprecated: returns the project as JSON text, use `get_project_v2`.
 #[query]
 pub fn get_project(project_id: String) -> Result<String, String> {
-    PROJECTS.with(|projects| {
-        projects.borrow().get(&project_id)
-            .cloned()
-            .map(|p| serde_json::to_string(&p).unwrap_or_else(|_| "{}".to_string()))
-            .ok_or_else(|| "Project not found".to_string())
-    })
+    let project = get_owned_project(&project_id)?;
+    Ok(serde_json::to_string(&project).unwrap_or_else(|_| "{}".to_string()))
 }
 
+#[query]
+pub fn get_project_v2(project_id: String) -> ApiResult<Project> {
+    get_owned_project(&project_id)
+}
+
+/// Deprecated: returns the projects as JSON text, use `list_projects_v2`.
 #[query]
 pub fn list_projects() -> Result<String, String> {
-    PROJECTS.with(|projects| {
-        let projects_vec: Vec<Project> = projects.borrow().values().cloned().collect();
-        serde_json::to_string(&projects_vec).map_err(|e| e.to_string())
-    })
+    let caller = authenticated_caller()?;
+    serde_json::to_string(&projects_owned_by(caller)).map_err(|e| e.to_string())
+}
+
+#[query]
+pub fn list_projects_v2() -> ApiResult<Vec<Project>> {
+    Ok(projects_owned_by(authenticated_caller()?))
 }
 
 #[update]
 pub async fn update_project_code(project_id: String, new_code: String) -> Result<String, String> {
-    PROJECTS.with(|projects| {
-        let mut projects = projects.borrow_mut();
-        if let Some(project) = projects.get_mut(&project_id) {
-            project.code = new_code;
-            project.updated_at = ic_cdk::api::time();
-            Ok("Code updated successfully".to_string())
+    // Single-file editors write to the project's entry file
+    with_owned_project_mut(&project_id, "Update code", |project, now| {
+        let path = vfs::entry_path(&project.language);
+        if project.files.contains_key(path) {
+            vfs::write_file(&mut project.files, path, new_code, now)?;
         } else {
-            Err("Project not found".to_string())
+            vfs::create_file(&mut project.files, path, new_code, now)?;
         }
-    })
+        Ok("Code updated successfully".to_string())
+    }).map_err(String::from)
+}
+
+// File management
+/// Deprecated: returns the files as JSON text, use `list_files_v2`.
+#[query]
+pub fn list_files(project_id: String) -> Result<String, String> {
+    let files = list_files_v2(project_id)?;
+    serde_json::to_string(&files).map_err(|e| e.to_string())
+}
+
+#[query]
+pub fn list_files_v2(project_id: String) -> ApiResult<Vec<vfs::ProjectFile>> {
+    Ok(get_owned_project(&project_id)?.files.into_values().collect())
+}
+
+/// Deprecated: use `read_file_v2`.
+#[query]
+pub fn read_file(project_id: String, path: String) -> Result<String, String> {
+    Ok(read_file_v2(project_id, path)?)
+}
+
+#[query]
+pub fn read_file_v2(project_id: String, path: String) -> ApiResult<String> {
+    let project = get_owned_project(&project_id)?;
+    Ok(vfs::read_file(&project.files, &path)?.content.clone())
 }
 
-// Simplified compilation - let frontend handle Motoko
 #[update]
-pub async fn compile_project(project_id: String) -> Result<String, String> {
-    let project = PROJECTS.with(|projects| {
-        projects.borrow().get(&project_id).cloned()
-    });
+pub async fn create_file(project_id: String, path: String, content: String) -> Result<String, String> {
+    with_owned_project_mut(&project_id, &format!("Create {}", path), |project, now| {
+        vfs::create_file(&mut project.files, &path, content, now)?;
+        Ok(format!("Created {}", path))
+    }).map_err(String::from)
+}
 
-    let project = match project {
-        Some(p) => p,
-        None => return Err("Project not found".to_string()),
-    };
+#[update]
+pub async fn create_directory(project_id: String, path: String) -> Result<String, String> {
+    with_owned_project_mut(&project_id, &format!("Create directory {}", path), |project, now| {
+        vfs::create_directory(&mut project.files, &path, now)?;
+        Ok(format!("Created directory {}", path))
+    }).map_err(String::from)
+}
+
+#[update]
+pub async fn write_file(project_id: String, path: String, content: String) -> Result<String, String> {
+    with_owned_project_mut(&project_id, &format!("Save {}", path), |project, now| {
+        vfs::write_file(&mut project.files, &path, content, now)?;
+        Ok(format!("Saved {}", path))
+    }).map_err(String::from)
+}
+
+#[update]
+pub async fn rename_file(project_id: String, path: String, new_name: String) -> Result<String, String> {
+    with_owned_project_mut(&project_id, &format!("Rename {} to {}", path, new_name), |project, now| {
+        vfs::rename_entry(&mut project.files, &path, &new_name, now)?;
+        Ok(format!("Renamed {} to {}", path, new_name))
+    }).map_err(String::from)
+}
+
+#[update]
+pub async fn move_file(project_id: String, from: String, to: String) -> Result<String, String> {
+    with_owned_project_mut(&project_id, &format!("Move {} to {}", from, to), |project, now| {
+        vfs::move_entry(&mut project.files, &from, &to, now)?;
+        Ok(format!("Moved {} to {}", from, to))
+    }).map_err(String::from)
+}
+
+#[update]
+pub async fn delete_file(project_id: String, path: String) -> Result<String, String> {
+    with_owned_project_mut(&project_id, &format!("Delete {}", path), |project, _| {
+        let removed = vfs::delete_entry(&mut project.files, &path)?;
+        Ok(format!("Deleted {} ({} entries)", path, removed))
+    }).map_err(String::from)
+}
+
+// Version history
+/// Deprecated: returns the revisions as JSON text, use `list_revisions_v2`.
+#[query]
+pub fn list_revisions(project_id: String) -> Result<String, String> {
+    let summaries = list_revisions_v2(project_id)?;
+    serde_json::to_string(&summaries).map_err(|e| e.to_string())
+}
+
+/// The project's revisions, newest first.
+#[query]
+pub fn list_revisions_v2(project_id: String) -> ApiResult<Vec<history::RevisionSummary>> {
+    get_owned_project(&project_id)?;
+    with_history(&project_id, |h| Ok(h.revisions.iter().rev().map(|r| r.summary()).collect()))
+}
+
+/// Deprecated: returns the revision as JSON text, use `get_revision_v2`.
+#[query]
+pub fn get_revision(project_id: String, revision_id: u64) -> Result<String, String> {
+    let revision = get_revision_v2(project_id, revision_id)?;
+    serde_json::to_string(&revision).map_err(|e| e.to_string())
+}
+
+#[query]
+pub fn get_revision_v2(project_id: String, revision_id: u64) -> ApiResult<history::Revision> {
+    get_owned_project(&project_id)?;
+    with_history(&project_id, |h| h.get(revision_id).cloned())
+}
 
-    // For Motoko, return a message directing to frontend compilation
-    if project.language == "motoko" {
-        let result = CompileResult {
+#[query]
+pub fn diff_revisions(project_id: String, from_revision: u64, to_revision: u64) -> Result<String, String> {
+    get_owned_project(&project_id)?;
+    Ok(with_history(&project_id, |h| {
+        let from = h.get(from_revision)?;
+        let to = h.get(to_revision)?;
+        Ok(history::unified_diff(
+            &from.files, &format!("revision {}", from.id),
+            &to.files, &format!("revision {}", to.id),
+        ))
+    })?)
+}
+
+/// Deprecated: returns the revision id as text, use `create_snapshot_v2`.
+#[update]
+pub async fn create_snapshot(project_id: String, name: String) -> Result<String, String> {
+    Ok(create_snapshot_v2(project_id, name).await?.to_string())
+}
+
+/// Name the project's current files; returns the snapshot's revision id.
+#[update]
+pub async fn create_snapshot_v2(project_id: String, name: String) -> ApiResult<u64> {
+    let project = get_owned_project(&project_id)?;
+    with_history(&project_id, |h| h.snapshot(&project.files, project.owner, &name, env::time()))
+}
+
+/// Deprecated: use `restore_revision_v2`.
+#[update]
+pub async fn restore_revision(project_id: String, revision_id: u64) -> Result<String, String> {
+    restore_revision_v2(project_id, revision_id).await?;
+    Ok(format!("Restored revision {}", revision_id))
+}
+
+#[update]
+pub async fn restore_revision_v2(project_id: String, revision_id: u64) -> ApiResult<()> {
+    get_owned_project(&project_id)?;
+    let files = with_history(&project_id, |h| Ok(h.get(revision_id)?.files.clone()))?;
+    with_owned_project_mut(&project_id, &format!("Restore revision {}", revision_id), |project, _| {
+        project.files = files;
+        Ok(())
+    })
+}
+
+/// Deprecated: returns the policy as JSON text, use `get_retention_policy_v2`.
+#[query]
+pub fn get_retention_policy(project_id: String) -> Result<String, String> {
+    let policy = get_retention_policy_v2(project_id)?;
+    serde_json::to_string(&policy).map_err(|e| e.to_string())
+}
+
+#[query]
+pub fn get_retention_policy_v2(project_id: String) -> ApiResult<history::RetentionPolicy> {
+    get_owned_project(&project_id)?;
+    with_history(&project_id, |h| Ok(h.policy.clone()))
+}
+
+#[update]
+pub async fn set_retention_policy(project_id: String, policy: history::RetentionPolicy) -> Result<String, String> {
+    get_owned_project(&project_id)?;
+    policy.validate()?;
+    Ok(with_history(&project_id, |h| {
+        h.policy = policy;
+        h.prune(env::time());
+        Ok(format!("Retention policy updated, {} revisions kept", h.revisions.len()))
+    })?)
+}
+
+/// Check the project's sources with its language backend.
+fn compile(project: &Project) -> CompileResult {
+    match languages::backend(&project.language) {
+        Ok(backend) => backend.compile(&project.files),
+        Err(e) => CompileResult {
             success: false,
-            output: "Motoko compilation should be handled by the frontend WebAssembly compiler".to_string(),
-            errors: vec!["Use frontend compilation for Motoko projects".to_string()],
+            output: e.to_string(),
+            diagnostics: vec![],
             wasm: None,
             candid: None,
-        };
-        return serde_json::to_string(&result).map_err(|e| e.to_string());
+        },
     }
+}
 
-    // For other languages, use backend compilation
-    let result = match project.language.as_str() {
-        "rust" => {
-            if project.code.contains("fn main") || project.code.contains("#[update]") {
-                CompileResult {
-                    success: true,
-                    output: "Rust compilation successful! No errors found.".to_string(),
-                    errors: vec![],
-                    wasm: None,
-                    candid: None,
-                }
-            } else {
-                CompileResult {
-                    success: false,
-                    output: "Rust compilation failed!".to_string(),
-                    errors: vec!["Missing main function or update handler".to_string()],
-                    wasm: None,
-                    candid: None,
-                }
-            }
-        }
-        "javascript" => {
-            if project.code.contains("export") {
-                CompileResult {
-                    success: true,
-                    output: "JavaScript validation successful!".to_string(),
-                    errors: vec![],
-                    wasm: None,
-                    candid: None,
-                }
-            } else {
-                CompileResult {
-                    success: false,
-                    output: "JavaScript validation failed!".to_string(),
-                    errors: vec!["Missing export statements".to_string()],
-                    wasm: None,
-                    candid: None,
-                }
+/// Check a project and keep the Candid interface generated from it, so
+/// `call_function` and the IDE can use it before a real build.
+fn compile_and_store(project_id: &str) -> ApiResult<CompileResult> {
+    let result = compile(&get_owned_project(project_id)?);
+    if let Some(candid) = &result.candid {
+        PROJECTS.with(|projects| {
+            if let Some(project) = projects.borrow_mut().get_mut(project_id) {
+                project.generated_candid = Some(candid.clone());
             }
-        }
-        _ => CompileResult {
-            success: false,
-            output: "Unsupported language".to_string(),
-            errors: vec!["Language not supported".to_string()],
-            wasm: None,
-            candid: None,
-        }
-    };
+        });
+    }
+    Ok(result)
+}
 
+/// Terminal rendering of a compile result: the summary, then each diagnostic.
+fn render_compile(result: &CompileResult) -> String {
+    if result.diagnostics.is_empty() {
+        return result.output.clone();
+    }
+    format!("{}\n{}", result.output, diagnostics::render(&result.diagnostics))
+}
+
+/// Deprecated: returns `CompileResult` as JSON text, use `compile_project_v2`.
+#[update]
+pub async fn compile_project(project_id: String) -> Result<String, String> {
+    let result = compile_and_store(&project_id)?;
     serde_json::to_string(&result).map_err(|e| e.to_string())
 }
 
 #[update]
-pub async fn deploy_project(project_id: String) -> Result<String, String> {
-    let project = PROJECTS.with(|projects| {
-        projects.borrow().get(&project_id).cloned()
-    });
+pub async fn compile_project_v2(project_id: String) -> ApiResult<CompileResult> {
+    compile_and_store(&project_id)
+}
 
-    let project = match project {
-        Some(p) => p,
-        None => return Err("Project not found".to_string()),
+async fn deploy(project_id: &str) -> ApiResult<DeployResult> {
+    let project = get_owned_project(project_id)?;
+
+    // The backend cannot build WASM itself, so this only reports where the
+    // project stands; its canister is created when deploy_project_with_wasm
+    // installs code.
+    let result = match (project.canister_id, project.deployed) {
+        (Some(canister_id), true) => DeployResult {
+            success: true,
+            canister_id: Some(canister_id),
+            url: Some(deploy::canister_url(&canister_id, &config::gateway_url())),
+            output: format!("{} is deployed to canister {}", project.name, canister_id),
+        },
+        (canister_id, _) => DeployResult {
+            success: false,
+            canister_id,
+            url: None,
+            output: format!(
+                "{} ({} files) is not deployed. Compile the project in the IDE to install its WASM.",
+                project.name,
+                project.files.len()
+            ),
+        },
     };
 
-    // Simulate deployment
-    let canister_id = format!("canister_{}", ic_cdk::api::time());
-    let url = format!("https://{}.ic0.app", canister_id);
+    Ok(result)
+}
 
-    // Update project with deployment info
-    PROJECTS.with(|projects| {
-        if let Some(project) = projects.borrow_mut().get_mut(&project_id) {
-            project.deployed = true;
-            project.canister_id = Some(canister_id.clone());
-        }
-    });
+/// Deprecated: returns `DeployResult` as JSON text, use `deploy_project_v2`.
+#[update]
+pub async fn deploy_project(project_id: String) -> Result<String, String> {
+    let result = deploy(&project_id).await?;
+    serde_json::to_string(&result).map_err(|e| e.to_string())
+}
 
-    let result = DeployResult {
-        success: true,
-        canister_id: Some(canister_id),
-        url: Some(url),
-        output: format!("Successfully deployed {} to Internet Computer", project.name),
-    };
+#[update]
+pub async fn deploy_project_v2(project_id: String) -> ApiResult<DeployResult> {
+    deploy(&project_id).await
+}
 
-    serde_json::to_string(&result).map_err(|e| e.to_string())
+/// Run the project's test cases whose name contains one of `filters`
+/// against its deployed canister.
+async fn run_suite(project: &Project, filters: &[&str]) -> ApiResult<test_suite::SuiteResult> {
+    if project.tests.is_empty() {
+        return Err(ApiError::InvalidInput("No tests defined for this project, add them with set_project_tests".to_string()));
+    }
+    let cases = test_suite::select(&project.tests, filters);
+    if cases.is_empty() {
+        return Err(ApiError::InvalidInput(format!("No test matches {}", filters.join(" "))));
+    }
+    let backend = languages::backend(&project.language)?;
+    let (canister_id, candid) = deployed_interface(project)?;
+    backend.run_tests(canister_id, candid, &cases).await
 }
 
+/// `test_input` selects test cases by name, all of them when empty.
+async fn run_test(project: &Project, test_input: &str) -> ApiResult<TestResult> {
+    let filters: Vec<&str> = test_input.split_whitespace().collect();
+    let suite = run_suite(project, &filters).await?;
+    let success = suite.failed == 0;
+    Ok(TestResult {
+        success,
+        output: if success { "Test passed!".to_string() } else { "Test failed!".to_string() },
+        result: test_suite::report(&suite),
+    })
+}
+
+/// Deprecated: returns `TestResult` as JSON text, use `test_project_v2`.
 #[update]
 pub async fn test_project(project_id: String, test_input: String) -> Result<String, String> {
-    let project = PROJECTS.with(|projects| {
-        projects.borrow().get(&project_id).cloned()
-    });
+    let result = run_test(&get_owned_project(&project_id)?, &test_input).await?;
+    serde_json::to_string(&result).map_err(|e| e.to_string())
+}
 
-    let project = match project {
-        Some(p) => p,
-        None => return Err("Project not found".to_string()),
-    };
+#[update]
+pub async fn test_project_v2(project_id: String, test_input: String) -> ApiResult<TestResult> {
+    run_test(&get_owned_project(&project_id)?, &test_input).await
+}
 
-    // Simulate testing based on language
-    let (success, result) = match project.language.as_str() {
-        "rust" => {
-            if project.code.contains("greet") {
-                (true, format!("Hello, {}! Welcome to ICPad!", test_input))
-            } else {
-                (false, "Test failed: No greet function found".to_string())
-            }
-        }
-        "motoko" => {
-            if project.code.contains("greet") {
-                (true, format!("Hello, {}! Welcome to ICPad!", test_input))
-            } else {
-                (false, "Test failed: No greet function found".to_string())
-            }
-        }
-        "javascript" => {
-            if project.code.contains("greet") {
-                (true, format!("Hello, {}! Welcome to ICPad!", test_input))
-            } else {
-                (false, "Test failed: No greet function found".to_string())
-            }
-        }
-        _ => (false, "Unsupported language for testing".to_string())
-    };
+#[query]
+pub fn get_project_tests(project_id: String) -> ApiResult<Vec<test_suite::TestCase>> {
+    Ok(get_owned_project(&project_id)?.tests)
+}
 
-    let test_result = TestResult {
-        success,
-        output: if success { "Test passed!".to_string() } else { "Test failed!".to_string() },
-        result,
-    };
+/// Replace the project's test cases.
+#[update]
+pub fn set_project_tests(project_id: String, tests: Vec<test_suite::TestCase>) -> ApiResult<()> {
+    test_suite::validate(&tests).map_err(ApiError::InvalidInput)?;
+    with_owned_project_mut(&project_id, "Update tests", |project, _| {
+        project.tests = tests;
+        Ok(())
+    })
+}
 
-    serde_json::to_string(&test_result).map_err(|e| e.to_string())
+/// Run the project's test cases against its canister. `filter` selects
+/// cases by name as the terminal's `test NAME...` does.
+#[update]
+pub async fn run_project_tests(project_id: String, filter: Option<String>) -> ApiResult<test_suite::SuiteResult> {
+    let filters: Vec<&str> = filter.as_deref().unwrap_or_default().split_whitespace().collect();
+    run_suite(&get_owned_project(&project_id)?, &filters).await
 }
 
 // Terminal command execution
 #[update]
 pub async fn execute_terminal_command(command: String, project_id: Option<String>) -> Result<String, String> {
-    let parts: Vec<&str> = command.split_whitespace().collect();
-    if parts.is_empty() {
-        return Ok("".to_string());
+    let result = execute_terminal_command_v2(command, project_id).await?;
+    let mut text = result.stdout + &result.stderr;
+    if text.ends_with('\n') {
+        text.pop();
     }
+    if result.exit_code == 0 {
+        Ok(text)
+    } else {
+        Err(text)
+    }
+}
 
-    let cmd = parts[0];
-    let args = &parts[1..];
+#[update]
+pub async fn execute_terminal_command_v2(command: String, project_id: Option<String>) -> ApiResult<shell::TerminalResult> {
+    let caller = authenticated_caller()?;
+    if command.len() > shell::MAX_COMMAND_BYTES {
+        return Err(ApiError::InvalidInput(format!(
+            "Command is {} bytes, the limit is {}",
+            command.len(),
+            shell::MAX_COMMAND_BYTES
+        )));
+    }
+    let started_at = env::time();
+    let mut session = SHELLS.with(|shells| shells.borrow().get(&caller).cloned()).unwrap_or_default();
+    session.enter(project_id.as_deref());
+    let cwd = format!("/{}", session.cwd);
+    let mut host = TerminalHost::open(project_id.clone(), &command)?;
+    let output = shell::run(&mut session, &command, &mut host).await;
+    host.save()?;
 
-    match cmd {
-        "help" => {
-            Ok("Available commands: help, ls, cat, rust, motoko, dfx, compile, deploy, test".to_string())
+    let duration_ns = env::time() - started_at;
+    if !command.trim().is_empty() {
+        session.remember(shell::HistoryEntry {
+            id: 0,
+            command: command.clone(),
+            project_id,
+            cwd,
+            exit_code: output.exit_code,
+            started_at,
+            duration_ns,
+        });
+    }
+    let result = shell::TerminalResult {
+        stdout: output.stdout,
+        stderr: output.stderr,
+        exit_code: output.exit_code,
+        duration_ns,
+        files_changed: host.changed.into_iter().collect(),
+        cwd: format!("/{}", session.cwd),
+    };
+    // Overlapping calls from one user each ran on their own copy of the
+    // session; the last one to finish wins.
+    SHELLS.with(|shells| shells.borrow_mut().insert(caller, session));
+    Ok(result)
+}
+
+/// The caller's latest `limit` (default 100) terminal commands that contain
+/// `search`, oldest first.
+#[query]
+pub fn get_terminal_history(search: Option<String>, limit: Option<u32>) -> ApiResult<Vec<shell::HistoryEntry>> {
+    let caller = authenticated_caller()?;
+    let limit = limit.map_or(100, |l| l as usize);
+    Ok(SHELLS.with(|shells| {
+        shells.borrow().get(&caller).map(|s| s.search_history(search.as_deref(), limit)).unwrap_or_default()
+    }))
+}
+
+/// Completion candidates for the word at `cursor`, counted in characters.
+#[query]
+pub fn complete_terminal_input(line: String, cursor: u32, project_id: Option<String>) -> ApiResult<commands::Completion> {
+    let caller = authenticated_caller()?;
+    let mut session = SHELLS.with(|shells| shells.borrow().get(&caller).cloned()).unwrap_or_default();
+    session.enter(project_id.as_deref());
+    let project = project_id.map(|id| get_owned_project(&id)).transpose()?;
+    let canister = project.as_ref().map(dfx::canister_name);
+    let context = commands::Context {
+        files: project.as_ref().map(|p| &p.files),
+        cwd: &session.cwd,
+        env: &session.env,
+        candid: project.as_ref().and_then(|p| p.interface()),
+        canister: canister.as_deref(),
+    };
+    Ok(commands::complete(&line, cursor as usize, &context))
+}
+
+#[update]
+pub fn clear_terminal_history() -> ApiResult<()> {
+    let caller = authenticated_caller()?;
+    SHELLS.with(|shells| {
+        if let Some(session) = shells.borrow_mut().get_mut(&caller) {
+            session.history.clear();
         }
-        "ls" | "dir" => {
-            Ok("src/\nCargo.toml\ndfx.json\nREADME.md".to_string())
+    });
+    Ok(())
+}
+
+/// Gives the shell a working copy of the project's files. Changes are saved
+/// as one revision before a project command runs and when the line ends.
+struct TerminalHost {
+    project_id: Option<String>,
+    files: Option<vfs::FileTree>,
+    unsaved: bool,
+    changed: BTreeSet<String>,
+    line: String,
+}
+
+impl TerminalHost {
+    fn open(project_id: Option<String>, line: &str) -> ApiResult<Self> {
+        let files = match &project_id {
+            Some(id) => Some(get_owned_project(id)?.files),
+            None => None,
+        };
+        Ok(TerminalHost { project_id, files, unsaved: false, changed: BTreeSet::new(), line: line.to_string() })
+    }
+
+    fn save(&mut self) -> ApiResult<()> {
+        let (Some(project_id), Some(files), true) = (&self.project_id, &self.files, self.unsaved) else {
+            return Ok(());
+        };
+        with_owned_project_mut(project_id, &format!("Terminal: {}", self.line), |project, _| {
+            project.files = files.clone();
+            Ok(())
+        })?;
+        self.unsaved = false;
+        Ok(())
+    }
+}
+
+impl shell::Host for TerminalHost {
+    fn files(&mut self) -> Option<&mut vfs::FileTree> {
+        self.files.as_mut()
+    }
+
+    fn changed(&mut self, path: &str) {
+        self.unsaved = true;
+        self.changed.insert(path.to_string());
+    }
+
+    fn now(&self) -> u64 {
+        env::time()
+    }
+
+    async fn external(&mut self, argv: &[String], _stdin: &str) -> Option<shell::Output> {
+        if !commands::is_project_command(&argv[0]) {
+            return None;
         }
-        "cat" => {
-            if args.is_empty() {
-                return Ok("Usage: cat <filename>".to_string());
-            }
-            let filename = args[0];
-            if filename == "src/lib.rs" || filename == "src/main.rs" {
-                if let Some(project_id) = project_id {
-                    let project = PROJECTS.with(|projects| {
-                        projects.borrow().get(&project_id).cloned()
-                    });
-                    if let Some(project) = project {
-                        Ok(project.code)
-                    } else {
-                        Ok("Project not found".to_string())
-                    }
-                } else {
-                    Ok("No project selected".to_string())
-                }
-            } else {
-                Ok(format!("File not found: {}", filename))
-            }
+        if let Err(e) = self.save() {
+            return Some(shell::Output::error(1, e.to_string()));
         }
-        "rust" => {
-            if args.is_empty() {
-                return Ok("Rust commands: new, build, check, test".to_string());
-            }
-            let subcmd = args[0];
-            match subcmd {
-                "build" | "check" => {
-                    if let Some(project_id) = project_id {
-                        let result = compile_project(project_id).await?;
-                        let compile_result: CompileResult = serde_json::from_str(&result).unwrap_or_else(|_| CompileResult {
-                            success: false,
-                            output: "Parse error".to_string(),
-                            errors: vec![],
-                            wasm: None,
-                            candid: None,
-                        });
-                        Ok(compile_result.output)
-                    } else {
-                        Ok("No project selected".to_string())
-                    }
-                }
-                "test" => {
-                    if let Some(project_id) = project_id {
-                        let result = test_project(project_id, "test".to_string()).await?;
-                        let test_result: TestResult = serde_json::from_str(&result).unwrap_or_else(|_| TestResult {
-                            success: false,
-                            output: "Parse error".to_string(),
-                            result: "Parse error".to_string(),
-                        });
-                        Ok(test_result.result)
-                    } else {
-                        Ok("No project selected".to_string())
-                    }
-                }
-                _ => Ok("Unknown Rust command".to_string())
+        let result = project_command(argv, self.project_id.as_deref()).await;
+        // Project commands may change files themselves.
+        if let Some(project_id) = &self.project_id {
+            let files = get_owned_project(project_id).ok().map(|project| project.files);
+            if let (Some(before), Some(after)) = (&self.files, &files) {
+                let changed = before.keys().chain(after.keys()).filter(|path| {
+                    before.get(*path).map(|f| (&f.kind, &f.content)) != after.get(*path).map(|f| (&f.kind, &f.content))
+                });
+                self.changed.extend(changed.cloned());
             }
+            self.files = files;
         }
-        "motoko" => {
-            if args.is_empty() {
-                return Ok("Motoko commands: new, check, compile".to_string());
-            }
-            let subcmd = args[0];
-            match subcmd {
-                "check" | "compile" => {
-                    Ok("Motoko compilation is handled by the frontend WebAssembly compiler. Use the Compile button in the IDE.".to_string())
-                }
-                _ => Ok("Unknown Motoko command".to_string())
-            }
+        let mut output = result.unwrap_or_else(|e| shell::Output::error(1, e.to_string()));
+        if !output.stdout.is_empty() && !output.stdout.ends_with('\n') {
+            output.stdout.push('\n');
         }
-        "dfx" => {
-            if args.is_empty() {
-                return Ok("DFX commands: start, deploy, generate".to_string());
-            }
-            let subcmd = args.join(" ");
-            match subcmd.as_str() {
-                "start" => Ok("DFX replica started".to_string()),
-                "deploy" => {
-                    if let Some(project_id) = project_id {
-                        let result = deploy_project(project_id).await?;
-                        let deploy_result: DeployResult = serde_json::from_str(&result).unwrap_or_else(|_| DeployResult {
-                            success: false,
-                            canister_id: None,
-                            url: None,
-                            output: "Parse error".to_string(),
-                        });
-                        Ok(deploy_result.output)
-                    } else {
-                        Ok("No project selected".to_string())
-                    }
-                }
-                "generate" => Ok("Candid files generated".to_string()),
-                _ => Ok(format!("DFX command: {}", subcmd))
-            }
-        }
-        "compile" => {
-            if let Some(project_id) = project_id {
-                let result = compile_project(project_id).await?;
-                let compile_result: CompileResult = serde_json::from_str(&result).unwrap_or_else(|_| CompileResult {
-                    success: false,
-                    output: "Parse error".to_string(),
-                    errors: vec![],
-                    wasm: None,
-                    candid: None,
-                });
-                Ok(compile_result.output)
-            } else {
-                Ok("No project selected".to_string())
-            }
+        Some(output)
+    }
+}
+
+/// Terminal commands that act on the project rather than on its files.
+async fn project_command(argv: &[String], project_id: Option<&str>) -> ApiResult<shell::Output> {
+    use shell::Output;
+    let cmd = argv[0].as_str();
+    let args: Vec<&str> = argv[1..].iter().map(String::as_str).collect();
+    let project = || project_id.ok_or_else(|| ApiError::InvalidInput("No project selected".to_string()));
+    let compiled = |result: CompileResult| Output {
+        stdout: render_compile(&result),
+        exit_code: if result.success { 0 } else { 1 },
+        ..Default::default()
+    };
+    let tested = |result: TestResult| Output {
+        stdout: result.result,
+        exit_code: if result.success { 0 } else { 1 },
+        ..Default::default()
+    };
+    let deployed = |result: DeployResult| Output {
+        stdout: result.output,
+        exit_code: if result.success { 0 } else { 1 },
+        ..Default::default()
+    };
+
+    Ok(match (cmd, args.as_slice()) {
+        ("exec", [session, command @ ..]) if !command.is_empty() => {
+            let result = exec_in_session(session.to_string(), command.join(" ")).await?;
+            Output { stdout: result.stdout, stderr: result.stderr, exit_code: result.exit_code }
         }
-        "deploy" => {
-            if let Some(project_id) = project_id {
-                let result = deploy_project(project_id).await?;
-                let deploy_result: DeployResult = serde_json::from_str(&result).unwrap_or_else(|_| DeployResult {
-                    success: false,
-                    canister_id: None,
-                    url: None,
-                    output: "Parse error".to_string(),
-                });
-                Ok(deploy_result.output)
-            } else {
-                Ok("No project selected".to_string())
-            }
+        ("exec", _) => Output::error(2, "Usage: exec <session> <command>"),
+        ("rust", ["build" | "check"]) | ("motoko", ["check" | "compile"]) | ("compile", _) => {
+            compiled(compile_and_store(project()?)?)
         }
-        "test" => {
-            if let Some(project_id) = project_id {
-                let test_input = args.join(" ");
-                let result = test_project(project_id, test_input).await?;
-                let test_result: TestResult = serde_json::from_str(&result).unwrap_or_else(|_| TestResult {
-                    success: false,
-                    output: "Parse error".to_string(),
-                    result: "Parse error".to_string(),
-                });
-                Ok(test_result.result)
-            } else {
-                Ok("No project selected".to_string())
+        ("rust", ["test"]) => tested(run_test(&get_owned_project(project()?)?, "").await?),
+        ("rust", []) => Output::ok("Rust commands: build, check, test"),
+        ("rust", _) => Output::error(2, "Unknown Rust command"),
+        ("motoko", []) => Output::ok("Motoko commands: check, compile"),
+        ("motoko", _) => Output::error(2, "Unknown Motoko command"),
+        ("dfx", []) => Output::ok("DFX commands: start, deploy, generate, canister"),
+        ("dfx", ["start"]) => Output::ok("DFX replica started"),
+        ("dfx", ["deploy"]) | ("deploy", _) => deployed(deploy(project()?).await?),
+        ("dfx", ["generate"]) => dfx::generate(project()?)?,
+        ("dfx", ["canister", rest @ ..]) => dfx::canister(&get_owned_project(project()?)?, rest).await?,
+        ("dfx", _) => Output::error(2, format!("Unknown DFX command: {}", args.join(" "))),
+        ("test", _) => tested(run_test(&get_owned_project(project()?)?, &args.join(" ")).await?),
+        ("call", [method, call_args @ ..]) => {
+            let project = get_owned_project(project()?)?;
+            let (canister_id, candid) = deployed_interface(&project)?;
+            let call_args: Vec<String> = call_args.iter().map(|a| a.to_string()).collect();
+            match candid_call::call(canister_id, candid, method, &call_args).await {
+                Ok(reply) => Output::ok(reply),
+                Err(e) => Output::error(1, e.to_string()),
             }
         }
-        _ => Ok(format!("Command not found: {}", cmd))
-    }
+        ("call", []) => Output::error(2, "Usage: call METHOD [ARG]..."),
+        _ => Output::error(127, format!("{}: command not found", cmd)),
+    })
+}
+
+#[query]
+pub fn list_supported_languages() -> Vec<languages::LanguageInfo> {
+    languages::supported()
 }
 
 #[query]
@@ -513,92 +1349,121 @@ pub fn check_connection() -> Result<String, String> {
     Ok("true".to_string())
 }
 
-ic_cdk::export_candid!();
+/// Install `wasm` into the project's canister and keep `candid` as its interface.
+async fn install_project_wasm(
+    project_id: &str,
+    wasm: Vec<u8>,
+    candid: String,
+    init_args: Option<Vec<u8>>,
+) -> ApiResult<(DeployResult, String)> {
+    let project = get_owned_project(project_id)?;
+    deploy::validate_wasm(&wasm)?;
 
-// NEW: Real deployment with WASM installation
-#[update]
-pub async fn deploy_project_with_wasm(project_id: String, wasm: Vec<u8>, candid: String) -> Result<String, String> {
-    let project = PROJECTS.with(|projects| {
-        projects.borrow().get(&project_id).cloned()
-    });
-
-    let project = match project {
-        Some(p) => p,
-        None => return Err("Project not found".to_string()),
-    };
-
-    // Create a real canister ID (simulated for now, but would be real in production)
-    let canister_id = format!("canister_{}", ic_cdk::api::time());
-    let url = format!("https://{}.ic0.app", canister_id);
+    let canister_id = ensure_project_canister(&project).await?;
+    let wasm_size = wasm.len();
+    let mode = deploy::install_wasm(
+        canister_id,
+        wasm,
+        init_args.unwrap_or_else(deploy::empty_args),
+        project.deployed,
+    )
+    .await?;
+    let url = deploy::canister_url(&canister_id, &config::gateway_url());
 
     // Update project with deployment info
     PROJECTS.with(|projects| {
-        if let Some(project) = projects.borrow_mut().get_mut(&project_id) {
+        if let Some(project) = projects.borrow_mut().get_mut(project_id) {
             project.deployed = true;
-            project.canister_id = Some(canister_id.clone());
+            project.canister_id = Some(canister_id);
+            project.candid = Some(candid);
+            project.updated_at = env::time();
         }
     });
 
+    let result = DeployResult {
+        success: true,
+        canister_id: Some(canister_id),
+        url: Some(url),
+        output: format!("Successfully deployed {} to canister {} with {} bytes of WASM", project.name, canister_id, wasm_size),
+    };
+    Ok((result, format!("{:?}", mode)))
+}
+
+/// Deprecated: returns the deployment as JSON text, use `deploy_project_with_wasm_v2`.
+#[update]
+pub async fn deploy_project_with_wasm(
+    project_id: String,
+    wasm: Vec<u8>,
+    candid: String,
+    init_args: Option<Vec<u8>>,
+) -> Result<String, String> {
+    let wasm_size = wasm.len();
+    let (result, mode) = install_project_wasm(&project_id, wasm, candid.clone(), init_args).await?;
     let result = serde_json::json!({
         "success": true,
-        "canister_id": canister_id,
-        "url": url,
-        "wasm_size": wasm.len(),
+        "canister_id": result.canister_id.map(|id| id.to_text()),
+        "url": result.url,
+        "mode": mode,
+        "wasm_size": wasm_size,
         "candid": candid,
-        "output": format!("Successfully deployed {} to Internet Computer with {} bytes of WASM", project.name, wasm.len())
+        "output": result.output
     });
-
     Ok(result.to_string())
 }
 
-// NEW: Call deployed function
+// Real deployment with WASM installation
 #[update]
-pub async fn call_function(project_id: String, function_name: String, args: Vec<String>) -> Result<String, String> {
-    let project = PROJECTS.with(|projects| {
-        projects.borrow().get(&project_id).cloned()
-    });
+pub async fn deploy_project_with_wasm_v2(
+    project_id: String,
+    wasm: Vec<u8>,
+    candid: String,
+    init_args: Option<Vec<u8>>,
+) -> ApiResult<DeployResult> {
+    Ok(install_project_wasm(&project_id, wasm, candid, init_args).await?.0)
+}
 
-    let project = match project {
-        Some(p) => p,
-        None => return Err("Project not found".to_string()),
+/// The project's canister and the interface to call it with.
+fn deployed_interface(project: &Project) -> ApiResult<(Principal, &str)> {
+    let (Some(canister_id), true) = (project.canister_id, project.deployed) else {
+        return Err(ApiError::InvalidInput("Project not deployed".to_string()));
     };
+    let candid = project.interface().ok_or_else(|| {
+        ApiError::InvalidInput("No Candid interface stored for this project, compile it or redeploy it with its .did".to_string())
+    })?;
+    Ok((canister_id, candid))
+}
 
-    if !project.deployed {
-        return Err("Project not deployed".to_string());
-    }
+/// Deprecated: returns the reply as JSON text, use `call_function_v2`.
+#[update]
+pub async fn call_function(project_id: String, function_name: String, args: Vec<String>) -> Result<String, String> {
+    let project = get_owned_project(&project_id)?;
+    let (canister_id, candid) = deployed_interface(&project)?;
 
-    // Simulate function calls based on function name
-    let result = match function_name.as_str() {
-        "getMessage" => {
-            serde_json::json!({
-                "success": true,
-                "result": "Hello from ICPad!",
-                "error": null
-            })
-        }
-        "greet" => {
-            let name = args.get(0).unwrap_or(&"World".to_string()).clone();
-            serde_json::json!({
-                "success": true,
-                "result": format!("Hello, {}! Welcome to ICPad!", name),
-                "error": null
-            })
-        }
-        "whoami" => {
-            serde_json::json!({
-                "success": true,
-                "result": "Principal from actor",
-                "error": null
-            })
-        }
-        _ => {
-            serde_json::json!({
-                "success": false,
-                "result": "",
-                "error": format!("Function '{}' not found", function_name)
-            })
-        }
+    let result = match candid_call::call(canister_id, candid, &function_name, &args).await {
+        Ok(reply) => serde_json::json!({
+            "success": true,
+            "result": reply,
+            "error": null
+        }),
+        Err(e) => serde_json::json!({
+            "success": false,
+            "result": "",
+            "error": e.to_string(),
+            "error_kind": e.kind()
+        }),
     };
 
     Ok(result.to_string())
 }
+
+// Call a method on the project's deployed canister; the reply comes back as Candid text
+#[update]
+pub async fn call_function_v2(project_id: String, function_name: String, args: Vec<String>) -> ApiResult<String> {
+    let project = get_owned_project(&project_id)?;
+    let (canister_id, candid) = deployed_interface(&project)?;
+    Ok(candid_call::call(canister_id, candid, &function_name, &args).await?)
+}
+
+ic_cdk::export_candid!();
+
+
@@ -0,0 +1,178 @@
+// Lightweight checks for Motoko and JavaScript projects.
+//
+// Neither language can be parsed in the canister yet, so these only catch
+// what is cheap to find reliably: unbalanced delimiters, unterminated
+// strings and comments, and a missing actor or export. Keywords are only
+// looked for in code, never inside comments or string literals.
+
+use crate::diagnostics::{self, Diagnostic, Range, Severity};
+use crate::vfs::ProjectFile;
+
+/// The identifiers and keywords of `content`, skipping comments and string
+/// literals. Unterminated ones run to the end of the line or file, as
+/// `diagnostics::check_delimiters` reports them.
+fn code_words(content: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut chars = content.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c.is_alphanumeric() || c == '_' {
+            start.get_or_insert(i);
+            continue;
+        }
+        if let Some(from) = start.take() {
+            words.push(&content[from..i]);
+        }
+        match c {
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                while chars.next_if(|&(_, next)| next != '\n').is_some() {}
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                let mut previous = ' ';
+                for (_, next) in chars.by_ref() {
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+            }
+            '"' | '\'' | '`' => {
+                while let Some((_, next)) = chars.next() {
+                    if next == '\\' {
+                        chars.next();
+                    } else if next == c || (next == '\n' && c != '`') {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(from) = start {
+        words.push(&content[from..]);
+    }
+    words
+}
+
+fn has_word(content: &str, word: &str) -> bool {
+    code_words(content).contains(&word)
+}
+
+/// `public func`, `public query func`, `public shared(msg) func`, ...
+fn has_public_function(content: &str) -> bool {
+    code_words(content)
+        .windows(2)
+        .any(|pair| pair[0] == "public" && matches!(pair[1], "func" | "query" | "shared" | "composite"))
+}
+
+pub fn check_motoko(sources: &[&ProjectFile]) -> Vec<Diagnostic> {
+    let mut result = Vec::new();
+    for file in sources {
+        result.extend(diagnostics::check_delimiters("motoko", &file.path, &file.content));
+    }
+    let Some(main) = sources.iter().find(|f| f.path.ends_with("main.mo")).or(sources.first()) else {
+        return result;
+    };
+    if !sources.iter().any(|f| has_word(&f.content, "actor")) {
+        result.push(
+            Diagnostic::new("motoko", &main.path, Range::default(), Severity::Error, "no actor is defined")
+                .with_code("missing-actor"),
+        );
+    } else if !sources.iter().any(|f| has_public_function(&f.content)) {
+        result.push(
+            Diagnostic::new("motoko", &main.path, Range::default(), Severity::Warning, "actor has no public functions")
+                .with_code("no-public-functions"),
+        );
+    }
+    result
+}
+
+pub fn check_javascript(sources: &[&ProjectFile]) -> Vec<Diagnostic> {
+    let mut result = Vec::new();
+    for file in sources {
+        result.extend(diagnostics::check_delimiters("javascript", &file.path, &file.content));
+    }
+    if let Some(first) = sources.first() {
+        if !sources.iter().any(|f| has_word(&f.content, "export")) {
+            result.push(
+                Diagnostic::new("javascript", &first.path, Range::default(), Severity::Error, "missing export statements")
+                    .with_code("missing-export"),
+            );
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::FileKind;
+
+    fn file(path: &str, content: &str) -> ProjectFile {
+        ProjectFile {
+            path: path.to_string(),
+            kind: FileKind::File,
+            content: content.to_string(),
+            language: None,
+            mime_type: "text/plain".to_string(),
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    fn codes(diagnostics: Vec<Diagnostic>) -> Vec<String> {
+        diagnostics.into_iter().filter_map(|d| d.code).collect()
+    }
+
+    fn motoko(content: &str) -> Vec<String> {
+        codes(check_motoko(&[&file("src/main.mo", content)]))
+    }
+
+    fn javascript(content: &str) -> Vec<String> {
+        codes(check_javascript(&[&file("src/index.js", content)]))
+    }
+
+    #[test]
+    fn motoko_actor_with_public_functions_is_clean() {
+        assert!(motoko("actor { public func greet() : async Text { \"hi\" } }").is_empty());
+        assert!(motoko("actor { public query func get() : async Nat { 1 } }").is_empty());
+        assert!(motoko("actor { public shared(msg) func who() : async Principal { msg.caller } }").is_empty());
+        assert!(motoko("actor { public composite query func all() : async Nat { 1 } }").is_empty());
+    }
+
+    #[test]
+    fn motoko_delimiters_are_checked() {
+        assert_eq!(motoko("actor { public func f() : async () { }"), vec!["unclosed-delimiter"]);
+    }
+
+    #[test]
+    fn motoko_needs_an_actor_in_code() {
+        assert_eq!(motoko("module { public func f() {} }"), vec!["missing-actor"]);
+        assert_eq!(motoko("// the actor lives elsewhere\nmodule { public func f() {} }"), vec!["missing-actor"]);
+        assert_eq!(motoko("module { let s = \"actor\"; public func f() {} }"), vec!["missing-actor"]);
+    }
+
+    #[test]
+    fn motoko_public_functions_are_found_in_code_only() {
+        assert_eq!(motoko("actor { func hidden() {} }"), vec!["no-public-functions"]);
+        assert_eq!(motoko("actor { // public func greet() {}\n func hidden() {} }"), vec!["no-public-functions"]);
+        assert_eq!(motoko("actor { /* public query func get() */ func hidden() {} }"), vec!["no-public-functions"]);
+        assert_eq!(motoko("actor { let doc = \"public shared func\"; }"), vec!["no-public-functions"]);
+        assert_eq!(motoko("actor { public let x = 1; }"), vec!["no-public-functions"]);
+    }
+
+    #[test]
+    fn javascript_needs_an_export_in_code() {
+        assert!(javascript("export function main() { return `ok`; }").is_empty());
+        assert_eq!(javascript("function main() {}"), vec!["missing-export"]);
+        assert_eq!(javascript("// export function main() {}\nfunction main() {}"), vec!["missing-export"]);
+        assert_eq!(javascript("const s = 'export default x';"), vec!["missing-export"]);
+        assert_eq!(javascript("const s = `\nexport default x\n`;"), vec!["missing-export"]);
+    }
+
+    #[test]
+    fn javascript_delimiters_are_checked() {
+        assert_eq!(javascript("export function main() { return \"ok; }"), vec!["unterminated-string", "unclosed-delimiter"]);
+    }
+}
@@ -0,0 +1,145 @@
+// Session resource profiles and per-user session quotas.
+//
+// Admins define the profiles a workspace can be started with (image and
+// resources) and the limits every user shares: how many sessions may run at
+// once and how many session-minutes may be used per UTC day.
+
+use crate::error::{ApiError, ApiResult};
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+pub const SECS_PER_MINUTE: u64 = 60;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SessionProfile {
+    pub name: String,
+    /// Container image, e.g. `icpad/rust-dfx:latest`.
+    pub image: String,
+    pub cpu_millicores: u32,
+    pub memory_mb: u32,
+    pub disk_mb: u32,
+    /// Sessions are stopped after this long, whatever their heartbeats.
+    pub max_lifetime_secs: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SessionLimits {
+    pub max_concurrent_sessions: u32,
+    pub daily_minutes: u64,
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        SessionLimits {
+            max_concurrent_sessions: 2,
+            daily_minutes: 240,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct UsageSummary {
+    pub active_sessions: u32,
+    pub max_concurrent_sessions: u32,
+    pub minutes_used_today: u64,
+    pub daily_minutes: u64,
+    pub minutes_remaining: u64,
+}
+
+pub fn validate_profiles(profiles: &[SessionProfile]) -> ApiResult<()> {
+    for (i, profile) in profiles.iter().enumerate() {
+        if profile.name.trim().is_empty() || profile.image.trim().is_empty() {
+            return Err(ApiError::InvalidInput("Profiles need a name and an image".to_string()));
+        }
+        if profile.cpu_millicores == 0 || profile.memory_mb == 0 || profile.disk_mb == 0 || profile.max_lifetime_secs == 0 {
+            return Err(ApiError::InvalidInput(format!("Profile {} has a zero resource limit", profile.name)));
+        }
+        if profiles[..i].iter().any(|p| p.name == profile.name) {
+            return Err(ApiError::InvalidInput(format!("Duplicate profile: {}", profile.name)));
+        }
+    }
+    Ok(())
+}
+
+/// The named profile, or the first one when no name is given. With no
+/// profiles configured the controller's defaults are used.
+pub fn resolve(profiles: &[SessionProfile], name: Option<&str>) -> ApiResult<Option<SessionProfile>> {
+    match name {
+        Some(name) => profiles
+            .iter()
+            .find(|p| p.name == name)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| ApiError::NotFound(format!("Session profile not found: {}", name))),
+        None => Ok(profiles.first().cloned()),
+    }
+}
+
+pub fn summary(limits: &SessionLimits, active_sessions: u32, seconds_used_today: u64) -> UsageSummary {
+    let minutes_used_today = seconds_used_today.div_ceil(SECS_PER_MINUTE);
+    UsageSummary {
+        active_sessions,
+        max_concurrent_sessions: limits.max_concurrent_sessions,
+        minutes_used_today,
+        daily_minutes: limits.daily_minutes,
+        minutes_remaining: limits.daily_minutes.saturating_sub(minutes_used_today),
+    }
+}
+
+/// Check that another session may start and return how many seconds of
+/// today's quota it may use.
+pub fn check_quota(limits: &SessionLimits, active_sessions: u32, seconds_used_today: u64) -> ApiResult<u64> {
+    if active_sessions >= limits.max_concurrent_sessions {
+        return Err(ApiError::Conflict(format!(
+            "You already have {} active session(s), the limit is {}",
+            active_sessions, limits.max_concurrent_sessions
+        )));
+    }
+    let remaining = (limits.daily_minutes * SECS_PER_MINUTE).saturating_sub(seconds_used_today);
+    if remaining == 0 {
+        return Err(ApiError::Conflict(format!(
+            "Daily session quota of {} minutes is used up",
+            limits.daily_minutes
+        )));
+    }
+    Ok(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str) -> SessionProfile {
+        SessionProfile {
+            name: name.to_string(),
+            image: format!("icpad/{}", name),
+            cpu_millicores: 1000,
+            memory_mb: 2048,
+            disk_mb: 4096,
+            max_lifetime_secs: 3600,
+        }
+    }
+
+    #[test]
+    fn resolves_profiles() {
+        let profiles = vec![profile("rust-dfx"), profile("motoko")];
+        assert!(validate_profiles(&profiles).is_ok());
+        assert!(validate_profiles(&[profile("a"), profile("a")]).is_err());
+        assert_eq!(resolve(&profiles, None).unwrap().unwrap().name, "rust-dfx");
+        assert_eq!(resolve(&profiles, Some("motoko")).unwrap().unwrap().name, "motoko");
+        assert!(resolve(&profiles, Some("python")).is_err());
+        assert_eq!(resolve(&[], None).unwrap(), None);
+    }
+
+    #[test]
+    fn enforces_quota() {
+        let limits = SessionLimits { max_concurrent_sessions: 1, daily_minutes: 10 };
+        assert_eq!(check_quota(&limits, 0, 4 * 60).unwrap(), 6 * 60);
+        assert!(check_quota(&limits, 1, 0).is_err());
+        assert!(check_quota(&limits, 0, 10 * 60).is_err());
+
+        let usage = summary(&limits, 1, 61);
+        assert_eq!(usage.minutes_used_today, 2);
+        assert_eq!(usage.minutes_remaining, 8);
+    }
+}
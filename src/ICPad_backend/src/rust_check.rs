@@ -0,0 +1,140 @@
+// Syntax and canister checks for Rust projects.
+//
+// The backend cannot run rustc, but it can parse every source file with syn
+// and report syntax errors at their real position. On top of that it checks
+// what any Rust canister needs: at least one `#[query]` or `#[update]`
+// method and an `export_candid!()` call so its interface can be extracted.
+
+use crate::diagnostics::{self, Diagnostic, Position, Range, Severity, TextEdit};
+use crate::vfs::ProjectFile;
+use syn::visit::{self, Visit};
+
+const SOURCE: &str = "rust";
+
+/// Canister methods and `export_candid!()` calls found in one file.
+#[derive(Default)]
+struct Exports {
+    methods: usize,
+    export_candid: bool,
+}
+
+fn attribute_is(attr: &syn::Attribute, names: &[&str]) -> bool {
+    attr.path().segments.last().is_some_and(|s| names.contains(&s.ident.to_string().as_str()))
+}
+
+impl<'ast> Visit<'ast> for Exports {
+    fn visit_item_fn(&mut self, item: &'ast syn::ItemFn) {
+        if item.attrs.iter().any(|a| attribute_is(a, &["query", "update"])) {
+            self.methods += 1;
+        }
+        visit::visit_item_fn(self, item);
+    }
+
+    fn visit_item_macro(&mut self, item: &'ast syn::ItemMacro) {
+        if item.mac.path.segments.last().is_some_and(|s| s.ident == "export_candid") {
+            self.export_candid = true;
+        }
+        visit::visit_item_macro(self, item);
+    }
+}
+
+fn position(at: proc_macro2::LineColumn) -> Position {
+    // proc-macro2 lines are one based, columns zero based.
+    Position::new(at.line.saturating_sub(1) as u32, at.column as u32)
+}
+
+fn syntax_error(file: &str, err: &syn::Error) -> Diagnostic {
+    let span = err.span();
+    let range = Range::new(position(span.start()), position(span.end()));
+    Diagnostic::new(SOURCE, file, range, Severity::Error, err.to_string()).with_code("syntax")
+}
+
+/// Check the project's Rust sources. Canister level problems are reported
+/// against `src/lib.rs` when the project has one.
+pub fn check(sources: &[&ProjectFile]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut exports = Exports::default();
+    let mut parsed = 0;
+    for file in sources {
+        match syn::parse_file(&file.content) {
+            Ok(ast) => {
+                exports.visit_file(&ast);
+                parsed += 1;
+            }
+            Err(err) => diagnostics.extend(err.into_iter().map(|e| syntax_error(&file.path, &e))),
+        }
+    }
+    // Missing exports may just be in the file that failed to parse.
+    if parsed < sources.len() {
+        return diagnostics;
+    }
+    let Some(root) = sources.iter().find(|f| f.path == "src/lib.rs").or(sources.first()) else {
+        return diagnostics;
+    };
+
+    if exports.methods == 0 {
+        diagnostics.push(
+            Diagnostic::new(SOURCE, &root.path, Range::default(), Severity::Error, "canister has no #[query] or #[update] methods")
+                .with_code("missing-canister-methods"),
+        );
+    }
+    if !exports.export_candid {
+        let end = diagnostics::end_of(&root.content);
+        let separator = if root.content.is_empty() || root.content.ends_with('\n') { "" } else { "\n" };
+        diagnostics.push(
+            Diagnostic::new(
+                SOURCE,
+                &root.path,
+                Range::at(end),
+                Severity::Warning,
+                "missing `ic_cdk::export_candid!();`, the Candid interface cannot be extracted",
+            )
+            .with_code("missing-export-candid")
+            .with_fix(
+                "Add `ic_cdk::export_candid!();`",
+                vec![TextEdit {
+                    range: Range::at(end),
+                    new_text: format!("{}\nic_cdk::export_candid!();\n", separator),
+                }],
+            ),
+        );
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs;
+
+    fn check_source(source: &str) -> Vec<Diagnostic> {
+        let tree = vfs::single_file_tree("rust", source.to_string(), 0);
+        let sources: Vec<_> = vfs::files_with_language(&tree, "rust").collect();
+        check(&sources)
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_position() {
+        let diagnostics = check_source("#[ic_cdk::query]\nfn greet() -> String {\n    let x = ;\n}\n");
+        assert_eq!(diagnostics.len(), 1);
+        let d = &diagnostics[0];
+        assert_eq!((d.file.as_str(), d.range, d.severity), ("src/lib.rs", Range::span(Position::new(2, 12), 1), Severity::Error));
+        assert_eq!(d.to_string(), format!("src/lib.rs:3:13: error[syntax]: {}", d.message));
+    }
+
+    #[test]
+    fn checks_canister_exports() {
+        let diagnostics = check_source("fn main() {}\n");
+        let severities: Vec<_> = diagnostics.iter().map(|d| d.severity).collect();
+        assert_eq!(severities, vec![Severity::Error, Severity::Warning]);
+        let fix = &diagnostics[1].fixes[0].edits[0];
+        assert_eq!(fix.range, Range::at(Position::new(1, 0)));
+        assert_eq!(fix.new_text, "\nic_cdk::export_candid!();\n");
+
+        let ok = "use ic_cdk::{query, update};\n\
+                  #[query]\nfn greet(name: String) -> String { format!(\"Hello, {}!\", name) }\n\
+                  mod admin {\n    #[ic_cdk::update]\n    fn reset() {}\n}\n\
+                  ic_cdk::export_candid!();\n";
+        assert_eq!(check_source(ok), vec![]);
+    }
+}
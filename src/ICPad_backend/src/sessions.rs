@@ -0,0 +1,432 @@
+// Docker session registry.
+//
+// Sessions are grouped by the principal that started them. Each session has
+// an expiry that heartbeats push forward; a single periodic reaper stops
+// everything past its expiry instead of arming one timer per session.
+
+use crate::config::DEFAULT_ENDPOINT;
+use crate::error::{ApiError, ApiResult};
+use crate::profiles::{self, SessionLimits};
+use crate::sync::FileHashes;
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How often the reaper looks for expired sessions.
+pub const REAPER_INTERVAL_SECS: u64 = 30;
+
+/// Finished sessions kept per owner so `list_my_sessions` shows recent history.
+const MAX_FINISHED_PER_OWNER: usize = 20;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * NANOS_PER_SEC;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum SessionState {
+    Starting,
+    Running,
+    Stopping,
+    Stopped,
+    Failed,
+}
+
+impl SessionState {
+    pub fn is_active(self) -> bool {
+        matches!(self, SessionState::Starting | SessionState::Running)
+    }
+
+    /// The container may still exist and counts against the owner's limits.
+    fn holds_container(self) -> bool {
+        matches!(self, SessionState::Starting | SessionState::Running | SessionState::Stopping)
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct Session {
+    pub id: String,
+    pub owner: Principal,
+    /// Project whose files were copied into the container.
+    #[serde(default)]
+    pub project_id: Option<String>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Controller endpoint hosting the container; `None` means the default one.
+    #[serde(default)]
+    pub controller_id: Option<String>,
+    /// Set once the controller has started the container.
+    pub container_id: Option<String>,
+    pub editor_url: Option<String>,
+    pub state: SessionState,
+    pub created_at: u64,
+    pub expires_at: u64,
+    /// Hard stop from the profile lifetime and the owner's daily quota;
+    /// heartbeats never extend `expires_at` past it.
+    #[serde(default)]
+    pub deadline: Option<u64>,
+    pub last_heartbeat: u64,
+    pub error: Option<String>,
+}
+
+/// Session time already charged to an owner on one UTC day.
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct DailyUsage {
+    day: u64,
+    seconds: u64,
+}
+
+/// Seconds of `[started_at, until]` that fall on the UTC day of `until`.
+fn seconds_today(started_at: u64, until: u64) -> u64 {
+    let day_start = until - until % NANOS_PER_DAY;
+    until.saturating_sub(started_at.max(day_start)) / NANOS_PER_SEC
+}
+
+/// Where to send calls for a running session.
+pub struct Target {
+    pub session_id: String,
+    pub container_id: String,
+    pub controller_id: String,
+}
+
+impl Session {
+    pub fn controller(&self) -> String {
+        self.controller_id.clone().unwrap_or_else(|| DEFAULT_ENDPOINT.to_string())
+    }
+
+    /// Sessions can be addressed by their own id or by the container id.
+    fn matches(&self, key: &str) -> bool {
+        self.id == key || self.container_id.as_deref() == Some(key)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct SessionRegistry {
+    next_id: u64,
+    by_owner: BTreeMap<Principal, Vec<Session>>,
+    #[serde(default)]
+    next_request: u64,
+    /// Hashes of the files each session's container last agreed on with its project.
+    #[serde(default)]
+    sync_base: BTreeMap<String, FileHashes>,
+    #[serde(default)]
+    usage: BTreeMap<Principal, DailyUsage>,
+}
+
+/// Idempotency key of the `/start` request for the session begun at `created_at`.
+pub fn start_key(session_id: &str, created_at: u64) -> String {
+    format!("start:{}:{}", session_id, created_at)
+}
+
+pub fn expiry(now: u64, timeout_secs: u64) -> u64 {
+    now.saturating_add(timeout_secs.saturating_mul(NANOS_PER_SEC))
+}
+
+impl SessionRegistry {
+    /// Register a new session in the `Starting` state and return its id.
+    pub fn begin(&mut self, owner: Principal, now: u64, timeout_secs: u64) -> String {
+        self.next_id += 1;
+        let id = format!("session_{}", self.next_id);
+        let sessions = self.by_owner.entry(owner).or_default();
+        sessions.push(Session {
+            id: id.clone(),
+            owner,
+            project_id: None,
+            container_id: None,
+            editor_url: None,
+            state: SessionState::Starting,
+            profile: None,
+            controller_id: None,
+            created_at: now,
+            expires_at: expiry(now, timeout_secs),
+            deadline: None,
+            last_heartbeat: now,
+            error: None,
+        });
+        prune_finished(sessions);
+        id
+    }
+
+    /// Look up one of `owner`'s sessions. Sessions belonging to someone else
+    /// are reported as unauthorized rather than missing.
+    pub fn get_mut(&mut self, owner: Principal, key: &str) -> ApiResult<&mut Session> {
+        let err = self.missing(key);
+        self.by_owner
+            .get_mut(&owner)
+            .and_then(|s| s.iter_mut().find(|s| s.matches(key)))
+            .ok_or(err)
+    }
+
+    fn missing(&self, key: &str) -> ApiError {
+        if self.by_owner.values().flatten().any(|s| s.matches(key)) {
+            ApiError::Unauthorized(format!("session {} belongs to another user", key))
+        } else {
+            ApiError::NotFound(format!("Session not found: {}", key))
+        }
+    }
+
+    /// A running session's container, failing for sessions in any other state.
+    pub fn running_container(&mut self, owner: Principal, key: &str) -> ApiResult<Target> {
+        let session = self.get_mut(owner, key)?;
+        match (&session.container_id, session.state) {
+            (Some(container_id), SessionState::Running) => Ok(Target {
+                session_id: session.id.clone(),
+                container_id: container_id.clone(),
+                controller_id: session.controller(),
+            }),
+            (_, state) => Err(ApiError::Conflict(format!("Session {} is {:?}", session.id, state))),
+        }
+    }
+
+    /// Sessions that may still hold a container, counted per controller endpoint.
+    pub fn load_by_controller(&self) -> BTreeMap<String, u32> {
+        let mut load = BTreeMap::new();
+        for session in self.by_owner.values().flatten().filter(|s| s.state.holds_container()) {
+            *load.entry(session.controller()).or_insert(0) += 1;
+        }
+        load
+    }
+
+    /// Unique id the controller uses to deduplicate the copies of one
+    /// request sent by each replica.
+    pub fn request_id(&mut self, session_id: &str) -> String {
+        self.next_request += 1;
+        format!("{}-{}", session_id, self.next_request)
+    }
+
+    pub fn sync_base(&self, session_id: &str) -> FileHashes {
+        self.sync_base.get(session_id).cloned().unwrap_or_default()
+    }
+
+    pub fn set_sync_base(&mut self, session_id: &str, hashes: FileHashes) {
+        self.sync_base.insert(session_id.to_string(), hashes);
+    }
+
+    pub fn forget_sync_base(&mut self, session_id: &str) {
+        self.sync_base.remove(session_id);
+    }
+
+    /// Push an active session's expiry `timeout_secs` into the future.
+    pub fn heartbeat(&mut self, owner: Principal, key: &str, now: u64, timeout_secs: u64) -> ApiResult<Session> {
+        let session = self.get_mut(owner, key)?;
+        if !session.state.is_active() {
+            return Err(ApiError::Conflict(format!("Session {} is {:?}", session.id, session.state)));
+        }
+        session.last_heartbeat = now;
+        session.expires_at = expiry(now, timeout_secs).min(session.deadline.unwrap_or(u64::MAX));
+        Ok(session.clone())
+    }
+
+    /// Sessions that may still have a container, counted against the concurrency limit.
+    pub fn active_count(&self, owner: Principal) -> u32 {
+        self.by_owner
+            .get(&owner)
+            .map_or(0, |s| s.iter().filter(|s| s.state.holds_container()).count() as u32)
+    }
+
+    /// Record the time a finished session used today.
+    pub fn charge(&mut self, owner: Principal, started_at: u64, now: u64) {
+        let day = now / NANOS_PER_DAY;
+        let usage = self.usage.entry(owner).or_default();
+        if usage.day != day {
+            *usage = DailyUsage { day, seconds: 0 };
+        }
+        usage.seconds += seconds_today(started_at, now);
+    }
+
+    /// Session seconds used today: finished sessions plus the ones still running.
+    pub fn seconds_used_today(&self, owner: Principal, now: u64) -> u64 {
+        let charged = self
+            .usage
+            .get(&owner)
+            .filter(|u| u.day == now / NANOS_PER_DAY)
+            .map_or(0, |u| u.seconds);
+        let running: u64 = self
+            .by_owner
+            .get(&owner)
+            .map_or(0, |s| {
+                s.iter()
+                    .filter(|s| s.state.holds_container())
+                    .map(|s| seconds_today(s.created_at, now))
+                    .sum()
+            });
+        charged + running
+    }
+
+    /// Seconds active sessions may still run before their deadlines. They
+    /// were promised this time when they started, so it is not free today.
+    pub fn seconds_reserved(&self, owner: Principal, now: u64) -> u64 {
+        self.by_owner.get(&owner).map_or(0, |s| {
+            s.iter()
+                .filter(|s| s.state.holds_container())
+                .filter_map(|s| s.deadline)
+                .map(|deadline| deadline.saturating_sub(now) / NANOS_PER_SEC)
+                .sum()
+        })
+    }
+
+    /// Seconds of today's quota a new session may run for, after the time
+    /// used today and the time active sessions have reserved.
+    pub fn quota_for_new_session(&self, owner: Principal, limits: &SessionLimits, now: u64) -> ApiResult<u64> {
+        profiles::check_quota(
+            limits,
+            self.active_count(owner),
+            self.seconds_used_today(owner, now) + self.seconds_reserved(owner, now),
+        )
+    }
+
+    pub fn list(&self, owner: Principal) -> Vec<Session> {
+        self.by_owner.get(&owner).cloned().unwrap_or_default()
+    }
+
+    /// Active sessions whose expiry has passed, as `(owner, session id)`.
+    pub fn expired(&self, now: u64) -> Vec<(Principal, String)> {
+        self.by_owner
+            .values()
+            .flatten()
+            .filter(|s| s.state == SessionState::Running && s.expires_at <= now)
+            .map(|s| (s.owner, s.id.clone()))
+            .collect()
+    }
+
+    /// Fix up sessions whose controller calls were cut short by an upgrade.
+    /// Background retries live in timers, which do not survive it: a stop that
+    /// never completed is retried by the reaper, and a start that never
+    /// returned a container is marked failed. The controller may still have
+    /// started that container, so the `(controller, start key)` of each failed
+    /// start is returned for a cleanup `/stop`.
+    pub fn recover_after_upgrade(&mut self) -> Vec<(String, String)> {
+        let mut cleanups = Vec::new();
+        for session in self.by_owner.values_mut().flatten() {
+            match session.state {
+                SessionState::Stopping => session.state = SessionState::Running,
+                SessionState::Starting if session.container_id.is_none() => {
+                    session.state = SessionState::Failed;
+                    session.error = Some("Start did not complete before the backend was upgraded".to_string());
+                    cleanups.push((session.controller(), start_key(&session.id, session.created_at)));
+                }
+                _ => {}
+            }
+        }
+        cleanups
+    }
+}
+
+fn prune_finished(sessions: &mut Vec<Session>) {
+    let is_finished = |s: &Session| matches!(s.state, SessionState::Stopped | SessionState::Failed);
+    let mut excess = sessions.iter().filter(|s| is_finished(s)).count().saturating_sub(MAX_FINISHED_PER_OWNER);
+    sessions.retain(|s| {
+        if excess > 0 && is_finished(s) {
+            excess -= 1;
+            return false;
+        }
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: u64 = NANOS_PER_SEC;
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    fn running(registry: &mut SessionRegistry, owner: Principal, container: &str, now: u64) -> String {
+        let id = registry.begin(owner, now, 300);
+        let session = registry.get_mut(owner, &id).unwrap();
+        session.container_id = Some(container.to_string());
+        session.state = SessionState::Running;
+        id
+    }
+
+    #[test]
+    fn only_owner_can_reach_a_session() {
+        let mut registry = SessionRegistry::default();
+        running(&mut registry, user(1), "c1", 0);
+
+        assert!(registry.get_mut(user(1), "c1").is_ok());
+        assert!(matches!(registry.get_mut(user(2), "c1"), Err(ApiError::Unauthorized(_))));
+        assert!(matches!(registry.get_mut(user(2), "c9"), Err(ApiError::NotFound(_))));
+        assert!(registry.list(user(2)).is_empty());
+    }
+
+    #[test]
+    fn heartbeat_extends_expiry() {
+        let mut registry = SessionRegistry::default();
+        let id = running(&mut registry, user(1), "c1", 0);
+        assert_eq!(registry.expired(300 * SEC), vec![(user(1), id.clone())]);
+
+        registry.heartbeat(user(1), "c1", 200 * SEC, 300).unwrap();
+        assert!(registry.expired(300 * SEC).is_empty());
+        assert_eq!(registry.expired(500 * SEC).len(), 1);
+
+        registry.get_mut(user(1), &id).unwrap().state = SessionState::Stopped;
+        assert!(matches!(registry.heartbeat(user(1), &id, 0, 300), Err(ApiError::Conflict(_))));
+    }
+
+    #[test]
+    fn interrupted_sessions_recover_after_upgrade() {
+        let mut registry = SessionRegistry::default();
+        let stopping = running(&mut registry, user(1), "c1", 0);
+        registry.get_mut(user(1), &stopping).unwrap().state = SessionState::Stopping;
+        let starting = registry.begin(user(1), 400 * SEC, 300);
+
+        let cleanups = registry.recover_after_upgrade();
+        assert_eq!(cleanups, vec![(DEFAULT_ENDPOINT.to_string(), format!("start:{}:{}", starting, 400 * SEC))]);
+
+        let state = |registry: &mut SessionRegistry, id: &str| registry.get_mut(user(1), id).unwrap().state;
+        assert_eq!(state(&mut registry, &stopping), SessionState::Running);
+        assert_eq!(state(&mut registry, &starting), SessionState::Failed);
+        // The deadline passed during the upgrade, so the first sweep stops it.
+        assert_eq!(registry.expired(500 * SEC), vec![(user(1), stopping)]);
+    }
+
+    #[test]
+    fn usage_counts_running_and_finished_sessions_today() {
+        let mut registry = SessionRegistry::default();
+        let day = NANOS_PER_DAY;
+        // Started before midnight: only the part after midnight counts today.
+        let first = running(&mut registry, user(1), "c1", day - 60 * SEC);
+        registry.get_mut(user(1), &first).unwrap().state = SessionState::Stopped;
+        registry.charge(user(1), day - 60 * SEC, day + 120 * SEC);
+        running(&mut registry, user(1), "c2", day + 200 * SEC);
+
+        assert_eq!(registry.active_count(user(1)), 1);
+        assert_eq!(registry.seconds_used_today(user(1), day + 500 * SEC), 120 + 300);
+        // Yesterday's charges do not carry over.
+        assert_eq!(registry.seconds_used_today(user(1), 2 * day + 10 * SEC), 10);
+    }
+
+    #[test]
+    fn concurrent_sessions_share_the_daily_quota() {
+        let mut registry = SessionRegistry::default();
+        let limits = SessionLimits { max_concurrent_sessions: 2, daily_minutes: 10 };
+        let now = NANOS_PER_DAY + 1000 * SEC;
+        let start = |registry: &mut SessionRegistry, max_lifetime: u64, now: u64| {
+            let lifetime = registry.quota_for_new_session(user(1), &limits, now)?.min(max_lifetime);
+            let id = running(registry, user(1), "c", now);
+            registry.get_mut(user(1), &id).unwrap().deadline = Some(expiry(now, lifetime));
+            Ok::<_, ApiError>(lifetime)
+        };
+        assert_eq!(start(&mut registry, 240, now), Ok(240));
+        assert_eq!(start(&mut registry, 3600, now + 100 * SEC), Ok(360));
+
+        let deadlines: u64 = registry.list(user(1)).iter().map(|s| (s.deadline.unwrap() - s.created_at) / SEC).sum();
+        assert_eq!(deadlines, limits.daily_minutes * 60);
+        assert!(start(&mut registry, 60, now + 200 * SEC).is_err());
+    }
+
+    #[test]
+    fn finished_sessions_are_capped() {
+        let mut registry = SessionRegistry::default();
+        for i in 0..MAX_FINISHED_PER_OWNER + 5 {
+            let id = running(&mut registry, user(1), &format!("c{}", i), 0);
+            registry.get_mut(user(1), &id).unwrap().state = SessionState::Stopped;
+        }
+        running(&mut registry, user(1), "live", 0);
+        let sessions = registry.list(user(1));
+        assert_eq!(sessions.len(), MAX_FINISHED_PER_OWNER + 1);
+        assert_eq!(sessions.last().unwrap().container_id.as_deref(), Some("live"));
+    }
+}
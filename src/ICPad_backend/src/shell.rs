@@ -0,0 +1,1172 @@
+// Virtual shell behind the IDE terminal.
+//
+// Every user gets a `ShellSession` holding a working directory and
+// environment variables. A command line is tokenized like a POSIX shell
+// (quotes, backslash escapes, `$VAR`), split into pipelines joined by `&&`
+// and `;`, and run command by command. File commands work directly on the
+// project's `FileTree`; anything else is handed to the `Host`, which is how
+// the canister adds `compile`, `deploy`, `dfx` and friends.
+
+use crate::commands;
+use crate::vfs::{self, FileKind, FileTree, ProjectFile};
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ShellSession {
+    /// Project the working directory belongs to.
+    #[serde(default)]
+    pub project_id: Option<String>,
+    /// Relative to the project root, which is "".
+    #[serde(default)]
+    pub cwd: String,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Oldest first, at most `HISTORY_LIMIT` entries.
+    #[serde(default)]
+    pub history: VecDeque<HistoryEntry>,
+    #[serde(default)]
+    next_history_id: u64,
+}
+
+/// Commands kept per user.
+pub const HISTORY_LIMIT: usize = 500;
+/// Longest command line accepted, so the history stays small.
+pub const MAX_COMMAND_BYTES: usize = 4 * 1024;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub command: String,
+    pub project_id: Option<String>,
+    /// Working directory the command started in, e.g. `/src`.
+    pub cwd: String,
+    pub exit_code: i32,
+    pub started_at: u64,
+    pub duration_ns: u64,
+}
+
+/// What one command line did.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TerminalResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+    /// Wall time, including calls to the controller and other canisters.
+    pub duration_ns: u64,
+    /// Project paths created, changed or removed, in path order.
+    pub files_changed: Vec<String>,
+    /// Working directory after the command, e.g. `/src`.
+    pub cwd: String,
+}
+
+impl ShellSession {
+    /// Switching projects starts again at the root; variables are kept.
+    pub fn enter(&mut self, project_id: Option<&str>) {
+        if self.project_id.as_deref() != project_id {
+            self.project_id = project_id.map(str::to_string);
+            self.cwd.clear();
+        }
+    }
+
+    /// Append to the history, dropping the oldest entries past the limit.
+    pub fn remember(&mut self, mut entry: HistoryEntry) {
+        self.next_history_id += 1;
+        entry.id = self.next_history_id;
+        self.history.push_back(entry);
+        while self.history.len() > HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+    }
+
+    /// The latest `limit` entries containing `search`, oldest first.
+    pub fn search_history(&self, search: Option<&str>, limit: usize) -> Vec<HistoryEntry> {
+        let search = search.map(str::to_lowercase).unwrap_or_default();
+        let mut found: Vec<HistoryEntry> = self
+            .history
+            .iter()
+            .rev()
+            .filter(|entry| entry.command.to_lowercase().contains(&search))
+            .take(limit)
+            .cloned()
+            .collect();
+        found.reverse();
+        found
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+impl Output {
+    pub fn ok(stdout: impl Into<String>) -> Self {
+        Output { stdout: stdout.into(), ..Default::default() }
+    }
+
+    pub fn error(exit_code: i32, message: impl Into<String>) -> Self {
+        let mut output = Output { exit_code, ..Default::default() };
+        output.fail(exit_code, message);
+        output
+    }
+
+    /// Report an error and keep going, as `rm a b` does when `a` is missing.
+    fn fail(&mut self, exit_code: i32, message: impl Into<String>) {
+        self.stderr.push_str(&message.into());
+        self.stderr.push('\n');
+        self.exit_code = exit_code;
+    }
+}
+
+/// What the shell needs from the canister.
+pub trait Host {
+    /// The open project's files, `None` when no project is selected.
+    fn files(&mut self) -> Option<&mut FileTree>;
+
+    /// Called after the shell created, changed or removed `path`.
+    fn changed(&mut self, path: &str);
+
+    fn now(&self) -> u64;
+
+    /// Run a command the shell does not implement itself, `None` if there
+    /// is no such command.
+    async fn external(&mut self, argv: &[String], stdin: &str) -> Option<Output>;
+}
+
+// ---------------------------------------------------------------------------
+// Parsing
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Var(String),
+}
+
+/// A word before variable expansion.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Word {
+    parts: Vec<Part>,
+    /// Quoted words survive expanding to nothing, as in `echo ""`.
+    quoted: bool,
+}
+
+impl Word {
+    fn push(&mut self, c: char) {
+        match self.parts.last_mut() {
+            Some(Part::Literal(text)) => text.push(c),
+            _ => self.parts.push(Part::Literal(c.to_string())),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.parts.is_empty() && !self.quoted
+    }
+
+    fn expand(&self, env: &BTreeMap<String, String>) -> Option<String> {
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => text.push_str(literal),
+                Part::Var(name) => text.push_str(env.get(name).map_or("", String::as_str)),
+            }
+        }
+        (self.quoted || !text.is_empty()).then_some(text)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(Word),
+    And,
+    Semi,
+    Pipe,
+    Redirect { append: bool },
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) && name.chars().all(is_name_char)
+}
+
+/// Read a variable reference after `$`: `NAME` or `{NAME}`.
+fn variable(chars: &mut Peekable<Chars>, word: &mut Word) -> Result<(), String> {
+    let mut name = String::new();
+    if chars.peek() == Some(&'{') {
+        chars.next();
+        loop {
+            match chars.next() {
+                Some('}') => break,
+                Some(c) => name.push(c),
+                None => return Err("unterminated ${".to_string()),
+            }
+        }
+        if !is_name(&name) {
+            return Err(format!("${{{}}}: bad substitution", name));
+        }
+    } else {
+        while let Some(&c) = chars.peek().filter(|c| is_name_char(**c)) {
+            name.push(c);
+            chars.next();
+        }
+        if name.is_empty() {
+            word.push('$');
+            return Ok(());
+        }
+    }
+    word.parts.push(Part::Var(name));
+    Ok(())
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut word = Word::default();
+    let mut chars = line.chars().peekable();
+    let finish = |tokens: &mut Vec<Token>, word: &mut Word| {
+        if !word.is_empty() {
+            tokens.push(Token::Word(std::mem::take(word)));
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => finish(&mut tokens, &mut word),
+            '\'' => {
+                word.quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                word.quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some('$') => variable(&mut chars, &mut word)?,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.push(c),
+                None => return Err("unexpected end of line after `\\`".to_string()),
+            },
+            '$' => variable(&mut chars, &mut word)?,
+            '&' if chars.peek() == Some(&'&') => {
+                chars.next();
+                finish(&mut tokens, &mut word);
+                tokens.push(Token::And);
+            }
+            '&' => return Err("background jobs (`&`) are not supported".to_string()),
+            '|' if chars.peek() == Some(&'|') => return Err("`||` is not supported".to_string()),
+            '|' => {
+                finish(&mut tokens, &mut word);
+                tokens.push(Token::Pipe);
+            }
+            ';' => {
+                finish(&mut tokens, &mut word);
+                tokens.push(Token::Semi);
+            }
+            '>' => {
+                finish(&mut tokens, &mut word);
+                let append = chars.next_if_eq(&'>').is_some();
+                tokens.push(Token::Redirect { append });
+            }
+            '<' => return Err("input redirection is not supported".to_string()),
+            c => word.push(c),
+        }
+    }
+    finish(&mut tokens, &mut word);
+    Ok(tokens)
+}
+
+#[derive(Debug, Default)]
+struct Command {
+    words: Vec<Word>,
+    /// Target and whether to append.
+    redirect: Option<(Word, bool)>,
+}
+
+#[derive(Debug, Default)]
+struct Pipeline {
+    commands: Vec<Command>,
+    /// Only run when the previous pipeline succeeded (`&&`).
+    if_ok: bool,
+}
+
+fn syntax_error(token: &str) -> String {
+    format!("syntax error near unexpected token `{}`", token)
+}
+
+fn parse(tokens: Vec<Token>) -> Result<Vec<Pipeline>, String> {
+    let mut pipelines = Vec::new();
+    let mut pipeline = Pipeline::default();
+    let mut command = Command::default();
+    let mut tokens = tokens.into_iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => command.words.push(word),
+            Token::Redirect { append } => match tokens.next() {
+                Some(Token::Word(target)) => command.redirect = Some((target, append)),
+                Some(_) | None => return Err(syntax_error(if append { ">>" } else { ">" })),
+            },
+            Token::Pipe => {
+                if command.words.is_empty() {
+                    return Err(syntax_error("|"));
+                }
+                pipeline.commands.push(std::mem::take(&mut command));
+                if tokens.peek().is_none() {
+                    return Err(syntax_error("|"));
+                }
+            }
+            Token::And | Token::Semi => {
+                let and = token == Token::And;
+                if command.words.is_empty() {
+                    return Err(syntax_error(if and { "&&" } else { ";" }));
+                }
+                pipeline.commands.push(std::mem::take(&mut command));
+                pipelines.push(std::mem::take(&mut pipeline));
+                pipeline.if_ok = and;
+                if and && tokens.peek().is_none() {
+                    return Err(syntax_error("&&"));
+                }
+            }
+        }
+    }
+    if !command.words.is_empty() {
+        pipeline.commands.push(command);
+        pipelines.push(pipeline);
+    } else if command.redirect.is_some() {
+        return Err(syntax_error(">"));
+    }
+    Ok(pipelines)
+}
+
+// ---------------------------------------------------------------------------
+// Execution
+
+/// Run a command line in `session`.
+pub async fn run<H: Host>(session: &mut ShellSession, line: &str, host: &mut H) -> Output {
+    let pipelines = match tokenize(line).and_then(parse) {
+        Ok(pipelines) => pipelines,
+        Err(e) => return Output::error(2, format!("sh: {}", e)),
+    };
+    let mut result = Output::default();
+    for pipeline in pipelines {
+        if pipeline.if_ok && result.exit_code != 0 {
+            continue;
+        }
+        let output = run_pipeline(session, &pipeline, host).await;
+        result.stdout.push_str(&output.stdout);
+        result.stderr.push_str(&output.stderr);
+        result.exit_code = output.exit_code;
+    }
+    result
+}
+
+async fn run_pipeline<H: Host>(session: &mut ShellSession, pipeline: &Pipeline, host: &mut H) -> Output {
+    let mut stdin = String::new();
+    let mut result = Output::default();
+    for command in &pipeline.commands {
+        let argv: Vec<String> = command.words.iter().filter_map(|w| w.expand(&session.env)).collect();
+        let mut output = if argv.is_empty() {
+            Output::default()
+        } else {
+            run_command(session, &argv, &stdin, host).await
+        };
+        if let Some((target, append)) = &command.redirect {
+            let target = target.expand(&session.env).unwrap_or_default();
+            let stdout = std::mem::take(&mut output.stdout);
+            if let Err(e) = redirect(session, host, &target, stdout, *append) {
+                output.fail(1, format!("sh: {}", e));
+            }
+        }
+        result.stderr.push_str(&output.stderr);
+        result.exit_code = output.exit_code;
+        stdin = output.stdout;
+    }
+    result.stdout = stdin;
+    result
+}
+
+async fn run_command<H: Host>(session: &mut ShellSession, argv: &[String], stdin: &str, host: &mut H) -> Output {
+    let (name, args) = (argv[0].as_str(), &argv[1..]);
+    if name != "echo" && args.iter().any(|a| a == "--help") {
+        if let Some(text) = help(name, args) {
+            return Output::ok(text);
+        }
+    }
+    match name {
+        "help" => {
+            let path: Vec<&str> = args.iter().map(String::as_str).collect();
+            return match path.as_slice() {
+                [] => Output::ok(commands::overview()),
+                path => commands::help(path)
+                    .map(Output::ok)
+                    .unwrap_or_else(|| Output::error(1, format!("help: no help topics match `{}`", path.join(" ")))),
+            };
+        }
+        "echo" => return echo(args),
+        "pwd" => return Output::ok(format!("/{}\n", session.cwd)),
+        "env" => return Output::ok(session.env.iter().map(|(k, v)| format!("{}={}\n", k, v)).collect::<String>()),
+        "export" => return export(session, args),
+        "unset" => {
+            for name in args {
+                session.env.remove(name);
+            }
+            return Output::default();
+        }
+        // `NAME=value` on its own sets a variable.
+        _ if argv.len() == 1 && name.split_once('=').is_some_and(|(n, _)| is_name(n)) => return export(session, argv),
+        _ => {}
+    }
+    if !commands::is_shell_command(name) {
+        return host
+            .external(argv, stdin)
+            .await
+            .unwrap_or_else(|| Output::error(127, format!("{}: command not found", name)));
+    }
+
+    let now = host.now();
+    let mut changed = Vec::new();
+    let Some(files) = host.files() else {
+        return Output::error(1, format!("{}: no project selected", name));
+    };
+    let cwd = session.cwd.clone();
+    let output = match name {
+        "ls" => ls(files, &cwd, args),
+        "cd" => cd(files, session, args),
+        "cat" => cat(files, &cwd, args, stdin),
+        "mkdir" => mkdir(files, &cwd, args, now, &mut changed),
+        "rm" => rm(files, &cwd, args, &mut changed),
+        "mv" => mv(files, &cwd, args, now, &mut changed),
+        "cp" => cp(files, &cwd, args, now, &mut changed),
+        "grep" => grep(files, &cwd, args, stdin),
+        "head" => head_tail(files, &cwd, "head", args, stdin),
+        "tail" => head_tail(files, &cwd, "tail", args, stdin),
+        "wc" => wc(files, &cwd, args, stdin),
+        _ => unreachable!("every builtin is matched"),
+    };
+    for path in changed {
+        host.changed(&path);
+    }
+    output
+}
+
+fn redirect<H: Host>(session: &ShellSession, host: &mut H, target: &str, text: String, append: bool) -> Result<(), String> {
+    let now = host.now();
+    let files = host.files().ok_or("no project selected")?;
+    let path = resolve(&session.cwd, target);
+    match files.get(&path) {
+        _ if path.is_empty() => return Err(format!("{}: Is a directory", target)),
+        Some(entry) if entry.kind == FileKind::Directory => return Err(format!("{}: Is a directory", target)),
+        Some(entry) => {
+            let content = if append { format!("{}{}", entry.content, text) } else { text };
+            vfs::write_file(files, &path, content, now).map_err(|e| e.to_string())?;
+        }
+        None => {
+            if !is_dir(files, vfs::parent_path(&path)) {
+                return Err(format!("{}: No such file or directory", target));
+            }
+            vfs::create_file(files, &path, text, now).map_err(|e| e.to_string())?;
+        }
+    }
+    host.changed(&path);
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Paths
+
+/// Resolve `path` against the working directory. The result is a project
+/// path; `..` stops at the root.
+pub fn resolve(cwd: &str, path: &str) -> String {
+    let (mut segments, rest): (Vec<&str>, &str) = if path == "~" || path.starts_with("~/") {
+        (Vec::new(), &path[1..])
+    } else if path.starts_with('/') {
+        (Vec::new(), path)
+    } else {
+        (cwd.split('/').filter(|s| !s.is_empty()).collect(), path)
+    };
+    for segment in rest.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+    segments.join("/")
+}
+
+fn is_dir(files: &FileTree, path: &str) -> bool {
+    path.is_empty() || files.get(path).is_some_and(|e| e.kind == FileKind::Directory)
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// `path` as the user would type it from `cwd`.
+fn relative(cwd: &str, path: &str) -> String {
+    if cwd.is_empty() {
+        return path.to_string();
+    }
+    match path.strip_prefix(cwd).and_then(|rest| rest.strip_prefix('/')) {
+        Some(rest) => rest.to_string(),
+        None => format!("/{}", path),
+    }
+}
+
+/// Split leading `-abc` flags from operands; `--` ends the flags.
+fn flags(args: &[String]) -> (BTreeSet<char>, Vec<String>) {
+    let mut flags = BTreeSet::new();
+    let mut rest = args.iter();
+    for arg in rest.by_ref() {
+        if arg == "--" {
+            break;
+        }
+        match arg.strip_prefix('-') {
+            Some(letters) if !letters.is_empty() => flags.extend(letters.chars()),
+            _ => return (flags, std::iter::once(arg).chain(rest).cloned().collect()),
+        }
+    }
+    (flags, rest.cloned().collect())
+}
+
+/// Help for `name` and the subcommands at the start of `args`.
+fn help(name: &str, args: &[String]) -> Option<String> {
+    let mut path = vec![name];
+    let mut spec = commands::find(name)?;
+    for arg in args {
+        let Some(sub) = spec.subcommand(arg) else { break };
+        path.push(arg);
+        spec = sub;
+    }
+    commands::help(&path)
+}
+
+fn unknown_flag(command: &str, flags: &BTreeSet<char>) -> Option<Output> {
+    let spec = commands::find(command)?;
+    flags
+        .iter()
+        .find(|f| !spec.accepts_flag(**f))
+        .map(|f| Output::error(2, format!("{}: invalid option -- '{}'", command, f)))
+}
+
+/// Inputs of a text command: the named files, or stdin when there are none.
+fn inputs(files: &FileTree, cwd: &str, command: &str, operands: &[String], stdin: &str, output: &mut Output) -> Vec<(String, String)> {
+    if operands.is_empty() {
+        return vec![(String::new(), stdin.to_string())];
+    }
+    let mut result = Vec::new();
+    for operand in operands {
+        match read(files, cwd, operand) {
+            Ok(content) => result.push((operand.clone(), content)),
+            Err(e) => output.fail(1, format!("{}: {}", command, e)),
+        }
+    }
+    result
+}
+
+fn read(files: &FileTree, cwd: &str, operand: &str) -> Result<String, String> {
+    let path = resolve(cwd, operand);
+    match files.get(&path) {
+        Some(entry) if entry.kind == FileKind::File => Ok(entry.content.clone()),
+        _ if is_dir(files, &path) => Err(format!("{}: Is a directory", operand)),
+        _ => Err(format!("{}: No such file or directory", operand)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Builtins
+
+fn echo(args: &[String]) -> Output {
+    let (newline, args) = match args.first() {
+        Some(flag) if flag == "-n" => (false, &args[1..]),
+        _ => (true, args),
+    };
+    let mut text = args.join(" ");
+    if newline {
+        text.push('\n');
+    }
+    Output::ok(text)
+}
+
+fn export(session: &mut ShellSession, args: &[String]) -> Output {
+    let mut output = Output::default();
+    for arg in args {
+        let (name, value) = arg.split_once('=').unwrap_or((arg, ""));
+        if !is_name(name) {
+            output.fail(1, format!("export: `{}`: not a valid identifier", arg));
+        } else if arg.contains('=') {
+            session.env.insert(name.to_string(), value.to_string());
+        }
+    }
+    output
+}
+
+fn ls(files: &FileTree, cwd: &str, args: &[String]) -> Output {
+    let (flags, operands) = flags(args);
+    if let Some(error) = unknown_flag("ls", &flags) {
+        return error;
+    }
+    let (all, long) = (flags.contains(&'a'), flags.contains(&'l'));
+    let line = |entry: &ProjectFile, name: &str| {
+        let suffix = if entry.kind == FileKind::Directory { "/" } else { "" };
+        if long {
+            let (kind, size) = match entry.kind {
+                FileKind::Directory => ('d', "-".to_string()),
+                FileKind::File => ('-', entry.content.len().to_string()),
+            };
+            format!("{} {:>8} {}{}\n", kind, size, name, suffix)
+        } else {
+            format!("{}{}\n", name, suffix)
+        }
+    };
+    let operands = if operands.is_empty() { vec![".".to_string()] } else { operands };
+
+    let mut output = Output::default();
+    for (i, operand) in operands.iter().enumerate() {
+        let path = resolve(cwd, operand);
+        if is_dir(files, &path) {
+            if operands.len() > 1 {
+                output.stdout.push_str(&format!("{}{}:\n", if i > 0 { "\n" } else { "" }, operand));
+            }
+            for entry in vfs::list_directory(files, &path).unwrap_or_default() {
+                let name = vfs::file_name(&entry.path);
+                if all || !name.starts_with('.') {
+                    output.stdout.push_str(&line(entry, name));
+                }
+            }
+        } else if let Some(entry) = files.get(&path) {
+            output.stdout.push_str(&line(entry, operand));
+        } else {
+            output.fail(2, format!("ls: cannot access '{}': No such file or directory", operand));
+        }
+    }
+    output
+}
+
+fn cd(files: &FileTree, session: &mut ShellSession, args: &[String]) -> Output {
+    let target = match args {
+        [] => "/",
+        [target] => target.as_str(),
+        _ => return Output::error(1, "cd: too many arguments"),
+    };
+    let path = resolve(&session.cwd, target);
+    if is_dir(files, &path) {
+        session.cwd = path;
+        Output::default()
+    } else if files.contains_key(&path) {
+        Output::error(1, format!("cd: {}: Not a directory", target))
+    } else {
+        Output::error(1, format!("cd: {}: No such file or directory", target))
+    }
+}
+
+fn cat(files: &FileTree, cwd: &str, args: &[String], stdin: &str) -> Output {
+    let mut output = Output::default();
+    for (_, content) in inputs(files, cwd, "cat", args, stdin, &mut output) {
+        output.stdout.push_str(&content);
+    }
+    output
+}
+
+fn mkdir(files: &mut FileTree, cwd: &str, args: &[String], now: u64, changed: &mut Vec<String>) -> Output {
+    let (flags, operands) = flags(args);
+    if let Some(error) = unknown_flag("mkdir", &flags) {
+        return error;
+    }
+    if operands.is_empty() {
+        return Output::error(1, "mkdir: missing operand");
+    }
+    let parents = flags.contains(&'p');
+    let mut output = Output::default();
+    for operand in &operands {
+        let path = resolve(cwd, operand);
+        if path.is_empty() || files.contains_key(&path) {
+            if !(parents && is_dir(files, &path)) {
+                output.fail(1, format!("mkdir: cannot create directory '{}': File exists", operand));
+            }
+        } else if !parents && !is_dir(files, vfs::parent_path(&path)) {
+            output.fail(1, format!("mkdir: cannot create directory '{}': No such file or directory", operand));
+        } else {
+            match vfs::create_directory(files, &path, now) {
+                Ok(()) => changed.push(path),
+                Err(e) => output.fail(1, format!("mkdir: {}", e)),
+            }
+        }
+    }
+    output
+}
+
+fn rm(files: &mut FileTree, cwd: &str, args: &[String], changed: &mut Vec<String>) -> Output {
+    let (flags, operands) = flags(args);
+    if let Some(error) = unknown_flag("rm", &flags) {
+        return error;
+    }
+    let (recursive, force) = (flags.contains(&'r') || flags.contains(&'R'), flags.contains(&'f'));
+    if operands.is_empty() && !force {
+        return Output::error(1, "rm: missing operand");
+    }
+    let mut output = Output::default();
+    for operand in &operands {
+        let path = resolve(cwd, operand);
+        match files.get(&path) {
+            _ if path.is_empty() => output.fail(1, "rm: refusing to remove '/'"),
+            None if force => {}
+            None => output.fail(1, format!("rm: cannot remove '{}': No such file or directory", operand)),
+            Some(entry) if entry.kind == FileKind::Directory && !recursive => {
+                output.fail(1, format!("rm: cannot remove '{}': Is a directory", operand))
+            }
+            Some(_) => match vfs::delete_entry(files, &path) {
+                Ok(_) => changed.push(path),
+                Err(e) => output.fail(1, format!("rm: {}", e)),
+            },
+        }
+    }
+    output
+}
+
+/// Where each source of `mv`/`cp` goes: into `dest` when it is a directory,
+/// otherwise `dest` itself, which then only takes one source.
+fn destinations(files: &FileTree, cwd: &str, command: &str, operands: &[String]) -> Result<Vec<(String, String)>, Output> {
+    let Some((dest, sources)) = operands.split_last().filter(|(_, sources)| !sources.is_empty()) else {
+        return Err(Output::error(1, format!("{}: missing destination file operand", command)));
+    };
+    let dest_path = resolve(cwd, dest);
+    if is_dir(files, &dest_path) {
+        return Ok(sources
+            .iter()
+            .map(|source| {
+                let from = resolve(cwd, source);
+                let to = join(&dest_path, vfs::file_name(&from));
+                (from, to)
+            })
+            .collect());
+    }
+    if sources.len() > 1 {
+        return Err(Output::error(1, format!("{}: target '{}' is not a directory", command, dest)));
+    }
+    if !is_dir(files, vfs::parent_path(&dest_path)) {
+        return Err(Output::error(1, format!("{}: cannot create '{}': No such file or directory", command, dest)));
+    }
+    Ok(vec![(resolve(cwd, &sources[0]), dest_path)])
+}
+
+fn mv(files: &mut FileTree, cwd: &str, args: &[String], now: u64, changed: &mut Vec<String>) -> Output {
+    let (flags, operands) = flags(args);
+    if let Some(error) = unknown_flag("mv", &flags) {
+        return error;
+    }
+    let moves = match destinations(files, cwd, "mv", &operands) {
+        Ok(moves) => moves,
+        Err(error) => return error,
+    };
+    let mut output = Output::default();
+    for (from, to) in moves {
+        let Some(kind) = files.get(&from).filter(|_| !from.is_empty()).map(|e| e.kind) else {
+            output.fail(1, format!("mv: cannot move '/{}': No such file or directory", from));
+            continue;
+        };
+        if from == to {
+            continue;
+        }
+        match files.get(&to) {
+            Some(target) if target.kind == FileKind::Directory => {
+                output.fail(1, format!("mv: cannot overwrite directory '/{}'", to));
+                continue;
+            }
+            Some(_) if kind == FileKind::Directory => {
+                output.fail(1, format!("mv: cannot overwrite non-directory '/{}' with directory", to));
+                continue;
+            }
+            Some(_) => {
+                let _ = vfs::delete_entry(files, &to);
+            }
+            None => {}
+        }
+        match vfs::move_entry(files, &from, &to, now) {
+            Ok(()) => changed.extend([from, to]),
+            Err(e) => output.fail(1, format!("mv: {}", e)),
+        }
+    }
+    output
+}
+
+fn copy_file(files: &mut FileTree, content: String, to: &str, now: u64) -> Result<(), String> {
+    match files.get(to) {
+        Some(entry) if entry.kind == FileKind::Directory => Err(format!("cannot overwrite directory '/{}' with non-directory", to)),
+        Some(_) => vfs::write_file(files, to, content, now).map_err(|e| e.to_string()),
+        None => vfs::create_file(files, to, content, now).map_err(|e| e.to_string()),
+    }
+}
+
+fn cp(files: &mut FileTree, cwd: &str, args: &[String], now: u64, changed: &mut Vec<String>) -> Output {
+    let (flags, operands) = flags(args);
+    if let Some(error) = unknown_flag("cp", &flags) {
+        return error;
+    }
+    let recursive = flags.contains(&'r') || flags.contains(&'R');
+    let copies = match destinations(files, cwd, "cp", &operands) {
+        Ok(copies) => copies,
+        Err(error) => return error,
+    };
+    let mut output = Output::default();
+    for (from, to) in copies {
+        let Some(source) = files.get(&from).cloned().filter(|_| !from.is_empty()) else {
+            if from.is_empty() && recursive {
+                output.fail(1, "cp: cannot copy the project root");
+            } else {
+                output.fail(1, format!("cp: cannot stat '/{}': No such file or directory", from));
+            }
+            continue;
+        };
+        if source.kind == FileKind::File {
+            match copy_file(files, source.content, &to, now) {
+                Ok(()) => changed.push(to),
+                Err(e) => output.fail(1, format!("cp: {}", e)),
+            }
+            continue;
+        }
+        if !recursive {
+            output.fail(1, format!("cp: -r not specified; omitting directory '/{}'", from));
+            continue;
+        }
+        if to == from || to.starts_with(&format!("{}/", from)) {
+            output.fail(1, format!("cp: cannot copy a directory, '/{}', into itself, '/{}'", from, to));
+            continue;
+        }
+        // Start at `from/`: siblings like `from-old` sort between `from` and its children.
+        let prefix = format!("{}/", from);
+        let children = files.range(prefix.clone()..).take_while(|(path, _)| path.starts_with(&prefix));
+        let entries: Vec<ProjectFile> = files.get(&from).into_iter().chain(children.map(|(_, entry)| entry)).cloned().collect();
+        for entry in entries {
+            let target = format!("{}{}", to, &entry.path[from.len()..]);
+            let result = match entry.kind {
+                FileKind::Directory if is_dir(files, &target) => Ok(()),
+                FileKind::Directory => vfs::create_directory(files, &target, now).map_err(|e| e.to_string()),
+                FileKind::File => copy_file(files, entry.content, &target, now),
+            };
+            if let Err(e) = result {
+                output.fail(1, format!("cp: {}", e));
+                break;
+            }
+        }
+        changed.push(to);
+    }
+    output
+}
+
+fn grep(files: &FileTree, cwd: &str, args: &[String], stdin: &str) -> Output {
+    let (flags, operands) = flags(args);
+    if let Some(error) = unknown_flag("grep", &flags) {
+        return error;
+    }
+    let Some((pattern, operands)) = operands.split_first() else {
+        return Output::error(2, "usage: grep [-inrvcl] PATTERN [FILE...]");
+    };
+    let has = |c| flags.contains(&c);
+    let (ignore_case, recursive) = (has('i'), has('r') || has('R'));
+    let pattern = if ignore_case { pattern.to_lowercase() } else { pattern.clone() };
+    let matches = |line: &str| {
+        let found = if ignore_case { line.to_lowercase().contains(&pattern) } else { line.contains(&pattern) };
+        found != has('v')
+    };
+
+    let mut output = Output::default();
+    let mut sources = Vec::new();
+    let operands = if operands.is_empty() && recursive { vec![".".to_string()] } else { operands.to_vec() };
+    if operands.is_empty() {
+        sources.push((String::new(), stdin.to_string()));
+    }
+    for operand in &operands {
+        let path = resolve(cwd, operand);
+        if is_dir(files, &path) {
+            if !recursive {
+                output.fail(2, format!("grep: {}: Is a directory", operand));
+                continue;
+            }
+            sources.extend(
+                files
+                    .values()
+                    .filter(|e| e.kind == FileKind::File && (path.is_empty() || e.path.starts_with(&format!("{}/", path))))
+                    .map(|e| (relative(cwd, &e.path), e.content.clone())),
+            );
+        } else {
+            match read(files, cwd, operand) {
+                Ok(content) => sources.push((operand.clone(), content)),
+                Err(e) => output.fail(2, format!("grep: {}", e)),
+            }
+        }
+    }
+
+    let prefix = sources.len() > 1 || recursive;
+    let mut matched = false;
+    for (name, content) in &sources {
+        let hits: Vec<(usize, &str)> = content.lines().enumerate().filter(|(_, line)| matches(line)).collect();
+        matched |= !hits.is_empty();
+        if has('l') {
+            if !hits.is_empty() {
+                output.stdout.push_str(&format!("{}\n", if name.is_empty() { "(standard input)" } else { name }));
+            }
+        } else if has('c') {
+            let count = hits.len();
+            output.stdout.push_str(&if prefix { format!("{}:{}\n", name, count) } else { format!("{}\n", count) });
+        } else {
+            for (number, line) in hits {
+                if prefix {
+                    output.stdout.push_str(&format!("{}:", name));
+                }
+                if has('n') {
+                    output.stdout.push_str(&format!("{}:", number + 1));
+                }
+                output.stdout.push_str(line);
+                output.stdout.push('\n');
+            }
+        }
+    }
+    output.exit_code = if matched { 0 } else if output.exit_code != 0 { 2 } else { 1 };
+    output
+}
+
+fn head_tail(files: &FileTree, cwd: &str, command: &str, args: &[String], stdin: &str) -> Output {
+    let mut count = 10usize;
+    let mut operands = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "-n" => match args.next() {
+                Some(value) => value.as_str(),
+                None => return Output::error(1, format!("{}: option requires an argument -- 'n'", command)),
+            },
+            _ if arg.starts_with("-n") => &arg[2..],
+            _ if arg.len() > 1 && arg.starts_with('-') => &arg[1..],
+            _ => {
+                operands.push(arg.clone());
+                continue;
+            }
+        };
+        count = match value.parse() {
+            Ok(count) => count,
+            Err(_) => return Output::error(1, format!("{}: invalid number of lines: '{}'", command, value)),
+        };
+    }
+
+    let mut output = Output::default();
+    let sources = inputs(files, cwd, command, &operands, stdin, &mut output);
+    let headers = sources.len() > 1;
+    for (i, (name, content)) in sources.iter().enumerate() {
+        if headers {
+            output.stdout.push_str(&format!("{}==> {} <==\n", if i > 0 { "\n" } else { "" }, name));
+        }
+        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+        let selected = if command == "head" {
+            &lines[..count.min(lines.len())]
+        } else {
+            &lines[lines.len().saturating_sub(count)..]
+        };
+        output.stdout.push_str(&selected.concat());
+    }
+    output
+}
+
+fn wc(files: &FileTree, cwd: &str, args: &[String], stdin: &str) -> Output {
+    let (flags, operands) = flags(args);
+    if let Some(error) = unknown_flag("wc", &flags) {
+        return error;
+    }
+    let all = flags.is_empty();
+    let columns: Vec<usize> = [('l', 0), ('w', 1), ('c', 2)]
+        .iter()
+        .filter(|(flag, _)| all || flags.contains(flag))
+        .map(|(_, column)| *column)
+        .collect();
+    let line = |counts: [usize; 3], name: &str| {
+        let mut fields: Vec<String> = columns.iter().map(|c| format!("{:>7}", counts[*c])).collect();
+        if !name.is_empty() {
+            fields.push(name.to_string());
+        }
+        format!("{}\n", fields.join(" "))
+    };
+
+    let mut output = Output::default();
+    let mut total = [0; 3];
+    let sources = inputs(files, cwd, "wc", &operands, stdin, &mut output);
+    for (name, content) in &sources {
+        let counts = [content.matches('\n').count(), content.split_whitespace().count(), content.len()];
+        for (sum, count) in total.iter_mut().zip(counts) {
+            *sum += count;
+        }
+        output.stdout.push_str(&line(counts, name));
+    }
+    if sources.len() > 1 {
+        output.stdout.push_str(&line(total, "total"));
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestHost {
+        files: FileTree,
+        changed: BTreeSet<String>,
+    }
+
+    impl Host for TestHost {
+        fn files(&mut self) -> Option<&mut FileTree> {
+            Some(&mut self.files)
+        }
+
+        fn changed(&mut self, path: &str) {
+            self.changed.insert(path.to_string());
+        }
+
+        fn now(&self) -> u64 {
+            7
+        }
+
+        async fn external(&mut self, argv: &[String], stdin: &str) -> Option<Output> {
+            (argv[0] == "upper").then(|| Output::ok(stdin.to_uppercase()))
+        }
+    }
+
+    /// Every future here completes without waiting.
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match future.as_mut().poll(&mut context) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("shell future did not complete"),
+        }
+    }
+
+    fn host() -> TestHost {
+        let mut files = vfs::single_file_tree("rust", "fn a() {}\n// TODO one\nfn b() {}\n".to_string(), 0);
+        vfs::create_file(&mut files, "README.md", "todo: docs\n".to_string(), 0).unwrap();
+        TestHost { files, changed: BTreeSet::new() }
+    }
+
+    fn sh(session: &mut ShellSession, host: &mut TestHost, line: &str) -> Output {
+        block_on(run(session, line, host))
+    }
+
+    #[test]
+    fn tokenizes_quotes_escapes_and_variables() {
+        let mut session = ShellSession::default();
+        session.env.insert("NAME".to_string(), "Ada Lovelace".to_string());
+        let mut host = host();
+        let out = sh(&mut session, &mut host, r#"echo 'single $NAME' "double $NAME" \$NAME ${NAME}! a\ b "" $MISSING"#);
+        assert_eq!(out.stdout, "single $NAME double Ada Lovelace $NAME Ada Lovelace! a b \n");
+
+        let out = sh(&mut session, &mut host, "export GREETING=hi && echo $GREETING; unset GREETING; echo \"[$GREETING]\"");
+        assert_eq!(out.stdout, "hi\n[]\n");
+        assert_eq!(sh(&mut session, &mut host, "echo 'open").exit_code, 2);
+        assert_eq!(sh(&mut session, &mut host, "echo a &&").stderr, "sh: syntax error near unexpected token `&&`\n");
+    }
+
+    #[test]
+    fn working_directory_and_file_commands() {
+        let mut session = ShellSession::default();
+        let mut host = host();
+        assert_eq!(sh(&mut session, &mut host, "cd src && pwd && ls").stdout, "/src\nlib.rs\n");
+        assert_eq!(sh(&mut session, &mut host, "cd ../missing").exit_code, 1);
+        assert_eq!(session.cwd, "src");
+
+        let out = sh(&mut session, &mut host, "mkdir -p ../docs/notes && cp lib.rs ../docs && mv ../docs/lib.rs ../docs/notes/copy.rs && ls -R");
+        assert_eq!(out.stderr, "ls: invalid option -- 'R'\n");
+        assert_eq!(host.files["docs/notes/copy.rs"].content, host.files["src/lib.rs"].content);
+        assert!(!host.files.contains_key("docs/lib.rs"));
+
+        assert_eq!(sh(&mut session, &mut host, "rm ../docs").stderr, "rm: cannot remove '../docs': Is a directory\n");
+        sh(&mut session, &mut host, "cp -r ../docs /backup && rm -r ../docs");
+        assert!(!host.files.contains_key("docs"));
+        assert!(host.files.contains_key("backup/notes/copy.rs"));
+        assert!(host.changed.contains("docs") && host.changed.contains("backup"));
+
+        assert_eq!(sh(&mut session, &mut host, "ls /missing /backup").exit_code, 2);
+
+        // A sibling sharing the directory's name as a prefix sorts before its children.
+        sh(&mut session, &mut host, "mkdir /src-old && echo old > /src-old/x.rs && cp -r /src /dest");
+        assert_eq!(host.files["dest/lib.rs"].content, host.files["src/lib.rs"].content);
+        assert!(!host.files.contains_key("dest-old") && !host.files.contains_key("dest/x.rs"));
+        sh(&mut session, &mut host, "rm -r /src-old /dest");
+        assert_eq!(sh(&mut session, &mut host, "ls -l /").stdout, "-       11 README.md\nd        - backup/\nd        - src/\n");
+    }
+
+    #[test]
+    fn pipes_redirects_and_text_commands() {
+        let mut session = ShellSession::default();
+        let mut host = host();
+        let out = sh(&mut session, &mut host, "grep -in todo src/lib.rs README.md | upper > todos.txt; cat todos.txt | wc -l");
+        assert_eq!(out.stdout, "      2\n");
+        assert_eq!(host.files["todos.txt"].content, "SRC/LIB.RS:2:// TODO ONE\nREADME.MD:1:TODO: DOCS\n");
+        assert!(host.changed.contains("todos.txt"));
+
+        sh(&mut session, &mut host, "echo -n more >> todos.txt");
+        assert!(host.files["todos.txt"].content.ends_with("DOCS\nmore"));
+        assert_eq!(sh(&mut session, &mut host, "head -n 1 src/lib.rs && tail -1 src/lib.rs").stdout, "fn a() {}\nfn b() {}\n");
+        assert_eq!(sh(&mut session, &mut host, "grep -rc fn").stdout, "README.md:0\nsrc/lib.rs:2\ntodos.txt:0\n");
+        assert_eq!(sh(&mut session, &mut host, "grep nothing README.md && echo found").exit_code, 1);
+        assert_eq!(sh(&mut session, &mut host, "wc README.md src/lib.rs").stdout, "      1       2      11 README.md\n      3       9      32 src/lib.rs\n      4      11      43 total\n");
+        assert_eq!(sh(&mut session, &mut host, "nope").exit_code, 127);
+        assert!(sh(&mut session, &mut host, "rust build --help").stdout.starts_with("rust build - "));
+        assert_eq!(sh(&mut session, &mut host, "help wc").stdout, sh(&mut session, &mut host, "wc --help").stdout);
+    }
+
+    #[test]
+    fn history_is_bounded_and_searchable() {
+        let mut session = ShellSession::default();
+        let entry = |command: String| HistoryEntry {
+            id: 0,
+            command,
+            project_id: None,
+            cwd: String::new(),
+            exit_code: 0,
+            started_at: 0,
+            duration_ns: 0,
+        };
+        for i in 0..HISTORY_LIMIT + 5 {
+            session.remember(entry(format!("echo {}", i)));
+        }
+        session.remember(entry("Grep TODO src".to_string()));
+        assert_eq!(session.history.len(), HISTORY_LIMIT);
+        assert_eq!(session.history[0].command, "echo 6");
+
+        let latest: Vec<_> = session.search_history(None, 2).into_iter().map(|e| (e.id, e.command)).collect();
+        let last_id = HISTORY_LIMIT as u64 + 6;
+        assert_eq!(latest, vec![(last_id - 1, format!("echo {}", HISTORY_LIMIT + 4)), (last_id, "Grep TODO src".to_string())]);
+        assert_eq!(session.search_history(Some("todo"), 10).len(), 1);
+        assert_eq!(session.search_history(Some("echo 49"), 20).len(), 11);
+    }
+}
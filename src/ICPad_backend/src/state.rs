@@ -0,0 +1,169 @@
+// Upgrade persistence for the backend heap state.
+//
+// Everything that must survive a `dfx deploy` is copied into a `StableState`
+// in `pre_upgrade`, written to stable memory as `(version, json bytes)` and
+// restored in `post_upgrade`. New fields should be added with
+// `#[serde(default)]`; bump `STATE_VERSION` and add a migration arm in
+// `decode` when an existing field changes shape.
+
+use crate::config::{BackendConfig, CONFIG};
+use crate::sessions::SessionRegistry;
+use crate::shell::ShellSession;
+use crate::{env, history::ProjectHistory, Project, HISTORY, NEXT_PROJECT_ID, PROJECTS, SESSIONS, SHELLS};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+
+pub const STATE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct StableState {
+    #[serde(default)]
+    pub projects: Vec<Project>,
+    #[serde(default)]
+    pub sessions: SessionRegistry,
+    #[serde(default)]
+    pub history: Vec<(String, ProjectHistory)>,
+    #[serde(default)]
+    pub config: BackendConfig,
+    #[serde(default)]
+    pub shells: Vec<(Principal, ShellSession)>,
+    /// Counter behind project ids.
+    #[serde(default)]
+    pub next_project_id: u64,
+}
+
+/// Copy the live thread-local state into a serializable snapshot.
+pub fn snapshot() -> StableState {
+    StableState {
+        projects: PROJECTS.with(|p| p.borrow().values().cloned().collect()),
+        sessions: SESSIONS.with(|s| s.borrow().clone()),
+        history: HISTORY.with(|h| {
+            h.borrow()
+                .iter()
+                .map(|(project_id, history)| (project_id.clone(), history.clone()))
+                .collect()
+        }),
+        config: CONFIG.with(|c| c.borrow().clone()),
+        shells: SHELLS.with(|s| s.borrow().iter().map(|(user, shell)| (*user, shell.clone())).collect()),
+        next_project_id: NEXT_PROJECT_ID.with(|next| next.get()),
+    }
+}
+
+/// Replace the live thread-local state with a snapshot.
+pub fn restore(state: StableState) {
+    PROJECTS.with(|p| {
+        *p.borrow_mut() = state
+            .projects
+            .into_iter()
+            .map(|project| (project.id.clone(), project))
+            .collect();
+    });
+    SESSIONS.with(|s| {
+        *s.borrow_mut() = state.sessions;
+    });
+    HISTORY.with(|h| {
+        *h.borrow_mut() = state.history.into_iter().collect();
+    });
+    CONFIG.with(|c| {
+        *c.borrow_mut() = state.config;
+    });
+    SHELLS.with(|s| {
+        *s.borrow_mut() = state.shells.into_iter().collect();
+    });
+    NEXT_PROJECT_ID.with(|next| next.set(state.next_project_id.max(1)));
+}
+
+pub fn encode(state: &StableState) -> Result<Vec<u8>, String> {
+    serde_json::to_vec(state).map_err(|e| format!("Failed to encode state: {}", e))
+}
+
+pub fn decode(version: u32, bytes: &[u8]) -> Result<StableState, String> {
+    match version {
+        1 => serde_json::from_slice(bytes).map_err(|e| format!("Failed to decode state v1: {}", e)),
+        v => Err(format!("Unsupported stable state version: {}", v)),
+    }
+}
+
+pub fn save_to_stable_memory() {
+    let bytes = encode(&snapshot()).unwrap_or_else(|e| ic_cdk::trap(&e));
+    env::stable_save(STATE_VERSION, bytes).unwrap_or_else(|e| ic_cdk::trap(format!("Failed to write stable memory: {}", e)));
+}
+
+pub fn load_from_stable_memory() {
+    let saved = env::stable_restore().unwrap_or_else(|e| ic_cdk::trap(format!("Failed to read stable memory: {}", e)));
+    // A fresh install has nothing to restore.
+    let Some((version, bytes)) = saved else {
+        return;
+    };
+    let state = decode(version, &bytes).unwrap_or_else(|e| ic_cdk::trap(&e));
+    restore(state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_project, list_projects_v2, next_project_id, post_upgrade, pre_upgrade};
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    fn owner() -> Principal {
+        Principal::from_slice(&[1, 2, 3])
+    }
+
+    // `create_project` never awaits, so one poll completes it.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        match pin!(future).as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future did not complete"),
+        }
+    }
+
+    fn listed() -> Vec<serde_json::Value> {
+        let mut projects: Vec<serde_json::Value> = list_projects_v2()
+            .unwrap()
+            .iter()
+            .map(|p| serde_json::to_value(p).unwrap())
+            .collect();
+        projects.sort_by_key(|p| p["id"].as_str().unwrap().to_string());
+        projects
+    }
+
+    // Drops the heap state, as installing the new wasm does.
+    fn clear_heap() {
+        restore(StableState::default());
+        NEXT_PROJECT_ID.with(|next| next.set(1));
+    }
+
+    #[test]
+    fn projects_survive_upgrade() {
+        env::set_caller(owner());
+        env::set_time(1);
+        let first = block_on(create_project("One".into(), "rust".into(), "fn main() {}".into())).unwrap();
+        let second = block_on(create_project("Two".into(), "motoko".into(), String::new())).unwrap();
+        SESSIONS.with(|s| s.borrow_mut().begin(owner(), 1, 300));
+        let before = listed();
+        assert_eq!(before.len(), 2);
+
+        pre_upgrade();
+        clear_heap();
+        assert!(listed().is_empty());
+        post_upgrade();
+
+        assert_eq!(listed(), before);
+        assert_eq!(before[0]["id"], first.as_str());
+        assert_eq!(before[1]["id"], second.as_str());
+        let sessions = SESSIONS.with(|s| s.borrow().list(owner()));
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].expires_at, 1 + 300 * 1_000_000_000);
+        // The start was in flight during the upgrade, so it is written off.
+        assert_eq!(sessions[0].state, crate::sessions::SessionState::Failed);
+        // Ids keep counting after an upgrade instead of reusing `proj_1`.
+        assert_eq!(next_project_id(), "proj_3");
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        assert!(decode(STATE_VERSION + 1, b"{}").is_err());
+    }
+}
@@ -0,0 +1,240 @@
+// Moving project files between the canister and a session container.
+//
+// A session starts with a copy of the project's files. The hashes of what the
+// container was last given (or last pulled from it) are kept as the sync base,
+// so a pull can tell apart files changed in the container, files changed in
+// the browser meanwhile, and files changed on both sides.
+
+use crate::error::{ApiError, ApiResult};
+use crate::profiles::SessionProfile;
+use crate::vfs::{self, FileKind, FileTree};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+pub const PULL_PATH: &str = "/files/pull";
+
+/// HTTP outcall requests are limited to 2MB, envelope included.
+const MAX_UPLOAD_BYTES: usize = 2_000_000;
+
+/// Content hash of every file, keyed by path.
+pub type FileHashes = BTreeMap<String, String>;
+
+#[derive(Deserialize)]
+pub struct RemoteFile {
+    pub path: String,
+    pub content: String,
+}
+
+#[derive(Deserialize)]
+struct PulledFiles {
+    files: Vec<RemoteFile>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct SyncReport {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+    /// Files changed both in the container and in the project; left untouched.
+    pub conflicts: Vec<String>,
+}
+
+pub fn hash(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+pub fn hashes(tree: &FileTree) -> FileHashes {
+    tree.values()
+        .filter(|f| f.kind == FileKind::File)
+        .map(|f| (f.path.clone(), hash(&f.content)))
+        .collect()
+}
+
+/// Body of the `/start` request: the project, a copy of its files and the
+/// resource profile to start the container with.
+pub fn start_payload(project_id: &str, user: Principal, tree: &FileTree, profile: Option<&SessionProfile>) -> ApiResult<String> {
+    let files: Vec<_> = tree
+        .values()
+        .filter(|f| f.kind == FileKind::File)
+        .map(|f| json!({ "path": f.path, "content": f.content }))
+        .collect();
+    let profile = profile.map(|p| {
+        json!({
+            "name": p.name,
+            "image": p.image,
+            "cpu_millicores": p.cpu_millicores,
+            "memory_mb": p.memory_mb,
+            "disk_mb": p.disk_mb,
+        })
+    });
+    let payload = json!({
+        "project_id": project_id,
+        "user_id": user.to_text(),
+        "files": files,
+        "profile": profile,
+    })
+    .to_string();
+    if payload.len() > MAX_UPLOAD_BYTES {
+        return Err(ApiError::InvalidInput(format!(
+            "Project is too large to send to a session ({} bytes, limit {})",
+            payload.len(),
+            MAX_UPLOAD_BYTES
+        )));
+    }
+    Ok(payload)
+}
+
+pub fn pull_payload(container_id: &str, request_id: &str) -> String {
+    json!({ "container_id": container_id, "request_id": request_id }).to_string()
+}
+
+pub fn parse_pull(body: &[u8]) -> ApiResult<Vec<RemoteFile>> {
+    serde_json::from_slice::<PulledFiles>(body)
+        .map(|pulled| pulled.files)
+        .map_err(|e| ApiError::External(format!("Unexpected controller response: {}", e)))
+}
+
+/// Apply the container's files to `tree`. Returns what changed and the new
+/// sync base; conflicting paths keep their old base so they are reported
+/// again until the two sides agree. On error `tree` is left untouched.
+pub fn merge(tree: &mut FileTree, base: &FileHashes, remote: Vec<RemoteFile>, now: u64) -> ApiResult<(SyncReport, FileHashes)> {
+    let mut report = SyncReport::default();
+    let mut next_base = base.clone();
+    let current = hashes(tree);
+    let mut merged = tree.clone();
+
+    let mut remote_hashes = FileHashes::new();
+    let mut remote_files = BTreeMap::new();
+    for file in remote {
+        let path = vfs::normalize_path(&file.path)
+            .map_err(|_| ApiError::External(format!("Controller returned an invalid path: {}", file.path)))?;
+        remote_hashes.insert(path.clone(), hash(&file.content));
+        remote_files.insert(path, file.content);
+    }
+
+    for (path, content) in remote_files {
+        let theirs = &remote_hashes[&path];
+        let base_hash = base.get(&path);
+        let ours = current.get(&path);
+        if ours == Some(theirs) {
+            next_base.insert(path, theirs.clone());
+            continue;
+        }
+        if base_hash == Some(theirs) {
+            // Only the project changed since the last sync.
+            continue;
+        }
+        if merged.get(&path).is_some_and(|f| f.kind == FileKind::Directory) {
+            report.conflicts.push(path);
+            continue;
+        }
+        match (ours, base_hash) {
+            (None, None) => {
+                vfs::create_file(&mut merged, &path, content, now)?;
+                report.created.push(path.clone());
+            }
+            (Some(ours), Some(base_hash)) if ours == base_hash => {
+                vfs::write_file(&mut merged, &path, content, now)?;
+                report.updated.push(path.clone());
+            }
+            _ => {
+                report.conflicts.push(path);
+                continue;
+            }
+        }
+        next_base.insert(path, theirs.clone());
+    }
+
+    for (path, base_hash) in base {
+        if remote_hashes.contains_key(path) {
+            continue;
+        }
+        match current.get(path) {
+            Some(ours) if ours == base_hash => {
+                vfs::delete_entry(&mut merged, path)?;
+                report.deleted.push(path.clone());
+                next_base.remove(path);
+            }
+            Some(_) => report.conflicts.push(path.clone()),
+            None => {
+                next_base.remove(path);
+            }
+        }
+    }
+    *tree = merged;
+    Ok((report, next_base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(files: &[(&str, &str)]) -> Vec<RemoteFile> {
+        files
+            .iter()
+            .map(|(path, content)| RemoteFile { path: path.to_string(), content: content.to_string() })
+            .collect()
+    }
+
+    #[test]
+    fn merges_container_changes_and_flags_conflicts() {
+        let mut tree = vfs::single_file_tree("rust", "fn a() {}".to_string(), 0);
+        vfs::create_file(&mut tree, "README.md", "readme".to_string(), 0).unwrap();
+        vfs::create_file(&mut tree, "notes.txt", "notes".to_string(), 0).unwrap();
+        vfs::create_file(&mut tree, "old.txt", "old".to_string(), 0).unwrap();
+        let base = hashes(&tree);
+
+        // Edited in the browser while the session ran.
+        vfs::write_file(&mut tree, "README.md", "readme, edited in browser".to_string(), 1).unwrap();
+        vfs::write_file(&mut tree, "notes.txt", "notes, edited in browser".to_string(), 1).unwrap();
+
+        let pulled = remote(&[
+            ("src/lib.rs", "fn a() { edited in container }"),
+            ("README.md", "readme"),
+            ("notes.txt", "notes, edited in container"),
+            ("Cargo.toml", "[package]"),
+        ]);
+        let (report, next_base) = merge(&mut tree, &base, pulled, 2).unwrap();
+
+        assert_eq!(report.updated, vec!["src/lib.rs"]);
+        assert_eq!(report.created, vec!["Cargo.toml"]);
+        assert_eq!(report.deleted, vec!["old.txt"]);
+        assert_eq!(report.conflicts, vec!["notes.txt"]);
+        assert_eq!(tree["src/lib.rs"].content, "fn a() { edited in container }");
+        assert_eq!(tree["README.md"].content, "readme, edited in browser");
+        assert_eq!(tree["notes.txt"].content, "notes, edited in browser");
+        assert!(!tree.contains_key("old.txt"));
+        assert_eq!(next_base["notes.txt"], base["notes.txt"]);
+
+        // Pulling the same files again only reports the unresolved conflict.
+        let again = remote(&[
+            ("src/lib.rs", "fn a() { edited in container }"),
+            ("README.md", "readme"),
+            ("notes.txt", "notes, edited in container"),
+            ("Cargo.toml", "[package]"),
+        ]);
+        let (report, _) = merge(&mut tree, &next_base, again, 3).unwrap();
+        assert_eq!(report, SyncReport { conflicts: vec!["notes.txt".to_string()], ..Default::default() });
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_project() {
+        let mut tree = FileTree::new();
+        assert!(merge(&mut tree, &FileHashes::new(), remote(&[("../etc/passwd", "")]), 0).is_err());
+    }
+
+    #[test]
+    fn failed_merge_leaves_the_tree_untouched() {
+        let mut tree = FileTree::new();
+        vfs::create_file(&mut tree, "notes.txt", "notes".to_string(), 0).unwrap();
+        let before = hashes(&tree);
+        // `a.rs` is created before `notes.txt/x.rs` fails on its parent.
+        let pulled = remote(&[("a.rs", "fn a() {}"), ("notes.txt/x.rs", "")]);
+        assert!(merge(&mut tree, &FileHashes::new(), pulled, 1).is_err());
+        assert_eq!(hashes(&tree), before);
+        assert!(!tree.contains_key("a.rs"));
+    }
+}
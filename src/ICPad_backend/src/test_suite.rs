@@ -0,0 +1,286 @@
+// Declarative test suites for a project's deployed canister.
+//
+// A test case names a method, its arguments as Candid text and what the
+// reply should look like. Cases are stored with the project and run one
+// after another against its canister, each reporting pass or fail, the
+// actual reply and the call's latency.
+
+use crate::candid_call::{self, CallError, Method};
+use candid::types::value::{IDLArgs, IDLValue};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum Expectation {
+    /// The reply equals this Candid value or argument list, e.g. `"Hello, Ada!"`.
+    Equals(String),
+    /// The reply, as Candid text, contains this text.
+    Contains(String),
+    /// The reply is a variant with this tag, e.g. `Ok`.
+    Variant(String),
+    /// The call succeeds, whatever it returns.
+    Succeeds,
+    /// The call is rejected, with a message containing this text if given.
+    Rejects(Option<String>),
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    pub method: String,
+    /// Arguments as `call` takes them: one Candid value each, or a single `(...)` list.
+    pub args: Vec<String>,
+    pub expect: Expectation,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CaseResult {
+    pub name: String,
+    pub passed: bool,
+    /// The reply as Candid text, or the reject message.
+    pub actual: String,
+    /// Why the case failed.
+    pub failure: Option<String>,
+    pub latency_ns: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct SuiteResult {
+    pub cases: Vec<CaseResult>,
+    pub passed: u32,
+    pub failed: u32,
+    pub duration_ns: u64,
+}
+
+/// Check a suite before it is stored.
+pub fn validate(cases: &[TestCase]) -> Result<(), String> {
+    for (i, case) in cases.iter().enumerate() {
+        if case.name.trim().is_empty() {
+            return Err(format!("Test {} has no name", i + 1));
+        }
+        if case.method.trim().is_empty() {
+            return Err(format!("Test '{}' has no method", case.name));
+        }
+        if cases[..i].iter().any(|c| c.name == case.name) {
+            return Err(format!("Duplicate test name: {}", case.name));
+        }
+    }
+    Ok(())
+}
+
+/// Cases whose name contains one of `filters`, every case without filters.
+pub fn select<'a>(cases: &'a [TestCase], filters: &[&str]) -> Vec<&'a TestCase> {
+    cases
+        .iter()
+        .filter(|case| filters.is_empty() || filters.iter().any(|f| case.name.contains(f)))
+        .collect()
+}
+
+/// The expected reply of `Equals`, typed by the method's return types so it
+/// compares equal to a decoded reply.
+fn expected_reply(method: &Method, text: &str) -> Result<IDLArgs, String> {
+    let parsed = if text.trim_start().starts_with('(') {
+        candid_parser::parse_idl_args(text)
+    } else {
+        candid_parser::parse_idl_value(text).map(|value| IDLArgs::new(&[value]))
+    };
+    parsed
+        .map_err(|e| format!("invalid expected value {}: {}", text, e))?
+        .annotate_types(true, &method.env, &method.func.rets)
+        .map_err(|e| format!("expected value {} does not match the return type: {}", text, e))
+}
+
+/// Whether the `outcome` of calling `method` meets `expect`; `Err` says why not.
+pub fn check(expect: &Expectation, method: &Method, outcome: &Result<IDLArgs, CallError>) -> Result<(), String> {
+    match (expect, outcome) {
+        (Expectation::Rejects(text), Err(CallError::Rejected { message, .. })) => match text {
+            Some(text) if !message.contains(text.as_str()) => {
+                Err(format!("expected a reject containing {:?}, got {:?}", text, message))
+            }
+            _ => Ok(()),
+        },
+        (Expectation::Rejects(_), Ok(reply)) => Err(format!("expected a reject, got {}", reply)),
+        (_, Err(e)) => Err(e.to_string()),
+        (Expectation::Succeeds, Ok(_)) => Ok(()),
+        (Expectation::Contains(text), Ok(reply)) => {
+            if reply.to_string().contains(text.as_str()) {
+                Ok(())
+            } else {
+                Err(format!("expected a reply containing {:?}, got {}", text, reply))
+            }
+        }
+        (Expectation::Variant(tag), Ok(reply)) => match reply.args.first() {
+            Some(IDLValue::Variant(variant)) if variant.0.id.to_string() == *tag => Ok(()),
+            _ => Err(format!("expected variant {}, got {}", tag, reply)),
+        },
+        (Expectation::Equals(text), Ok(reply)) => {
+            let expected = expected_reply(method, text)?;
+            if expected == *reply {
+                Ok(())
+            } else {
+                Err(format!("expected {}, got {}", expected, reply))
+            }
+        }
+    }
+}
+
+impl SuiteResult {
+    fn push(&mut self, case: CaseResult) {
+        if case.passed {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+        self.cases.push(case);
+    }
+}
+
+/// Run `cases` one after another against `canister_id`, whose interface is `did`.
+pub async fn run(canister_id: Principal, did: &str, cases: &[&TestCase]) -> SuiteResult {
+    let started = ic_cdk::api::time();
+    let mut suite = SuiteResult::default();
+    for case in cases {
+        let start = ic_cdk::api::time();
+        let (actual, verdict) = match candid_call::resolve_method(did, &case.method) {
+            Ok(method) => {
+                let outcome = candid_call::call_method(canister_id, &method, &case.args).await;
+                let actual = match &outcome {
+                    Ok(reply) => reply.to_string(),
+                    Err(CallError::Rejected { message, .. }) => message.clone(),
+                    Err(e) => e.to_string(),
+                };
+                (actual, check(&case.expect, &method, &outcome))
+            }
+            Err(e) => (String::new(), Err(e.to_string())),
+        };
+        suite.push(CaseResult {
+            name: case.name.clone(),
+            passed: verdict.is_ok(),
+            actual,
+            failure: verdict.err(),
+            latency_ns: ic_cdk::api::time().saturating_sub(start),
+        });
+    }
+    suite.duration_ns = ic_cdk::api::time().saturating_sub(started);
+    suite
+}
+
+fn millis(ns: u64) -> String {
+    format!("{:.1} ms", ns as f64 / 1e6)
+}
+
+/// The suite's results in the style of `cargo test`.
+pub fn report(suite: &SuiteResult) -> String {
+    let mut text = format!("running {} test{}\n", suite.cases.len(), if suite.cases.len() == 1 { "" } else { "s" });
+    for case in &suite.cases {
+        let verdict = if case.passed { "ok" } else { "FAILED" };
+        text.push_str(&format!("test {} ... {} ({})\n", case.name, verdict, millis(case.latency_ns)));
+    }
+    let failures: Vec<_> = suite.cases.iter().filter(|c| !c.passed).collect();
+    if !failures.is_empty() {
+        text.push_str("\nfailures:\n");
+        for case in failures {
+            text.push_str(&format!("---- {} ----\n{}\n", case.name, case.failure.as_deref().unwrap_or_default()));
+        }
+    }
+    text.push_str(&format!(
+        "\ntest result: {}. {} passed; {} failed; finished in {}\n",
+        if suite.failed == 0 { "ok" } else { "FAILED" },
+        suite.passed,
+        suite.failed,
+        millis(suite.duration_ns)
+    ));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DID: &str = r#"
+        type Result = variant { Ok : nat; Err : text };
+        service : {
+            greet : (text) -> (text) query;
+            divide : (nat, nat) -> (Result);
+            profile : () -> (record { name : text; age : nat8 }) query;
+        }
+    "#;
+
+    fn outcome(method: &Method, reply: &str) -> Result<IDLArgs, CallError> {
+        let reply = candid_parser::parse_idl_args(reply).unwrap();
+        let bytes = reply.to_bytes_with_types(&method.env, &method.func.rets).unwrap();
+        candid_call::decode_reply(method, &bytes)
+    }
+
+    fn case(name: &str) -> TestCase {
+        TestCase { name: name.to_string(), method: "greet".to_string(), args: vec![], expect: Expectation::Succeeds }
+    }
+
+    #[test]
+    fn checks_replies_against_expectations() {
+        let greet = candid_call::resolve_method(DID, "greet").unwrap();
+        let hello = outcome(&greet, r#"("Hello, Ada!")"#);
+        assert_eq!(check(&Expectation::Equals(r#""Hello, Ada!""#.to_string()), &greet, &hello), Ok(()));
+        assert_eq!(check(&Expectation::Equals(r#"("Hello, Ada!")"#.to_string()), &greet, &hello), Ok(()));
+        assert_eq!(
+            check(&Expectation::Equals(r#""Hi""#.to_string()), &greet, &hello),
+            Err(r#"expected ("Hi"), got ("Hello, Ada!")"#.to_string())
+        );
+        assert!(check(&Expectation::Equals("42".to_string()), &greet, &hello).unwrap_err().contains("return type"));
+        assert_eq!(check(&Expectation::Contains("Ada".to_string()), &greet, &hello), Ok(()));
+        assert!(check(&Expectation::Rejects(None), &greet, &hello).is_err());
+
+        let divide = candid_call::resolve_method(DID, "divide").unwrap();
+        let quotient = outcome(&divide, "(variant { Ok = 3 : nat })");
+        assert_eq!(check(&Expectation::Variant("Ok".to_string()), &divide, &quotient), Ok(()));
+        assert!(check(&Expectation::Variant("Err".to_string()), &divide, &quotient).is_err());
+        assert_eq!(check(&Expectation::Equals("variant { Ok = 3 }".to_string()), &divide, &quotient), Ok(()));
+
+        // Record fields compare by label, whatever order they are written in.
+        let profile = candid_call::resolve_method(DID, "profile").unwrap();
+        let reply = outcome(&profile, r#"(record { name = "Ada"; age = 36 : nat8 })"#);
+        assert_eq!(check(&Expectation::Equals(r#"record { age = 36; name = "Ada" }"#.to_string()), &profile, &reply), Ok(()));
+
+        let rejected = Err(CallError::Rejected { code: 5, message: "division by zero".to_string() });
+        assert_eq!(check(&Expectation::Rejects(None), &divide, &rejected), Ok(()));
+        assert_eq!(check(&Expectation::Rejects(Some("zero".to_string())), &divide, &rejected), Ok(()));
+        assert!(check(&Expectation::Rejects(Some("overflow".to_string())), &divide, &rejected).is_err());
+        assert!(check(&Expectation::Succeeds, &divide, &rejected).is_err());
+    }
+
+    #[test]
+    fn validates_selects_and_reports() {
+        assert_eq!(validate(&[case("greets"), case("greets_twice")]), Ok(()));
+        assert_eq!(validate(&[case("greets"), case("greets")]), Err("Duplicate test name: greets".to_string()));
+        assert!(validate(&[case(" ")]).is_err());
+
+        let cases = [case("greets"), case("divides"), case("divides_by_zero")];
+        let names = |filters: &[&str]| select(&cases, filters).iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names(&[]).len(), 3);
+        assert_eq!(names(&["divides"]), vec!["divides", "divides_by_zero"]);
+        assert_eq!(names(&["greets", "zero"]), vec!["greets", "divides_by_zero"]);
+
+        let mut suite = SuiteResult { duration_ns: 3_000_000, ..Default::default() };
+        suite.push(CaseResult {
+            name: "greets".to_string(),
+            passed: true,
+            actual: "(\"Hello\")".to_string(),
+            failure: None,
+            latency_ns: 1_250_000,
+        });
+        suite.push(CaseResult {
+            name: "divides".to_string(),
+            passed: false,
+            actual: "(variant { Err = \"no\" })".to_string(),
+            failure: Some("expected variant Ok, got (variant { Err = \"no\" })".to_string()),
+            latency_ns: 1_750_000,
+        });
+        assert_eq!((suite.passed, suite.failed), (1, 1));
+        assert_eq!(
+            report(&suite),
+            "running 2 tests\ntest greets ... ok (1.2 ms)\ntest divides ... FAILED (1.8 ms)\n\n\
+             failures:\n---- divides ----\nexpected variant Ok, got (variant { Err = \"no\" })\n\n\
+             test result: FAILED. 1 passed; 1 failed; finished in 3.0 ms\n"
+        );
+    }
+}
@@ -0,0 +1,128 @@
+// Consensus-safe transform for HTTP outcalls to the Docker controller.
+//
+// Every replica on the subnet performs the outcall and the responses must be
+// byte-identical after transformation. Headers (dates, request ids, proxy
+// cookies) are dropped and the JSON body is re-encoded in a canonical form
+// chosen by the request path, which travels in the transform context.
+
+use crate::controller::{HEALTH_PATH, START_PATH, STOP_PATH};
+use crate::SessionResponse;
+use ic_cdk::management_canister::{HttpRequestResult, TransformArgs, TransformContext};
+use serde_json::Value;
+
+/// Name of the query registered as the outcall transform in `lib.rs`.
+pub const TRANSFORM_METHOD: &str = "transform_controller_response";
+
+/// Top-level fields of session replies that differ between replicas and
+/// carry no meaning for the backend.
+const VOLATILE_FIELDS: &[&str] = &["timestamp", "time", "date", "request_id", "requestId", "trace_id"];
+
+/// The transform for a request to `path`.
+pub fn context(path: &str) -> TransformContext {
+    ic_cdk::management_canister::transform_context_from_query(TRANSFORM_METHOD.to_string(), path.as_bytes().to_vec())
+}
+
+pub fn canonicalize(args: TransformArgs) -> HttpRequestResult {
+    let response = args.response;
+    let path = String::from_utf8_lossy(&args.context);
+    HttpRequestResult {
+        status: response.status,
+        headers: Vec::new(),
+        body: canonical_body(&path, &response.body),
+    }
+}
+
+/// A `/start` reply becomes a canonical `SessionResponse` and session replies
+/// lose their top-level volatile fields. Other JSON, such as file contents,
+/// is only re-encoded with sorted keys. Health replies are never read, and
+/// bodies that are not JSON cannot be compared reliably, so both are dropped.
+fn canonical_body(path: &str, body: &[u8]) -> Vec<u8> {
+    if path == HEALTH_PATH {
+        return Vec::new();
+    }
+    if path == START_PATH {
+        if let Ok(session) = serde_json::from_slice::<SessionResponse>(body) {
+            return serde_json::to_vec(&session).unwrap_or_default();
+        }
+    }
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            if path == START_PATH || path == STOP_PATH {
+                if let Value::Object(map) = &mut value {
+                    map.retain(|key, _| !VOLATILE_FIELDS.contains(&key.as_str()));
+                }
+            }
+            serde_json::to_vec(&value).unwrap_or_default()
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_cdk::management_canister::HttpHeader;
+
+    fn response(path: &str, status: u64, headers: &[(&str, &str)], body: &str) -> TransformArgs {
+        TransformArgs {
+            response: HttpRequestResult {
+                status: status.into(),
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| HttpHeader { name: name.to_string(), value: value.to_string() })
+                    .collect(),
+                body: body.as_bytes().to_vec(),
+            },
+            context: path.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn replica_responses_become_identical() {
+        let a = canonicalize(response(
+            START_PATH,
+            200,
+            &[("Date", "Mon, 01 Jan 2024 00:00:01 GMT"), ("ngrok-trace-id", "abc")],
+            r#"{"editor_url":"https://ide.example/c1","container_id":"c1","started_at":"12:00:01"}"#,
+        ));
+        let b = canonicalize(response(
+            START_PATH,
+            200,
+            &[("Date", "Mon, 01 Jan 2024 00:00:02 GMT")],
+            r#"{ "container_id": "c1", "editor_url": "https://ide.example/c1" }"#,
+        ));
+        assert_eq!(a, b);
+        assert!(a.headers.is_empty());
+        assert_eq!(
+            String::from_utf8(a.body).unwrap(),
+            r#"{"container_id":"c1","editor_url":"https://ide.example/c1"}"#
+        );
+    }
+
+    #[test]
+    fn other_bodies_are_normalized() {
+        let stop_a = canonicalize(response(STOP_PATH, 200, &[], r#"{"status":"stopped","timestamp":1,"request_id":"x"}"#));
+        let stop_b = canonicalize(response(STOP_PATH, 200, &[], r#"{"request_id":"y","timestamp":2,"status":"stopped"}"#));
+        assert_eq!(stop_a, stop_b);
+        assert_eq!(String::from_utf8(stop_a.body).unwrap(), r#"{"status":"stopped"}"#);
+
+        // File contents keep every key, including ones named like volatile fields.
+        let files = canonicalize(response(
+            crate::sync::PULL_PATH,
+            200,
+            &[],
+            r#"{"files":[{"path":"a.json","content":"{}","timestamp":3}],"container_id":"c1","editor_url":"u"}"#,
+        ));
+        assert_eq!(
+            String::from_utf8(files.body).unwrap(),
+            r#"{"container_id":"c1","editor_url":"u","files":[{"content":"{}","path":"a.json","timestamp":3}]}"#
+        );
+
+        let health = canonicalize(response(HEALTH_PATH, 200, &[], r#"{"status":"ok","uptime":12}"#));
+        assert!(health.body.is_empty());
+
+        let html = canonicalize(response(STOP_PATH, 502, &[("Server", "nginx")], "<html>Bad gateway 1a2b</html>"));
+        assert_eq!(html.status, 502u64);
+        assert!(html.body.is_empty());
+    }
+}
@@ -0,0 +1,430 @@
+// Per-project virtual filesystem.
+//
+// A project's files are kept in a flat map keyed by normalized path
+// ("src/lib.rs", no leading slash). Directories are explicit entries so that
+// empty folders survive, and every file's parent directories always exist.
+
+use crate::error::{ApiError, ApiResult};
+use crate::languages;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileKind {
+    File,
+    Directory,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ProjectFile {
+    pub path: String,
+    pub kind: FileKind,
+    pub content: String,
+    pub language: Option<String>,
+    pub mime_type: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+pub type FileTree = BTreeMap<String, ProjectFile>;
+
+/// Most bytes of file content one project may hold.
+pub const MAX_PROJECT_BYTES: u64 = 8 * 1024 * 1024;
+/// Most files and directories one project may hold.
+pub const MAX_ENTRIES: usize = 2_000;
+
+/// Where the code of a single-file project lives.
+pub fn entry_path(language: &str) -> &'static str {
+    languages::backend(language).map_or("src/lib.rs", |backend| backend.entry_path())
+}
+
+/// Guess `(language, mime type)` from a file name.
+pub fn detect_type(path: &str) -> (Option<String>, String) {
+    let extension = path.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("");
+    let (language, mime) = match extension {
+        "rs" => ("rust", "text/x-rust"),
+        "mo" => ("motoko", "text/x-motoko"),
+        "js" | "mjs" => ("javascript", "text/javascript"),
+        "ts" => ("typescript", "text/typescript"),
+        "py" => ("python", "text/x-python"),
+        "did" => ("candid", "text/x-candid"),
+        "toml" => ("toml", "application/toml"),
+        "json" => ("json", "application/json"),
+        "md" => ("markdown", "text/markdown"),
+        "html" => ("html", "text/html"),
+        "css" => ("css", "text/css"),
+        _ => return (None, "text/plain".to_string()),
+    };
+    (Some(language.to_string()), mime.to_string())
+}
+
+/// Normalize a user supplied path. The project root is the empty string.
+pub fn normalize_path(path: &str) -> ApiResult<String> {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.trim().split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return Err(ApiError::InvalidInput(format!("Invalid path '{}': '..' is not allowed", path))),
+            s => segments.push(s),
+        }
+    }
+    Ok(segments.join("/"))
+}
+
+pub fn parent_path(path: &str) -> &str {
+    path.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("")
+}
+
+pub fn file_name(path: &str) -> &str {
+    path.rsplit_once('/').map(|(_, name)| name).unwrap_or(path)
+}
+
+fn is_descendant(path: &str, ancestor: &str) -> bool {
+    ancestor.is_empty() || path.strip_prefix(ancestor).is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn new_entry(path: &str, kind: FileKind, content: String, now: u64) -> ProjectFile {
+    let (language, mime_type) = match kind {
+        FileKind::File => detect_type(path),
+        FileKind::Directory => (None, "inode/directory".to_string()),
+    };
+    ProjectFile {
+        path: path.to_string(),
+        kind,
+        content,
+        language,
+        mime_type,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+/// Reject a change that adds `new_entries` entries and replaces `old_bytes`
+/// of content with `new_bytes` if it would put the project over budget.
+fn check_budget(tree: &FileTree, new_entries: usize, old_bytes: usize, new_bytes: usize) -> ApiResult<()> {
+    if tree.len() + new_entries > MAX_ENTRIES {
+        return Err(ApiError::InvalidInput(format!("Projects are limited to {} files and directories", MAX_ENTRIES)));
+    }
+    let bytes: u64 = tree.values().map(|f| f.content.len() as u64).sum();
+    if bytes - old_bytes as u64 + new_bytes as u64 > MAX_PROJECT_BYTES {
+        return Err(ApiError::InvalidInput(format!("Projects are limited to {} bytes of file content", MAX_PROJECT_BYTES)));
+    }
+    Ok(())
+}
+
+/// Directories above `path` that do not exist yet, plus `path` itself.
+fn new_entries(tree: &FileTree, path: &str) -> usize {
+    let mut count = 1;
+    let mut parent = parent_path(path);
+    while !parent.is_empty() && !tree.contains_key(parent) {
+        count += 1;
+        parent = parent_path(parent);
+    }
+    count
+}
+
+/// Create every missing directory above `path`.
+fn ensure_parents(tree: &mut FileTree, path: &str, now: u64) -> ApiResult<()> {
+    let mut current = String::new();
+    let parent = parent_path(path);
+    if parent.is_empty() {
+        return Ok(());
+    }
+    for segment in parent.split('/') {
+        if !current.is_empty() {
+            current.push('/');
+        }
+        current.push_str(segment);
+        match tree.get(&current) {
+            Some(entry) if entry.kind == FileKind::File => {
+                return Err(ApiError::InvalidInput(format!("Not a directory: {}", current)));
+            }
+            Some(_) => {}
+            None => {
+                tree.insert(current.clone(), new_entry(&current, FileKind::Directory, String::new(), now));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Build the tree of a project that only has one file.
+pub fn single_file_tree(language: &str, code: String, now: u64) -> FileTree {
+    let mut tree = FileTree::new();
+    let path = entry_path(language);
+    // The entry path is a constant so this cannot fail.
+    let _ = create_file(&mut tree, path, code, now);
+    tree
+}
+
+pub fn create_file(tree: &mut FileTree, path: &str, content: String, now: u64) -> ApiResult<()> {
+    let path = normalize_path(path)?;
+    if path.is_empty() {
+        return Err(ApiError::InvalidInput("File path cannot be empty".to_string()));
+    }
+    if tree.contains_key(&path) {
+        return Err(ApiError::Conflict(format!("File already exists: {}", path)));
+    }
+    check_budget(tree, new_entries(tree, &path), 0, content.len())?;
+    ensure_parents(tree, &path, now)?;
+    tree.insert(path.clone(), new_entry(&path, FileKind::File, content, now));
+    Ok(())
+}
+
+pub fn create_directory(tree: &mut FileTree, path: &str, now: u64) -> ApiResult<()> {
+    let path = normalize_path(path)?;
+    if path.is_empty() {
+        return Err(ApiError::InvalidInput("Directory path cannot be empty".to_string()));
+    }
+    if tree.contains_key(&path) {
+        return Err(ApiError::Conflict(format!("File already exists: {}", path)));
+    }
+    check_budget(tree, new_entries(tree, &path), 0, 0)?;
+    ensure_parents(tree, &path, now)?;
+    tree.insert(path.clone(), new_entry(&path, FileKind::Directory, String::new(), now));
+    Ok(())
+}
+
+pub fn read_file<'a>(tree: &'a FileTree, path: &str) -> ApiResult<&'a ProjectFile> {
+    let path = normalize_path(path)?;
+    match tree.get(&path) {
+        Some(entry) if entry.kind == FileKind::Directory => Err(ApiError::InvalidInput(format!("Is a directory: {}", path))),
+        Some(entry) => Ok(entry),
+        None => Err(ApiError::NotFound(format!("File not found: {}", path))),
+    }
+}
+
+pub fn write_file(tree: &mut FileTree, path: &str, content: String, now: u64) -> ApiResult<()> {
+    let path = normalize_path(path)?;
+    if let Some(entry) = tree.get(&path) {
+        check_budget(tree, 0, entry.content.len(), content.len())?;
+    }
+    match tree.get_mut(&path) {
+        Some(entry) if entry.kind == FileKind::Directory => Err(ApiError::InvalidInput(format!("Is a directory: {}", path))),
+        Some(entry) => {
+            entry.content = content;
+            entry.updated_at = now;
+            Ok(())
+        }
+        None => Err(ApiError::NotFound(format!("File not found: {}", path))),
+    }
+}
+
+/// Move a file or a directory with everything below it.
+pub fn move_entry(tree: &mut FileTree, from: &str, to: &str, now: u64) -> ApiResult<()> {
+    let from = normalize_path(from)?;
+    let to = normalize_path(to)?;
+    if from.is_empty() || to.is_empty() {
+        return Err(ApiError::InvalidInput("Cannot move the project root".to_string()));
+    }
+    if !tree.contains_key(&from) {
+        return Err(ApiError::NotFound(format!("File not found: {}", from)));
+    }
+    if tree.contains_key(&to) {
+        return Err(ApiError::Conflict(format!("File already exists: {}", to)));
+    }
+    if is_descendant(&to, &from) {
+        return Err(ApiError::InvalidInput(format!("Cannot move {} into itself", from)));
+    }
+    ensure_parents(tree, &to, now)?;
+
+    let moved: Vec<String> = tree
+        .keys()
+        .filter(|path| **path == from || is_descendant(path, &from))
+        .cloned()
+        .collect();
+    for old_path in moved {
+        if let Some(mut entry) = tree.remove(&old_path) {
+            let new_path = format!("{}{}", to, &old_path[from.len()..]);
+            if entry.kind == FileKind::File {
+                let (language, mime_type) = detect_type(&new_path);
+                entry.language = language;
+                entry.mime_type = mime_type;
+            }
+            entry.path = new_path.clone();
+            entry.updated_at = now;
+            tree.insert(new_path, entry);
+        }
+    }
+    Ok(())
+}
+
+/// Rename an entry without changing its directory.
+pub fn rename_entry(tree: &mut FileTree, path: &str, new_name: &str, now: u64) -> ApiResult<()> {
+    if new_name.is_empty() || new_name.contains('/') || new_name == "." || new_name == ".." {
+        return Err(ApiError::InvalidInput(format!("Invalid file name: {}", new_name)));
+    }
+    let path = normalize_path(path)?;
+    let parent = parent_path(&path);
+    let target = if parent.is_empty() {
+        new_name.to_string()
+    } else {
+        format!("{}/{}", parent, new_name)
+    };
+    move_entry(tree, &path, &target, now)
+}
+
+/// Delete a file, or a directory and everything below it.
+pub fn delete_entry(tree: &mut FileTree, path: &str) -> ApiResult<usize> {
+    let path = normalize_path(path)?;
+    if path.is_empty() {
+        return Err(ApiError::InvalidInput("Cannot delete the project root".to_string()));
+    }
+    if !tree.contains_key(&path) {
+        return Err(ApiError::NotFound(format!("File not found: {}", path)));
+    }
+    let before = tree.len();
+    tree.retain(|p, _| *p != path && !is_descendant(p, &path));
+    Ok(before - tree.len())
+}
+
+/// Direct children of a directory (the root is "").
+pub fn list_directory<'a>(tree: &'a FileTree, dir: &str) -> ApiResult<Vec<&'a ProjectFile>> {
+    let dir = normalize_path(dir)?;
+    if !dir.is_empty() {
+        match tree.get(&dir) {
+            Some(entry) if entry.kind == FileKind::File => return Err(ApiError::InvalidInput(format!("Not a directory: {}", dir))),
+            Some(_) => {}
+            None => return Err(ApiError::NotFound(format!("Directory not found: {}", dir))),
+        }
+    }
+    Ok(tree
+        .values()
+        .filter(|entry| parent_path(&entry.path) == dir)
+        .collect())
+}
+
+/// All files written in `language`, in path order.
+pub fn files_with_language<'a>(tree: &'a FileTree, language: &'a str) -> impl Iterator<Item = &'a ProjectFile> {
+    tree.values()
+        .filter(move |entry| entry.kind == FileKind::File && entry.language.as_deref() == Some(language))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(tree: &FileTree) -> Vec<&str> {
+        tree.keys().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize_path("/src//lib.rs").unwrap(), "src/lib.rs");
+        assert_eq!(normalize_path(" ./src/./main/ ").unwrap(), "src/main");
+        assert_eq!(normalize_path("/").unwrap(), "");
+        assert!(matches!(normalize_path("src/../secret"), Err(ApiError::InvalidInput(_))));
+        assert_eq!(parent_path("src/lib.rs"), "src");
+        assert_eq!(file_name("src/lib.rs"), "lib.rs");
+    }
+
+    #[test]
+    fn creates_files_with_their_parents() {
+        let mut tree = FileTree::new();
+        create_file(&mut tree, "/src//bin/main.rs/", "fn main() {}".to_string(), 1).unwrap();
+        create_directory(&mut tree, "assets/", 1).unwrap();
+        assert_eq!(paths(&tree), vec!["assets", "src", "src/bin", "src/bin/main.rs"]);
+        assert_eq!(tree["src/bin"].kind, FileKind::Directory);
+        assert_eq!(read_file(&tree, "src/bin/main.rs").unwrap().language.as_deref(), Some("rust"));
+
+        assert!(matches!(create_file(&mut tree, "src/bin/main.rs", String::new(), 2), Err(ApiError::Conflict(_))));
+        assert!(matches!(create_directory(&mut tree, "src/", 2), Err(ApiError::Conflict(_))));
+        assert!(matches!(create_file(&mut tree, "src/bin/main.rs/x", String::new(), 2), Err(ApiError::InvalidInput(_))));
+        assert!(matches!(create_file(&mut tree, "//", String::new(), 2), Err(ApiError::InvalidInput(_))));
+        assert!(matches!(read_file(&tree, "src"), Err(ApiError::InvalidInput(_))));
+        assert!(matches!(write_file(&mut tree, "missing.rs", String::new(), 2), Err(ApiError::NotFound(_))));
+
+        write_file(&mut tree, "src/bin/main.rs", "fn main() { run() }".to_string(), 3).unwrap();
+        let file = read_file(&tree, "src/bin/main.rs").unwrap();
+        assert_eq!((file.content.as_str(), file.created_at, file.updated_at), ("fn main() { run() }", 1, 3));
+    }
+
+    #[test]
+    fn moves_and_renames_directories_with_their_contents() {
+        let mut tree = FileTree::new();
+        create_file(&mut tree, "src/lib.rs", String::new(), 1).unwrap();
+        create_file(&mut tree, "src/util/mod.rs", String::new(), 1).unwrap();
+        create_file(&mut tree, "src-old/lib.rs", String::new(), 1).unwrap();
+
+        move_entry(&mut tree, "src/", "crates/core", 2).unwrap();
+        assert_eq!(
+            paths(&tree),
+            vec![
+                "crates",
+                "crates/core",
+                "crates/core/lib.rs",
+                "crates/core/util",
+                "crates/core/util/mod.rs",
+                "src-old",
+                "src-old/lib.rs"
+            ]
+        );
+        assert_eq!(tree["crates/core/util/mod.rs"].path, "crates/core/util/mod.rs");
+        assert_eq!(tree["crates/core/lib.rs"].updated_at, 2);
+
+        rename_entry(&mut tree, "crates/core/lib.rs", "main.js", 3).unwrap();
+        assert_eq!(tree["crates/core/main.js"].language.as_deref(), Some("javascript"));
+        assert!(!tree.contains_key("crates/core/lib.rs"));
+        assert!(matches!(rename_entry(&mut tree, "crates/core/main.js", "../x.js", 3), Err(ApiError::InvalidInput(_))));
+        assert!(matches!(rename_entry(&mut tree, "crates/core/main.js", "..", 3), Err(ApiError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn rejects_bad_moves() {
+        let mut tree = FileTree::new();
+        create_file(&mut tree, "src/lib.rs", String::new(), 1).unwrap();
+        create_file(&mut tree, "README.md", String::new(), 1).unwrap();
+        let before: Vec<String> = tree.keys().cloned().collect();
+
+        assert!(matches!(move_entry(&mut tree, "src", "src/inner", 2), Err(ApiError::InvalidInput(_))));
+        assert!(matches!(move_entry(&mut tree, "src", "src/", 2), Err(ApiError::Conflict(_))));
+        assert!(matches!(move_entry(&mut tree, "README.md", "src/lib.rs", 2), Err(ApiError::Conflict(_))));
+        assert!(matches!(move_entry(&mut tree, "missing", "other", 2), Err(ApiError::NotFound(_))));
+        assert!(matches!(move_entry(&mut tree, "/", "other", 2), Err(ApiError::InvalidInput(_))));
+        assert!(matches!(move_entry(&mut tree, "src/lib.rs", "README.md/lib.rs", 2), Err(ApiError::InvalidInput(_))));
+        assert_eq!(tree.keys().cloned().collect::<Vec<_>>(), before);
+    }
+
+    #[test]
+    fn projects_have_a_budget() {
+        let mut tree = FileTree::new();
+        let half = "x".repeat(MAX_PROJECT_BYTES as usize / 2);
+        create_file(&mut tree, "a.txt", half.clone(), 1).unwrap();
+        create_file(&mut tree, "b.txt", half.clone(), 1).unwrap();
+        assert!(matches!(create_file(&mut tree, "c.txt", "x".to_string(), 1), Err(ApiError::InvalidInput(_))));
+        assert!(matches!(write_file(&mut tree, "a.txt", half.clone() + "x", 2), Err(ApiError::InvalidInput(_))));
+        // Shrinking a file always fits.
+        write_file(&mut tree, "a.txt", String::new(), 2).unwrap();
+
+        let mut tree = FileTree::new();
+        for i in 0..MAX_ENTRIES - 2 {
+            create_file(&mut tree, &format!("f{}", i), String::new(), 1).unwrap();
+        }
+        // The new directory and the file both count.
+        assert!(matches!(create_file(&mut tree, "d/e/f", String::new(), 1), Err(ApiError::InvalidInput(_))));
+        create_file(&mut tree, "d/f", String::new(), 1).unwrap();
+        assert!(matches!(create_directory(&mut tree, "g", 1), Err(ApiError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn deletes_entries_and_lists_directories() {
+        let mut tree = FileTree::new();
+        create_file(&mut tree, "src/lib.rs", String::new(), 1).unwrap();
+        create_file(&mut tree, "src/util/mod.rs", String::new(), 1).unwrap();
+        create_file(&mut tree, "src.bak/lib.rs", String::new(), 1).unwrap();
+
+        let names = |tree: &FileTree, dir: &str| -> Vec<String> {
+            list_directory(tree, dir).unwrap().iter().map(|e| e.path.clone()).collect()
+        };
+        assert_eq!(names(&tree, "/"), vec!["src", "src.bak"]);
+        assert_eq!(names(&tree, "src/"), vec!["src/lib.rs", "src/util"]);
+        assert!(matches!(list_directory(&tree, "src/lib.rs"), Err(ApiError::InvalidInput(_))));
+        assert!(matches!(list_directory(&tree, "missing"), Err(ApiError::NotFound(_))));
+
+        assert_eq!(delete_entry(&mut tree, "src/").unwrap(), 4);
+        assert_eq!(paths(&tree), vec!["src.bak", "src.bak/lib.rs"]);
+        assert!(matches!(delete_entry(&mut tree, "src"), Err(ApiError::NotFound(_))));
+        assert!(matches!(delete_entry(&mut tree, ""), Err(ApiError::InvalidInput(_))));
+    }
+}
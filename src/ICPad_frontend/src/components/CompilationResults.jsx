@@ -1,11 +1,13 @@
 import React, { useState } from 'react';
+import { diagnosticsOf, formatDiagnostic, severityOf } from '../utils/diagnostics';
 
 const CompilationResults = ({ result, onClose }) => {
   const [activeTab, setActiveTab] = useState('output');
 
   if (!result) return null;
 
-  const { success, output, errors, wasm, candid } = result;
+  const { success, output, wasm, candid } = result;
+  const diagnostics = diagnosticsOf(result);
 
   const downloadWasm = () => {
     if (wasm) {
@@ -66,7 +68,7 @@ const CompilationResults = ({ result, onClose }) => {
           >
             Output
           </button>
-          {errors && errors.length > 0 && (
+          {diagnostics.length > 0 && (
             <button
               onClick={() => setActiveTab('errors')}
               className={`px-4 py-2 text-sm font-medium ${
@@ -75,7 +77,7 @@ const CompilationResults = ({ result, onClose }) => {
                   : 'text-gray-400 hover:text-white'
               }`}
             >
-              Errors ({errors.length})
+              Problems ({diagnostics.length})
             </button>
           )}
           {wasm && (
@@ -114,13 +116,23 @@ const CompilationResults = ({ result, onClose }) => {
             </div>
           )}
 
-          {activeTab === 'errors' && errors && errors.length > 0 && (
+          {activeTab === 'errors' && diagnostics.length > 0 && (
             <div className="space-y-2">
-              {errors.map((error, index) => (
-                <div key={index} className="bg-red-900 bg-opacity-50 border border-red-700 rounded p-3">
-                  <div className="text-red-300 font-mono text-sm">{error}</div>
-                </div>
-              ))}
+              {diagnostics.map((diagnostic, index) => {
+                const isError = typeof diagnostic === 'string' || severityOf(diagnostic) === 'Error';
+                return (
+                  <div
+                    key={index}
+                    className={`bg-opacity-50 border rounded p-3 ${
+                      isError ? 'bg-red-900 border-red-700' : 'bg-yellow-900 border-yellow-700'
+                    }`}
+                  >
+                    <div className={`font-mono text-sm ${isError ? 'text-red-300' : 'text-yellow-300'}`}>
+                      {formatDiagnostic(diagnostic)}
+                    </div>
+                  </div>
+                );
+              })}
             </div>
           )}
 
@@ -1,5 +1,6 @@
 import React, { useEffect, useRef, useState } from 'react';
 import { useIDE } from '../contexts/IDEContext';
+import { completeTerminalInput, getTerminalHistory } from '../utils/canisterService';
 
 const Terminal = ({ height = '400px' }) => {
   const terminalRef = useRef(null);
@@ -10,6 +11,17 @@ const Terminal = ({ height = '400px' }) => {
   const [commandHistory, setCommandHistory] = useState([]);
   const [historyIndex, setHistoryIndex] = useState(-1);
 
+  // Restore the command history kept by the backend
+  useEffect(() => {
+    getTerminalHistory(null, 100).then(({ success, result }) => {
+      if (success) {
+        const commands = result.map(entry => entry.command);
+        setCommandHistory(commands);
+        setHistoryIndex(commands.length);
+      }
+    });
+  }, []);
+
   // Initialize terminal output
   useEffect(() => {
     const welcomeMessage = [
@@ -289,8 +301,25 @@ const Terminal = ({ height = '400px' }) => {
     await test(input);
   };
 
+  const completeCommand = async () => {
+    const cursor = inputRef.current ? inputRef.current.selectionStart : command.length;
+    const { success, result } = await completeTerminalInput(command, cursor, currentProject?.id);
+    if (!success || result.candidates.length === 0) return;
+    const chars = [...command];
+    const replace = (value) => chars.slice(0, result.start).join('') + value + chars.slice(result.end).join('');
+    if (result.candidates.length === 1) {
+      const [{ value }] = result.candidates;
+      setCommand(replace(value.endsWith('/') ? value : `${value} `));
+    } else {
+      addOutput(result.candidates.map(c => c.value).join('  '), 'info');
+    }
+  };
+
   const handleKeyPress = (e) => {
-    if (e.key === 'Enter') {
+    if (e.key === 'Tab') {
+      e.preventDefault();
+      completeCommand();
+    } else if (e.key === 'Enter') {
       e.preventDefault();
       handleCommand(command);
       setCommand('');
@@ -366,7 +395,7 @@ const Terminal = ({ height = '400px' }) => {
           type="text"
           value={command}
           onChange={(e) => setCommand(e.target.value)}
-          onKeyPress={handleKeyPress}
+          onKeyDown={handleKeyPress}
           className="flex-1 bg-transparent text-white outline-none font-mono"
           placeholder="Type a command..."
           autoFocus
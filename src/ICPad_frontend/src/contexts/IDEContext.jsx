@@ -11,6 +11,7 @@ import {
   checkCanisterConnection
 } from '../utils/canisterService';
 import { PrincipalContext } from './PrincipalContext';
+import { diagnosticsOf, formatDiagnostic } from '../utils/diagnostics';
 
 const IDEContext = createContext();
 
@@ -259,11 +260,9 @@ export const IDEProvider = ({ children }) => {
         } else {
           addTerminalOutput('❌ Compilation failed!');
           addTerminalOutput(compileResult.output);
-          if (compileResult.errors && compileResult.errors.length > 0) {
-            compileResult.errors.forEach(error => {
-              addTerminalOutput(`  - ${error}`);
-            });
-          }
+          diagnosticsOf(compileResult).forEach(diagnostic => {
+            addTerminalOutput(`  - ${formatDiagnostic(diagnostic)}`);
+          });
         }
       } else {
         addTerminalOutput('❌ Failed to compile: ' + result.error);
@@ -342,14 +341,9 @@ export const IDEProvider = ({ children }) => {
     try {
       const result = await callFunction(currentProject.id, functionName, args);
       if (result.success) {
-        const callResult = result.result;
-        if (callResult.success) {
-          addTerminalOutput(`✅ Function result: ${callResult.result}`);
-        } else {
-          addTerminalOutput(`❌ Function error: ${callResult.error}`);
-        }
+        addTerminalOutput(`✅ Function result: ${result.result}`);
       } else {
-        addTerminalOutput('❌ Failed to call function: ' + result.error);
+        addTerminalOutput(`❌ Function error: ${result.error}`);
       }
     } catch (error) {
       addTerminalOutput('❌ Error calling function: ' + error.message);
@@ -137,6 +137,18 @@ export const updateProjectCode = async (projectId, newCode) => {
   }
 };
 
+// Languages the backend can check, with their ids and entry files
+export const listSupportedLanguages = async () => {
+  try {
+    const actor = getActor();
+    const languages = await actor.list_supported_languages();
+    return { success: true, result: languages };
+  } catch (error) {
+    console.error('List supported languages error:', error);
+    return { success: false, error: error.message };
+  }
+};
+
 // Development operations
 export const compileProject = async (projectId) => {
   try {
@@ -177,16 +189,23 @@ export const deployProjectWithWasm = async (projectId, wasm, candid) => {
   try {
     console.log('Deploying project with WASM:', projectId, 'WASM size:', wasm.length);
     const actor = getActor();
-    const result = await actor.deploy_project_with_wasm(projectId, Array.from(wasm), candid);
+    const result = await actor.deploy_project_with_wasm_v2(projectId, Array.from(wasm), candid, []);
     console.log('Deploy with WASM result:', result);
     
     if ('Ok' in result) {
-      const deployResult = JSON.parse(result.Ok);
-      console.log('Parsed deploy result:', deployResult);
-      return { success: true, result: deployResult };
+      const { canister_id, url, ...rest } = result.Ok;
+      return {
+        success: true,
+        result: {
+          ...rest,
+          canister_id: canister_id[0]?.toText() ?? null,
+          url: url[0] ?? null,
+          wasm_size: wasm.length,
+        },
+      };
     } else {
       console.error('Deploy with WASM failed:', result.Err);
-      return { success: false, error: result.Err };
+      return { success: false, error: apiErrorMessage(result.Err) };
     }
   } catch (error) {
     console.error('Deploy project with WASM error:', error);
@@ -221,16 +240,14 @@ export const callFunction = async (projectId, functionName, args = []) => {
   try {
     console.log('Calling function:', functionName, 'with args:', args);
     const actor = getActor();
-    const result = await actor.call_function(projectId, functionName, args);
+    const result = await actor.call_function_v2(projectId, functionName, args);
     console.log('Call function result:', result);
     
     if ('Ok' in result) {
-      const callResult = JSON.parse(result.Ok);
-      console.log('Parsed call result:', callResult);
-      return { success: true, result: callResult };
+      return { success: true, result: result.Ok };
     } else {
       console.error('Call function failed:', result.Err);
-      return { success: false, error: result.Err };
+      return { success: false, error: apiErrorMessage(result.Err) };
     }
   } catch (error) {
     console.error('Call function error:', error);
@@ -253,6 +270,66 @@ export const testProject = async (projectId, testCode) => {
   }
 };
 
+// Test cases are { name, method, args: [text], expect }, where expect is one of
+// { Equals: text }, { Contains: text }, { Variant: tag }, { Succeeds: null }
+// or { Rejects: [] | [text] }.
+export const getProjectTests = async (projectId) => {
+  try {
+    const actor = getActor();
+    const result = await actor.get_project_tests(projectId);
+    if ('Ok' in result) {
+      return { success: true, result: result.Ok };
+    } else {
+      return { success: false, error: apiErrorMessage(result.Err) };
+    }
+  } catch (error) {
+    console.error('Get project tests error:', error);
+    return { success: false, error: error.message };
+  }
+};
+
+export const setProjectTests = async (projectId, tests) => {
+  try {
+    const actor = getActor();
+    const result = await actor.set_project_tests(projectId, tests);
+    if ('Ok' in result) {
+      return { success: true };
+    } else {
+      return { success: false, error: apiErrorMessage(result.Err) };
+    }
+  } catch (error) {
+    console.error('Set project tests error:', error);
+    return { success: false, error: error.message };
+  }
+};
+
+export const runProjectTests = async (projectId, filter = null) => {
+  try {
+    const actor = getActor();
+    const result = await actor.run_project_tests(projectId, filter ? [filter] : []);
+    if ('Ok' in result) {
+      const { cases, duration_ns, ...summary } = result.Ok;
+      return {
+        success: true,
+        result: {
+          ...summary,
+          durationMs: Number(duration_ns) / 1e6,
+          cases: cases.map(({ failure, latency_ns, ...rest }) => ({
+            ...rest,
+            failure: failure[0] ?? null,
+            latencyMs: Number(latency_ns) / 1e6,
+          })),
+        },
+      };
+    } else {
+      return { success: false, error: apiErrorMessage(result.Err) };
+    }
+  } catch (error) {
+    console.error('Run project tests error:', error);
+    return { success: false, error: error.message };
+  }
+};
+
 // Connection check
 export const checkCanisterConnection = async () => {
   try {
@@ -285,11 +362,96 @@ export const executeTerminalCommand = async (command, workingDir = null) => {
   }
 };
 
+// `ApiError` variants arrive as `{ NotFound: "..." }`.
+const apiErrorMessage = (error) => {
+  const [kind, message] = Object.entries(error)[0] || ['Error', ''];
+  return message ? `${kind}: ${message}` : kind;
+};
+
+// Structured terminal results: stdout, stderr, exit code, duration and changed files
+export const runTerminalCommand = async (command, projectId = null) => {
+  try {
+    const actor = getActor();
+    const result = await actor.execute_terminal_command_v2(command, projectId ? [projectId] : []);
+    if ('Ok' in result) {
+      const { exit_code, duration_ns, ...rest } = result.Ok;
+      return {
+        success: exit_code === 0,
+        result: { ...rest, exitCode: exit_code, durationMs: Number(duration_ns) / 1e6 },
+      };
+    } else {
+      return { success: false, error: apiErrorMessage(result.Err) };
+    }
+  } catch (error) {
+    console.error('Run terminal command error:', error);
+    return { success: false, error: error.message };
+  }
+};
+
+// The caller's command history, oldest first
+export const getTerminalHistory = async (search = null, limit = null) => {
+  try {
+    const actor = getActor();
+    const result = await actor.get_terminal_history(search ? [search] : [], limit ? [limit] : []);
+    if ('Ok' in result) {
+      return { success: true, result: result.Ok };
+    } else {
+      return { success: false, error: apiErrorMessage(result.Err) };
+    }
+  } catch (error) {
+    console.error('Get terminal history error:', error);
+    return { success: false, error: error.message };
+  }
+};
+
+// Completion candidates for the word at `cursor` (in characters)
+export const completeTerminalInput = async (line, cursor, projectId = null) => {
+  try {
+    const actor = getActor();
+    const result = await actor.complete_terminal_input(line, cursor, projectId ? [projectId] : []);
+    if ('Ok' in result) {
+      const { start, end, candidates } = result.Ok;
+      return {
+        success: true,
+        result: {
+          start,
+          end,
+          candidates: candidates.map(({ value, kind, detail }) => ({
+            value,
+            kind: Object.keys(kind)[0],
+            detail: detail[0] ?? null,
+          })),
+        },
+      };
+    } else {
+      return { success: false, error: apiErrorMessage(result.Err) };
+    }
+  } catch (error) {
+    console.error('Complete terminal input error:', error);
+    return { success: false, error: error.message };
+  }
+};
+
+export const clearTerminalHistory = async () => {
+  try {
+    const actor = getActor();
+    const result = await actor.clear_terminal_history();
+    if ('Ok' in result) {
+      return { success: true };
+    } else {
+      return { success: false, error: apiErrorMessage(result.Err) };
+    }
+  } catch (error) {
+    console.error('Clear terminal history error:', error);
+    return { success: false, error: error.message };
+  }
+};
+
 // Docker session management
-export const startDockerSession = async (sessionId) => {
+export const startDockerSession = async (projectId) => {
   try {
     const actor = getActor();
-    const result = await actor.start_docker_session(sessionId);
+    const result = await actor.start_docker_session(projectId);
     if ('Ok' in result) {
       return { success: true, result: result.Ok };
     } else {
@@ -316,6 +478,33 @@ export const stopDockerSession = async (sessionId) => {
   }
 };
 
+export const listSessionProfiles = async () => {
+  try {
+    const actor = getActor();
+    const profiles = await actor.list_session_profiles();
+    return { success: true, result: profiles };
+  } catch (error) {
+    console.error('List session profiles error:', error);
+    return { success: false, error: error.message };
+  }
+};
+
+// Remaining session quota for the Dashboard
+export const getSessionUsage = async () => {
+  try {
+    const actor = getActor();
+    const result = await actor.get_my_session_usage();
+    if ('Ok' in result) {
+      return { success: true, result: result.Ok };
+    } else {
+      return { success: false, error: Object.values(result.Err)[0] };
+    }
+  } catch (error) {
+    console.error('Get session usage error:', error);
+    return { success: false, error: error.message };
+  }
+};
+
 // Motoko compilation
 export const compileMotoko = async (code) => {
   try {
@@ -0,0 +1,24 @@
+// Helpers for the structured diagnostics returned by compile_project.
+//
+// Positions are zero based like the Language Server Protocol; editors and
+// messages show them one based. Severities arrive as Candid variants
+// (`{ Error: null }`) from the typed API and as plain strings from JSON.
+
+export const severityOf = (diagnostic) => {
+  const { severity } = diagnostic;
+  return typeof severity === 'string' ? severity : Object.keys(severity || {})[0] || 'Error';
+};
+
+export const formatDiagnostic = (diagnostic) => {
+  if (typeof diagnostic === 'string') return diagnostic;
+  const { file, range, code, message } = diagnostic;
+  const line = range.start.line + 1;
+  const column = range.start.character + 1;
+  const severity = severityOf(diagnostic).toLowerCase();
+  const codeSuffix = code && code.length ? `[${Array.isArray(code) ? code[0] : code}]` : '';
+  return `${file}:${line}:${column}: ${severity}${codeSuffix}: ${message}`;
+};
+
+// Diagnostics of a compile result; the frontend Motoko compiler still
+// reports plain error strings.
+export const diagnosticsOf = (result) => result.diagnostics || result.errors || [];